@@ -0,0 +1,464 @@
+//! Arithmetic expansion: `$(( ))`, `(( ))` and `let`.
+
+use crate::expansion::is_name_char;
+use crate::variables::Variables;
+
+/// Operators recognized in arithmetic expressions, longest first so that `<<=`
+/// is not read as `<` followed by `<=`.
+const ARITHMETIC_OPERATORS: [&str; 37] = [
+    "<<=", ">>=", "**", "++", "--", "<=", ">=", "==", "!=", "&&", "||", "<<", ">>", "+=", "-=",
+    "*=", "/=", "%=", "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "!", "~", "&", "^", "|",
+    "?", ":", "=", ",",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum ArithmeticToken {
+    Number(i64),
+    Name(String),
+    Operator(&'static str),
+    Open,
+    Close,
+}
+
+/// Parses an integer constant: decimal, hexadecimal with `0x`, octal with a
+/// leading `0`, or `base#digits` for bases 2 to 64.
+fn parse_arithmetic_number(text: &str) -> Option<i64> {
+    let (radix, digits) = if let Some((base, digits)) = text.split_once('#') {
+        (
+            base.parse().ok().filter(|base| (2..=64).contains(base))?,
+            digits,
+        )
+    } else if let Some(digits) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        (16, digits)
+    } else if text.len() > 1 && text.starts_with('0') {
+        (8, &text[1..])
+    } else {
+        (10, text)
+    };
+    if digits.is_empty() {
+        return None;
+    }
+    let mut value: i64 = 0;
+    for ch in digits.chars() {
+        let digit = match ch {
+            '0'..='9' => ch as i64 - '0' as i64,
+            'a'..='z' => ch as i64 - 'a' as i64 + 10,
+            'A'..='Z' if radix <= 36 => ch as i64 - 'A' as i64 + 10,
+            'A'..='Z' => ch as i64 - 'A' as i64 + 36,
+            '@' => 62,
+            '_' => 63,
+            _ => return None,
+        };
+        if digit >= radix {
+            return None;
+        }
+        value = value.wrapping_mul(radix).wrapping_add(digit);
+    }
+    Some(value)
+}
+
+fn lex_arithmetic(expr: &str) -> std::result::Result<Vec<ArithmeticToken>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = expr.trim_start();
+    while let Some(ch) = rest.chars().next() {
+        let len = if ch.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && !matches!(c, '_' | '#' | '@'))
+                .unwrap_or(rest.len());
+            let number = &rest[..len];
+            let value = parse_arithmetic_number(number).ok_or_else(|| {
+                format!("value too great for base (error token is \"{}\")", number)
+            })?;
+            tokens.push(ArithmeticToken::Number(value));
+            len
+        } else if ch.is_ascii_alphabetic() || ch == '_' {
+            let len = rest.find(|c: char| !is_name_char(c)).unwrap_or(rest.len());
+            tokens.push(ArithmeticToken::Name(rest[..len].to_string()));
+            len
+        } else if ch == '(' || ch == ')' {
+            tokens.push(if ch == '(' {
+                ArithmeticToken::Open
+            } else {
+                ArithmeticToken::Close
+            });
+            1
+        } else {
+            let Some(operator) = ARITHMETIC_OPERATORS
+                .iter()
+                .find(|operator| rest.starts_with(**operator))
+            else {
+                return Err(format!(
+                    "syntax error: invalid arithmetic operator (error token is \"{}\")",
+                    rest
+                ));
+            };
+            tokens.push(ArithmeticToken::Operator(operator));
+            operator.len()
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Evaluates an arithmetic expression with C semantics on 64-bit integers.
+/// Variables are referred to by name and may be assigned with `=`, `+=`, `++`
+/// and the like; an unset or empty variable counts as 0 and any other value is
+/// itself evaluated as an expression.
+pub(crate) fn eval_arithmetic(
+    expr: &str,
+    vars: &mut Variables,
+) -> std::result::Result<i64, String> {
+    eval_arithmetic_nested(expr, vars, 0)
+}
+
+fn eval_arithmetic_nested(
+    expr: &str,
+    vars: &mut Variables,
+    depth: usize,
+) -> std::result::Result<i64, String> {
+    if depth > 64 {
+        return Err("expression recursion level exceeded".to_string());
+    }
+    let mut evaluator = Arithmetic {
+        tokens: lex_arithmetic(expr)?,
+        pos: 0,
+        vars,
+        skip: 0,
+        depth,
+    };
+    if evaluator.tokens.is_empty() {
+        return Ok(0);
+    }
+    let value = evaluator.comma()?;
+    match evaluator.tokens.get(evaluator.pos) {
+        Some(_) => Err(evaluator.syntax_error()),
+        None => Ok(value),
+    }
+}
+
+/// A precedence-climbing evaluator over the tokens of an arithmetic
+/// expression. While `skip` is non-zero the operands are parsed but have no
+/// effect, for the side of `&&`, `||` and `?:` that is not taken.
+pub(crate) struct Arithmetic<'a> {
+    tokens: Vec<ArithmeticToken>,
+    pos: usize,
+    vars: &'a mut Variables,
+    skip: usize,
+    depth: usize,
+}
+
+/// Binary operators by precedence level, lowest first.
+const ARITHMETIC_BINARY_LEVELS: [&[&str]; 8] = [
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+impl Arithmetic<'_> {
+    fn peek_operator(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(ArithmeticToken::Operator(operator)) => Some(operator),
+            _ => None,
+        }
+    }
+
+    fn syntax_error(&self) -> String {
+        let rest: Vec<String> = self.tokens[self.pos..]
+            .iter()
+            .map(|token| match token {
+                ArithmeticToken::Number(value) => value.to_string(),
+                ArithmeticToken::Name(name) => name.clone(),
+                ArithmeticToken::Operator(operator) => operator.to_string(),
+                ArithmeticToken::Open => "(".to_string(),
+                ArithmeticToken::Close => ")".to_string(),
+            })
+            .collect();
+        if rest.is_empty() {
+            "syntax error: operand expected".to_string()
+        } else {
+            format!(
+                "syntax error in expression (error token is \"{}\")",
+                rest.join(" ")
+            )
+        }
+    }
+
+    fn expect_operator(&mut self, operator: &str) -> std::result::Result<(), String> {
+        if self.peek_operator() != Some(operator) {
+            return Err(self.syntax_error());
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn variable(&mut self, name: &str) -> std::result::Result<i64, String> {
+        let value = self.vars.get(name).unwrap_or_default().trim().to_string();
+        if value.is_empty() || self.skip > 0 {
+            return Ok(0);
+        }
+        match parse_arithmetic_number(&value) {
+            Some(number) => Ok(number),
+            None => eval_arithmetic_nested(&value, self.vars, self.depth + 1),
+        }
+    }
+
+    fn assign(&mut self, name: &str, value: i64) -> std::result::Result<(), String> {
+        if self.skip == 0 {
+            self.vars
+                .set(name, value.to_string())
+                .map_err(|error| error.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn comma(&mut self) -> std::result::Result<i64, String> {
+        let mut value = self.assignment()?;
+        while self.tokens.get(self.pos) == Some(&ArithmeticToken::Operator(",")) {
+            self.pos += 1;
+            value = self.assignment()?;
+        }
+        Ok(value)
+    }
+
+    fn assignment(&mut self) -> std::result::Result<i64, String> {
+        if let Some(ArithmeticToken::Name(name)) = self.tokens.get(self.pos)
+            && let Some(ArithmeticToken::Operator(operator)) = self.tokens.get(self.pos + 1)
+            && operator.ends_with('=')
+            && !matches!(*operator, "==" | "!=" | "<=" | ">=")
+        {
+            let name = name.clone();
+            let operator = operator.trim_end_matches('=');
+            self.pos += 2;
+            let rhs = self.assignment()?;
+            let value = if operator.is_empty() {
+                rhs
+            } else {
+                let lhs = self.variable(&name)?;
+                self.binary(operator, lhs, rhs)?
+            };
+            self.assign(&name, value)?;
+            return Ok(value);
+        }
+        self.conditional()
+    }
+
+    fn conditional(&mut self) -> std::result::Result<i64, String> {
+        let condition = self.logical_or()?;
+        if self.peek_operator() != Some("?") {
+            return Ok(condition);
+        }
+        self.pos += 1;
+        let then_value = self.skipping(condition == 0, Self::assignment)?;
+        self.expect_operator(":")?;
+        let else_value = self.skipping(condition != 0, Self::conditional)?;
+        Ok(if condition != 0 {
+            then_value
+        } else {
+            else_value
+        })
+    }
+
+    /// Runs `parse`, with its side effects suppressed when `skip` is set.
+    fn skipping(
+        &mut self,
+        skip: bool,
+        parse: fn(&mut Self) -> std::result::Result<i64, String>,
+    ) -> std::result::Result<i64, String> {
+        self.skip += skip as usize;
+        let value = parse(self);
+        self.skip -= skip as usize;
+        value
+    }
+
+    fn logical_or(&mut self) -> std::result::Result<i64, String> {
+        let mut value = self.logical_and()?;
+        while self.peek_operator() == Some("||") {
+            self.pos += 1;
+            let rhs = self.skipping(value != 0, Self::logical_and)?;
+            value = (value != 0 || rhs != 0) as i64;
+        }
+        Ok(value)
+    }
+
+    fn logical_and(&mut self) -> std::result::Result<i64, String> {
+        let mut value = self.binary_level(0)?;
+        while self.peek_operator() == Some("&&") {
+            self.pos += 1;
+            let rhs = self.skipping(value == 0, |this| this.binary_level(0))?;
+            value = (value != 0 && rhs != 0) as i64;
+        }
+        Ok(value)
+    }
+
+    fn binary_level(&mut self, level: usize) -> std::result::Result<i64, String> {
+        let Some(operators) = ARITHMETIC_BINARY_LEVELS.get(level) else {
+            return self.power();
+        };
+        let mut value = self.binary_level(level + 1)?;
+        while let Some(operator) = self
+            .peek_operator()
+            .filter(|operator| operators.contains(operator))
+        {
+            self.pos += 1;
+            let rhs = self.binary_level(level + 1)?;
+            value = self.binary(operator, value, rhs)?;
+        }
+        Ok(value)
+    }
+
+    fn power(&mut self) -> std::result::Result<i64, String> {
+        let base = self.unary()?;
+        if self.peek_operator() != Some("**") {
+            return Ok(base);
+        }
+        self.pos += 1;
+        let exponent = self.power()?;
+        self.binary("**", base, exponent)
+    }
+
+    fn binary(&self, operator: &str, lhs: i64, rhs: i64) -> std::result::Result<i64, String> {
+        if matches!(operator, "/" | "%") && rhs == 0 {
+            if self.skip > 0 {
+                return Ok(0);
+            }
+            return Err("division by 0".to_string());
+        }
+        Ok(match operator {
+            "|" => lhs | rhs,
+            "^" => lhs ^ rhs,
+            "&" => lhs & rhs,
+            "==" => (lhs == rhs) as i64,
+            "!=" => (lhs != rhs) as i64,
+            "<" => (lhs < rhs) as i64,
+            "<=" => (lhs <= rhs) as i64,
+            ">" => (lhs > rhs) as i64,
+            ">=" => (lhs >= rhs) as i64,
+            "<<" => lhs.wrapping_shl(rhs as u32),
+            ">>" => lhs.wrapping_shr(rhs as u32),
+            "+" => lhs.wrapping_add(rhs),
+            "-" => lhs.wrapping_sub(rhs),
+            "*" => lhs.wrapping_mul(rhs),
+            "/" => lhs.wrapping_div(rhs),
+            "%" => lhs.wrapping_rem(rhs),
+            "**" => match u32::try_from(rhs) {
+                Ok(exponent) => lhs.wrapping_pow(exponent),
+                Err(_) if rhs < 0 => return Err("exponent less than 0".to_string()),
+                Err(_) => 0,
+            },
+            _ => unreachable!("{} is not a binary operator", operator),
+        })
+    }
+
+    fn unary(&mut self) -> std::result::Result<i64, String> {
+        match self.peek_operator() {
+            Some(operator @ ("++" | "--")) => {
+                self.pos += 1;
+                let Some(ArithmeticToken::Name(name)) = self.tokens.get(self.pos).cloned() else {
+                    return Err(self.syntax_error());
+                };
+                self.pos += 1;
+                let value = self.variable(&name)?;
+                let value = if operator == "++" {
+                    value.wrapping_add(1)
+                } else {
+                    value.wrapping_sub(1)
+                };
+                self.assign(&name, value)?;
+                Ok(value)
+            }
+            Some(operator @ ("-" | "+" | "!" | "~")) => {
+                self.pos += 1;
+                let value = self.unary()?;
+                Ok(match operator {
+                    "-" => value.wrapping_neg(),
+                    "+" => value,
+                    "!" => (value == 0) as i64,
+                    _ => !value,
+                })
+            }
+            _ => self.postfix(),
+        }
+    }
+
+    fn postfix(&mut self) -> std::result::Result<i64, String> {
+        match self.tokens.get(self.pos).cloned() {
+            Some(ArithmeticToken::Number(value)) => {
+                self.pos += 1;
+                Ok(value)
+            }
+            Some(ArithmeticToken::Name(name)) => {
+                self.pos += 1;
+                let value = self.variable(&name)?;
+                if let Some(operator @ ("++" | "--")) = self.peek_operator() {
+                    self.pos += 1;
+                    let updated = if operator == "++" {
+                        value.wrapping_add(1)
+                    } else {
+                        value.wrapping_sub(1)
+                    };
+                    self.assign(&name, updated)?;
+                }
+                Ok(value)
+            }
+            Some(ArithmeticToken::Open) => {
+                self.pos += 1;
+                let value = self.comma()?;
+                if self.tokens.get(self.pos) != Some(&ArithmeticToken::Close) {
+                    return Err(self.syntax_error());
+                }
+                self.pos += 1;
+                Ok(value)
+            }
+            _ => Err(self.syntax_error()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(expr: &str) -> Result<i64, String> {
+        eval_arithmetic(expr, &mut Variables::default())
+    }
+
+    #[test]
+    fn follows_c_precedence() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("2 ** 3 ** 2"), Ok(512));
+        assert_eq!(eval("-7 % 3"), Ok(-1));
+        assert_eq!(eval("1 < 2 && 2 < 1 || 3 == 3"), Ok(1));
+        assert_eq!(eval("0 ? 1 : 2"), Ok(2));
+        assert_eq!(eval("1 << 4 | 1"), Ok(17));
+    }
+
+    #[test]
+    fn reads_number_bases() {
+        assert_eq!(eval("0x1f + 010 + 2#101"), Ok(31 + 8 + 5));
+        assert!(eval("08").is_err());
+    }
+
+    #[test]
+    fn assigns_and_reads_variables() {
+        let mut vars = Variables::default();
+        assert_eq!(eval_arithmetic("x = 5, x += 2, y = x++", &mut vars), Ok(7));
+        assert_eq!(vars.get("x"), Some("8"));
+        assert_eq!(vars.get("y"), Some("7"));
+        vars.set("e", "x * 2".to_string()).unwrap();
+        assert_eq!(eval_arithmetic("e + unset", &mut vars), Ok(16));
+    }
+
+    #[test]
+    fn rejects_division_by_zero_and_bad_syntax() {
+        assert!(eval("1 / 0").unwrap_err().contains("division by 0"));
+        assert!(eval("1 % 0").is_err());
+        assert!(eval("1 +").is_err());
+        assert!(eval("(1").is_err());
+    }
+}
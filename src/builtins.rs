@@ -0,0 +1,3189 @@
+//! Builtin commands and the options set with `set -o`.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::{self, Write};
+use std::mem;
+use std::os::fd::{AsRawFd, BorrowedFd, FromRawFd, RawFd};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+
+use nix::libc;
+use nix::sys::signal::{SaFlags, SigAction, SigHandler, SigSet, Signal, killpg, sigaction};
+use nix::sys::termios::{self, LocalFlags, SetArg};
+use nix::unistd::{Pid, isatty};
+
+use crate::arithmetic::eval_arithmetic;
+use crate::completion::complete_builtin;
+use crate::envfile::allowenv_builtin;
+use crate::error::{ShellError, describe_io_error};
+use crate::exec::{
+    CommandHash, FdAction, Shell, apply_fd_actions, executables_in_path, find_executable_in_path,
+    forget_cached_commands, is_executable, open_redirections, redirect_shell, restore_shell_fds,
+};
+use crate::frecency::{data_file_path, matching_directories, read_visited, write_visited};
+use crate::interactive::{
+    abbreviate_home, bind_builtin, edit_text, editor_command, history_builtin, history_file_path,
+    write_history_file,
+};
+use crate::jobs::{
+    JOB_CONTROL_SIGNALS, JobState, JobTable, JobWait, SIGNAL_LIMIT, format_job, handle_sigchld,
+    parse_signal_spec, prepare_child, record_trapped_signal, signal_name, wait_in_foreground,
+};
+use crate::parser::{Function, RESERVED_WORDS, SimpleCommand};
+use crate::spelling::Correction;
+use crate::variables::{Variables, is_valid_name, quote_if_needed, single_quote};
+
+/// A stream builtins write to: the shell's own stdout or stderr, or a file (or
+/// pipe) a redirection pointed them at.
+#[derive(Debug)]
+pub(crate) enum Output {
+    Stdout,
+    Stderr,
+    File(File),
+    /// Closed with `>&-`: writes fail.
+    Closed,
+}
+
+impl Output {
+    fn try_clone(&self) -> io::Result<Output> {
+        Ok(match self {
+            Output::Stdout => Output::Stdout,
+            Output::Stderr => Output::Stderr,
+            Output::File(file) => Output::File(file.try_clone()?),
+            Output::Closed => Output::Closed,
+        })
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Stdout => io::stdout().write(buf),
+            Output::Stderr => io::stderr().write(buf),
+            Output::File(file) => file.write(buf),
+            Output::Closed => Err(io::Error::from_raw_os_error(libc::EBADF)),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout => io::stdout().flush(),
+            Output::Stderr => io::stderr().flush(),
+            Output::File(file) => file.flush(),
+            Output::Closed => Ok(()),
+        }
+    }
+}
+
+/// A copy of the shell's descriptor `fd`, for a builtin to use in its place.
+fn copy_fd(fd: RawFd) -> io::Result<File> {
+    match unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 10) } {
+        -1 => Err(io::Error::last_os_error()),
+        copy => Ok(unsafe { File::from_raw_fd(copy) }),
+    }
+}
+
+/// The streams of a builtin, with its redirections applied.
+#[derive(Debug)]
+pub(crate) struct BuiltinIo {
+    /// A file stdin was redirected from, or `None` for the shell's own stdin.
+    stdin: Option<File>,
+    pub(crate) stdout: Output,
+    pub(crate) stderr: Output,
+}
+
+impl BuiltinIo {
+    /// Applies descriptor changes to the shell's stdin, stdout and stderr in
+    /// order. Changes to other descriptors do not affect builtins, but they
+    /// can read and write copies of them.
+    pub(crate) fn new(actions: Vec<FdAction>) -> std::result::Result<Self, ShellError> {
+        let mut io = BuiltinIo {
+            stdin: None,
+            stdout: Output::Stdout,
+            stderr: Output::Stderr,
+        };
+        for action in actions {
+            match action {
+                FdAction::Open(0, file) => io.stdin = Some(file),
+                FdAction::Open(1, file) => io.stdout = Output::File(file),
+                FdAction::Open(2, file) => io.stderr = Output::File(file),
+                FdAction::Duplicate { source, target: 0 } if source != 0 => {
+                    let file = copy_fd(source).map_err(|e| ShellError::Redirect {
+                        target: source.to_string(),
+                        source: e,
+                    })?;
+                    io.stdin = Some(file);
+                }
+                FdAction::Duplicate { source, target } if matches!(target, 1 | 2) => {
+                    let output = match source {
+                        1 => io.stdout.try_clone(),
+                        2 => io.stderr.try_clone(),
+                        _ => copy_fd(source).map(Output::File),
+                    }
+                    .map_err(|e| ShellError::Redirect {
+                        target: source.to_string(),
+                        source: e,
+                    })?;
+                    if target == 1 {
+                        io.stdout = output;
+                    } else {
+                        io.stderr = output;
+                    }
+                }
+                FdAction::Close(1) => io.stdout = Output::Closed,
+                FdAction::Close(2) => io.stderr = Output::Closed,
+                _ => {}
+            }
+        }
+        Ok(io)
+    }
+}
+
+/// Describes how each name would be run: as an alias, a reserved word, a
+/// function, a builtin or a file. `-t` prints only the kind and `-a` lists
+/// every match instead of the first.
+fn type_builtin(
+    aliases: &BTreeMap<String, String>,
+    functions: &BTreeMap<String, Rc<Function>>,
+    vars: &Variables,
+    args: &[&str],
+    io: &mut BuiltinIo,
+) -> io::Result<i32> {
+    let mut all = false;
+    let mut kind_only = false;
+    let mut names = args;
+    while let Some((option, rest)) = names.split_first()
+        && option.len() > 1
+        && let Some(flags) = option.strip_prefix('-')
+    {
+        names = rest;
+        if flags == "-" {
+            break;
+        }
+        for flag in flags.chars() {
+            match flag {
+                'a' => all = true,
+                't' => kind_only = true,
+                _ => {
+                    writeln!(io.stderr, "type: -{}: invalid option", flag)?;
+                    writeln!(io.stderr, "type: usage: type [-at] name [name ...]")?;
+                    return Ok(2);
+                }
+            }
+        }
+    }
+
+    let path_var = vars.get("PATH").unwrap_or_default();
+    let mut status = 0;
+    for &name in names {
+        let mut matches = lookup_command(name, aliases, functions, path_var);
+        if matches.is_empty() {
+            if !kind_only {
+                writeln!(io.stdout, "{name}: not found")?;
+            }
+            status = 1;
+            continue;
+        }
+        if !all {
+            matches.truncate(1);
+        }
+        for found in matches {
+            if kind_only {
+                writeln!(io.stdout, "{}", found.kind())?;
+            } else {
+                writeln!(io.stdout, "{}", found.describe(name))?;
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// Something a command name can refer to.
+enum CommandMatch {
+    Alias(String),
+    Keyword,
+    Function(Rc<Function>),
+    Builtin,
+    File(PathBuf),
+}
+
+impl CommandMatch {
+    fn kind(&self) -> &'static str {
+        match self {
+            CommandMatch::Alias(_) => "alias",
+            CommandMatch::Keyword => "keyword",
+            CommandMatch::Function(_) => "function",
+            CommandMatch::Builtin => "builtin",
+            CommandMatch::File(_) => "file",
+        }
+    }
+
+    fn describe(&self, name: &str) -> String {
+        match self {
+            CommandMatch::Alias(value) => format!("{name} is aliased to `{value}'"),
+            CommandMatch::Keyword => format!("{name} is a shell keyword"),
+            CommandMatch::Function(function) => format!("{name} is a function\n{}", function.text),
+            CommandMatch::Builtin => format!("{name} is a shell builtin"),
+            CommandMatch::File(path) => format!("{name} is {}", path.display()),
+        }
+    }
+}
+
+/// Everything `name` could run, in the order the shell tries them: an alias, a
+/// reserved word, a function, a builtin, then executables in `PATH` (or the file
+/// itself for a path).
+fn lookup_command(
+    name: &str,
+    aliases: &BTreeMap<String, String>,
+    functions: &BTreeMap<String, Rc<Function>>,
+    path_var: &str,
+) -> Vec<CommandMatch> {
+    let mut matches = Vec::new();
+    if let Some(value) = aliases.get(name) {
+        matches.push(CommandMatch::Alias(value.clone()));
+    }
+    if RESERVED_WORDS.contains(&name) {
+        matches.push(CommandMatch::Keyword);
+    }
+    if let Some(function) = functions.get(name) {
+        matches.push(CommandMatch::Function(function.clone()));
+    }
+    if is_builtin(name) {
+        matches.push(CommandMatch::Builtin);
+    }
+    if name.contains('/') {
+        let path = PathBuf::from(name);
+        if is_executable(&path) {
+            matches.push(CommandMatch::File(path));
+        }
+    } else {
+        matches.extend(executables_in_path(name, path_var).map(CommandMatch::File));
+    }
+    matches
+}
+
+/// Drops leading `command` words so that the rest runs as a plain builtin or
+/// program, without alias expansion or functions. `command -v` and `command -V`
+/// are left for the builtin.
+pub(crate) fn strip_command_prefix(command: &mut SimpleCommand) {
+    while command.words.first().is_some_and(|word| word == "command") {
+        match command.words.get(1).map(String::as_str) {
+            Some("--") => {
+                command.words.drain(..2);
+            }
+            Some(word) if !word.starts_with('-') => {
+                command.words.remove(0);
+            }
+            _ => break,
+        }
+        command.skip_functions = true;
+    }
+}
+
+/// `hash` lists the remembered locations of commands or looks names up and
+/// remembers them; `-r` empties the table, `-d` forgets names, `-t` prints
+/// their paths (looking them up if needed), `-p path name` remembers `name` at
+/// `path`, and `-l` lists the table as `hash -p` commands. Builtins are
+/// skipped. Returns 1 if a name was not found.
+fn hash_builtin(
+    hash: &mut CommandHash,
+    vars: &Variables,
+    args: &[&str],
+    io: &mut BuiltinIo,
+) -> io::Result<i32> {
+    let (mut reset, mut delete, mut show, mut reusable, mut pathname) =
+        (false, false, false, false, None);
+    let mut names = args;
+    while let Some((option, rest)) = names.split_first()
+        && option.len() > 1
+        && let Some(flags) = option.strip_prefix('-')
+    {
+        names = rest;
+        if flags == "-" {
+            break;
+        }
+        for flag in flags.chars() {
+            match flag {
+                'r' => reset = true,
+                'd' => delete = true,
+                't' => show = true,
+                'l' => reusable = true,
+                'p' => match names.split_first() {
+                    Some((path, rest)) => {
+                        pathname = Some(PathBuf::from(path));
+                        names = rest;
+                    }
+                    None => {
+                        writeln!(io.stderr, "hash: -p: option requires an argument")?;
+                        return Ok(2);
+                    }
+                },
+                _ => {
+                    writeln!(io.stderr, "hash: -{}: invalid option", flag)?;
+                    writeln!(
+                        io.stderr,
+                        "hash: usage: hash [-lr] [-p pathname] [-dt] [name ...]"
+                    )?;
+                    return Ok(2);
+                }
+            }
+        }
+    }
+    let path_var = vars.get("PATH").unwrap_or_default();
+    hash.check_path(path_var);
+    if reset {
+        forget_cached_commands();
+        hash.entries.clear();
+    }
+    if names.is_empty() {
+        if reset || delete || show {
+            return Ok(0);
+        }
+        if hash.entries.is_empty() {
+            writeln!(io.stderr, "hash: hash table empty")?;
+        } else if reusable {
+            for (name, (path, _)) in &hash.entries {
+                writeln!(
+                    io.stdout,
+                    "hash -p {} {}",
+                    quote_if_needed(&path.display().to_string()),
+                    name
+                )?;
+            }
+        } else {
+            writeln!(io.stdout, "hits\tcommand")?;
+            for (path, hits) in hash.entries.values() {
+                writeln!(io.stdout, "{:4}\t{}", hits, path.display())?;
+            }
+        }
+        return Ok(0);
+    }
+
+    let mut status = 0;
+    for &name in names {
+        if delete {
+            if hash.entries.remove(name).is_none() {
+                writeln!(io.stderr, "hash: {}: not found", name)?;
+                status = 1;
+            }
+            continue;
+        }
+        if let Some(path) = &pathname {
+            hash.entries.insert(name.to_string(), (path.clone(), 0));
+            continue;
+        }
+        if name.contains('/') || is_builtin(name) {
+            continue;
+        }
+        if !hash.entries.contains_key(name) {
+            match find_executable_in_path(name, path_var) {
+                Some(path) => {
+                    hash.entries.insert(name.to_string(), (path, 0));
+                }
+                None => {
+                    writeln!(io.stderr, "hash: {}: not found", name)?;
+                    status = 1;
+                    continue;
+                }
+            }
+        }
+        if show {
+            let (path, _) = &hash.entries[name];
+            if names.len() > 1 {
+                writeln!(io.stdout, "{}\t{}", name, path.display())?;
+            } else {
+                writeln!(io.stdout, "{}", path.display())?;
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// `command -v` prints how each name would be resolved, as an alias definition,
+/// a function or builtin name or a path; `-V` describes it like `type`. Returns
+/// 1 if a name was not found.
+fn command_builtin(
+    aliases: &BTreeMap<String, String>,
+    functions: &BTreeMap<String, Rc<Function>>,
+    vars: &Variables,
+    args: &[&str],
+    io: &mut BuiltinIo,
+) -> io::Result<i32> {
+    let mut verbose = None;
+    let mut names = args;
+    while let Some((option, rest)) = names.split_first()
+        && option.len() > 1
+        && let Some(flags) = option.strip_prefix('-')
+    {
+        names = rest;
+        if flags == "-" {
+            break;
+        }
+        for flag in flags.chars() {
+            match flag {
+                'v' => verbose = Some(false),
+                'V' => verbose = Some(true),
+                'p' => {}
+                _ => {
+                    writeln!(io.stderr, "command: -{}: invalid option", flag)?;
+                    writeln!(
+                        io.stderr,
+                        "command: usage: command [-pVv] command [arg ...]"
+                    )?;
+                    return Ok(2);
+                }
+            }
+        }
+    }
+    let Some(verbose) = verbose else {
+        return Ok(0);
+    };
+
+    let path_var = vars.get("PATH").unwrap_or_default();
+    let mut status = 0;
+    for &name in names {
+        match lookup_command(name, aliases, functions, path_var)
+            .into_iter()
+            .next()
+        {
+            Some(found) if verbose => writeln!(io.stdout, "{}", found.describe(name))?,
+            Some(CommandMatch::Alias(value)) => {
+                writeln!(io.stdout, "alias {}={}", name, single_quote(&value))?
+            }
+            Some(CommandMatch::Keyword | CommandMatch::Function(_) | CommandMatch::Builtin) => {
+                writeln!(io.stdout, "{}", name)?
+            }
+            Some(CommandMatch::File(path)) => writeln!(io.stdout, "{}", path.display())?,
+            None => {
+                if verbose {
+                    writeln!(io.stderr, "command: {}: not found", name)?;
+                }
+                status = 1;
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// Writes the arguments separated by spaces. `-n` suppresses the trailing
+/// newline, `-e` interprets backslash escapes and `-E` turns them off again.
+fn echo_builtin(args: &[&str], io: &mut BuiltinIo) -> io::Result<i32> {
+    let mut newline = true;
+    let mut escapes = false;
+    let mut words = args;
+    while let Some((option, rest)) = words.split_first() {
+        let Some(flags) = option.strip_prefix('-') else {
+            break;
+        };
+        if flags.is_empty() || !flags.chars().all(|c| matches!(c, 'n' | 'e' | 'E')) {
+            break;
+        }
+        for flag in flags.chars() {
+            match flag {
+                'n' => newline = false,
+                'e' => escapes = true,
+                _ => escapes = false,
+            }
+        }
+        words = rest;
+    }
+
+    let mut output = Vec::new();
+    for (i, word) in words.iter().enumerate() {
+        if i > 0 {
+            output.push(b' ');
+        }
+        if !escapes {
+            output.extend_from_slice(word.as_bytes());
+        } else if !push_echo_escapes(&mut output, word) {
+            io.stdout.write_all(&output)?;
+            return Ok(0);
+        }
+    }
+    if newline {
+        output.push(b'\n');
+    }
+    io.stdout.write_all(&output)?;
+    Ok(0)
+}
+
+/// Appends `word` to `output` with `echo -e` escapes interpreted. Returns false
+/// if the word contains `\c`, which ends all output.
+fn push_echo_escapes(output: &mut Vec<u8>, word: &str) -> bool {
+    let mut chars = word.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('a') => output.push(0x07),
+            Some('b') => output.push(0x08),
+            Some('c') => return false,
+            Some('e' | 'E') => output.push(0x1b),
+            Some('f') => output.push(0x0c),
+            Some('n') => output.push(b'\n'),
+            Some('r') => output.push(b'\r'),
+            Some('t') => output.push(b'\t'),
+            Some('v') => output.push(0x0b),
+            Some('\\') => output.push(b'\\'),
+            Some(base @ ('0' | 'x')) => {
+                let (radix, max_digits) = if base == '0' { (8, 3) } else { (16, 2) };
+                let mut value = 0;
+                let mut digits = 0;
+                while digits < max_digits
+                    && let Some(digit) = chars.peek().and_then(|c| c.to_digit(radix))
+                {
+                    value = value * radix + digit;
+                    digits += 1;
+                    chars.next();
+                }
+                if base == 'x' && digits == 0 {
+                    output.extend_from_slice(b"\\x");
+                } else {
+                    output.push(value as u8);
+                }
+            }
+            Some(other) => {
+                output.push(b'\\');
+                output.extend_from_slice(other.encode_utf8(&mut [0; 4]).as_bytes());
+            }
+            None => output.push(b'\\'),
+        }
+    }
+    true
+}
+
+fn alias_builtin(
+    aliases: &mut BTreeMap<String, String>,
+    args: &[&str],
+    io: &mut BuiltinIo,
+) -> io::Result<i32> {
+    let names: Vec<&str> = args.iter().copied().filter(|&arg| arg != "-p").collect();
+    if names.is_empty() {
+        for (name, value) in aliases.iter() {
+            writeln!(io.stdout, "alias {}={}", name, single_quote(value))?;
+        }
+        return Ok(0);
+    }
+
+    let mut status = 0;
+    for arg in names {
+        match arg.split_once('=') {
+            Some((name, value)) => {
+                if name.is_empty()
+                    || name.contains(|c: char| c.is_whitespace() || "/$`'\"\\|&;<>()".contains(c))
+                {
+                    writeln!(io.stderr, "alias: `{}': invalid alias name", name)?;
+                    status = 1;
+                    continue;
+                }
+                aliases.insert(name.to_string(), value.to_string());
+            }
+            None => match aliases.get(arg) {
+                Some(value) => writeln!(io.stdout, "alias {}={}", arg, single_quote(value))?,
+                None => {
+                    writeln!(io.stderr, "alias: {}: not found", arg)?;
+                    status = 1;
+                }
+            },
+        }
+    }
+    Ok(status)
+}
+
+fn unalias_builtin(
+    aliases: &mut BTreeMap<String, String>,
+    args: &[&str],
+    io: &mut BuiltinIo,
+) -> io::Result<i32> {
+    if args.contains(&"-a") {
+        aliases.clear();
+        return Ok(0);
+    }
+    if args.is_empty() {
+        writeln!(io.stderr, "unalias: usage: unalias [-a] name [name ...]")?;
+        return Ok(2);
+    }
+    let mut status = 0;
+    for &name in args {
+        if aliases.remove(name).is_none() {
+            writeln!(io.stderr, "unalias: {}: not found", name)?;
+            status = 1;
+        }
+    }
+    Ok(status)
+}
+
+fn export_builtin(vars: &mut Variables, args: &[&str], io: &mut BuiltinIo) -> io::Result<i32> {
+    let mut exported = true;
+    let mut names = Vec::new();
+    for &arg in args {
+        match arg {
+            "-n" => exported = false,
+            "-p" => {}
+            _ => names.push(arg),
+        }
+    }
+
+    if names.is_empty() {
+        for declaration in vars.declarations("x") {
+            writeln!(io.stdout, "{}", declaration)?;
+        }
+        return Ok(0);
+    }
+
+    let mut status = 0;
+    for arg in names {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg, None),
+        };
+        if !is_valid_name(name) {
+            writeln!(io.stderr, "export: `{}': not a valid identifier", arg)?;
+            status = 1;
+            continue;
+        }
+        if let Some(value) = value
+            && let Err(error) = vars.set(name, value.to_string())
+        {
+            writeln!(io.stderr, "export: {}", error)?;
+            status = 1;
+            continue;
+        }
+        vars.set_exported(name, exported);
+    }
+    Ok(status)
+}
+
+/// Removes variables, or functions with `-f`. Without either flag, a name
+/// that is not a set variable removes the function of that name.
+fn unset_builtin(
+    vars: &mut Variables,
+    functions: &mut BTreeMap<String, Rc<Function>>,
+    args: &[&str],
+    io: &mut BuiltinIo,
+) -> io::Result<i32> {
+    let (mut only_vars, mut only_functions) = (false, false);
+    let mut names = args;
+    while let Some((option, rest)) = names.split_first()
+        && option.len() > 1
+        && let Some(flags) = option.strip_prefix('-')
+    {
+        names = rest;
+        if flags == "-" {
+            break;
+        }
+        for flag in flags.chars() {
+            match flag {
+                'v' => only_vars = true,
+                'f' => only_functions = true,
+                _ => {
+                    writeln!(io.stderr, "unset: -{}: invalid option", flag)?;
+                    writeln!(io.stderr, "unset: usage: unset [-f] [-v] [name ...]")?;
+                    return Ok(2);
+                }
+            }
+        }
+    }
+
+    let mut status = 0;
+    for &name in names {
+        if only_functions && !only_vars {
+            functions.remove(name);
+            continue;
+        }
+        if !is_valid_name(name) {
+            writeln!(io.stderr, "unset: `{}': not a valid identifier", name)?;
+            status = 1;
+            continue;
+        }
+        if vars.is_readonly(name) {
+            writeln!(
+                io.stderr,
+                "unset: {}: cannot unset: readonly variable",
+                name
+            )?;
+            status = 1;
+            continue;
+        }
+        if vars.get(name).is_none() && !only_vars {
+            functions.remove(name);
+        }
+        vars.unset(name);
+    }
+    Ok(status)
+}
+
+/// `declare` and `typeset`, which is `name`: sets variables and their
+/// attributes, -i integer, -r read-only and -x exported, where `+i` and `+x`
+/// take an attribute away. Inside a function the variables are local unless
+/// -g is given. -p prints variables as `declare` commands, and -f and -F print
+/// functions or just their names.
+fn declare_builtin(
+    name: &str,
+    vars: &mut Variables,
+    functions: &BTreeMap<String, Rc<Function>>,
+    args: &[&str],
+    io: &mut BuiltinIo,
+) -> io::Result<i32> {
+    let (mut added, mut removed) = (String::new(), String::new());
+    let (mut print, mut print_functions, mut function_names, mut global) =
+        (false, false, false, false);
+    let mut names = args;
+    while let Some((option, rest)) = names.split_first()
+        && option.len() > 1
+        && (option.starts_with('-') || option.starts_with('+'))
+    {
+        names = rest;
+        if *option == "--" {
+            break;
+        }
+        let (sign, flags) = option.split_at(1);
+        for flag in flags.chars() {
+            match flag {
+                'i' | 'r' | 'x' if sign == "-" => added.push(flag),
+                'i' | 'r' | 'x' => removed.push(flag),
+                'p' => print = true,
+                'f' => print_functions = true,
+                'F' => function_names = true,
+                'g' => global = true,
+                _ => {
+                    writeln!(io.stderr, "{}: {}{}: invalid option", name, sign, flag)?;
+                    writeln!(
+                        io.stderr,
+                        "{}: usage: {} [-fFgiprx] [name[=value] ...]",
+                        name, name
+                    )?;
+                    return Ok(2);
+                }
+            }
+        }
+    }
+
+    let mut status = 0;
+    if print_functions || function_names {
+        let listed: Vec<&str> = match names {
+            [] => functions.keys().map(String::as_str).collect(),
+            names => names.to_vec(),
+        };
+        for function_name in listed {
+            match functions.get(function_name) {
+                Some(_) if function_names => writeln!(io.stdout, "declare -f {}", function_name)?,
+                Some(function) => writeln!(io.stdout, "{}", function.text)?,
+                None => status = 1,
+            }
+        }
+        return Ok(status);
+    }
+    if names.is_empty() {
+        if print || !added.is_empty() {
+            for declaration in vars.declarations(&added) {
+                writeln!(io.stdout, "{}", declaration)?;
+            }
+        } else {
+            let mut listed: Vec<_> = vars.iter().collect();
+            listed.sort();
+            for (var_name, value) in listed {
+                writeln!(io.stdout, "{}={}", var_name, quote_if_needed(value))?;
+            }
+        }
+        return Ok(0);
+    }
+    if print {
+        for &var_name in names {
+            match vars.declaration(var_name) {
+                Some(declaration) => writeln!(io.stdout, "{}", declaration)?,
+                None => {
+                    writeln!(io.stderr, "{}: {}: not found", name, var_name)?;
+                    status = 1;
+                }
+            }
+        }
+        return Ok(status);
+    }
+
+    for &arg in names {
+        let (var_name, value) = match arg.split_once('=') {
+            Some((var_name, value)) => (var_name, Some(value.to_string())),
+            None => (arg, None),
+        };
+        if !is_valid_name(var_name) {
+            writeln!(io.stderr, "{}: `{}': not a valid identifier", name, arg)?;
+            status = 1;
+            continue;
+        }
+        let declared = if removed.contains('r') && vars.is_readonly(var_name) {
+            Err(ShellError::Readonly(var_name.to_string()))
+        } else if global {
+            Ok(false)
+        } else {
+            vars.declare_local(var_name, None)
+        };
+        let assigned = declared.and_then(|_| {
+            if added.contains('i') || removed.contains('i') {
+                vars.set_integer(var_name, added.contains('i'));
+            }
+            match value {
+                Some(value) => vars.set(var_name, value),
+                None => Ok(()),
+            }
+        });
+        if let Err(error) = assigned {
+            writeln!(io.stderr, "{}: {}", name, error)?;
+            status = 1;
+            continue;
+        }
+        if added.contains('x') || removed.contains('x') {
+            vars.set_exported(var_name, added.contains('x'));
+        }
+        if added.contains('r') {
+            vars.set_readonly(var_name);
+        }
+    }
+    Ok(status)
+}
+
+/// Declares variables local to the function being run, each unset unless
+/// given a value. Without names, prints the function's locals.
+fn local_builtin(vars: &mut Variables, args: &[&str], io: &mut BuiltinIo) -> io::Result<i32> {
+    if args.is_empty() {
+        for (name, value) in vars.locals() {
+            writeln!(io.stdout, "{}={}", name, quote_if_needed(value))?;
+        }
+        return Ok(0);
+    }
+    let mut status = 0;
+    for &arg in args {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (arg, None),
+        };
+        if !is_valid_name(name) {
+            writeln!(io.stderr, "local: `{}': not a valid identifier", arg)?;
+            status = 1;
+            continue;
+        }
+        match vars.declare_local(name, value) {
+            Ok(true) => {}
+            Ok(false) => {
+                writeln!(io.stderr, "local: can only be used in a function")?;
+                return Ok(1);
+            }
+            Err(error) => {
+                writeln!(io.stderr, "local: {}", error)?;
+                status = 1;
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// Options changed with `set -o name` or the matching single-letter flag.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ShellOptions {
+    /// `-e`: exit when a command fails outside a condition.
+    pub(crate) errexit: bool,
+    /// `-u`: treat expanding an unset variable as an error.
+    pub(crate) nounset: bool,
+    /// `-x`: print each command to stderr, after `PS4`, before running it.
+    pub(crate) xtrace: bool,
+    /// A pipeline fails with the status of its last failing command.
+    pub(crate) pipefail: bool,
+    /// Tab cycles through completions in place instead of listing them.
+    pub(crate) menucomplete: bool,
+    /// Completions match the letters typed in order rather than as a prefix.
+    pub(crate) fuzzycomplete: bool,
+    /// The working directory and the prompt and command boundaries are reported
+    /// to the terminal with OSC 7 and OSC 133 escape sequences.
+    pub(crate) shellintegration: bool,
+    /// Lines are edited with vi's keys. Turning it on turns `emacs` off.
+    pub(crate) vi: bool,
+    /// Lines are edited with emacs' keys, the default for interactive shells.
+    pub(crate) emacs: bool,
+    /// Text killed in the line editor is copied to the system clipboard, and
+    /// Ctrl-Y pastes from it.
+    pub(crate) clipboard: bool,
+    /// At the prompt, a command that names a directory changes to it.
+    pub(crate) autocd: bool,
+    /// At the prompt, a misspelt command name or `cd` directory is offered a
+    /// correction.
+    pub(crate) correct: bool,
+}
+
+/// The long name of each option and its single-letter flag.
+const SHELL_OPTIONS: [(&str, Option<char>); 12] = [
+    ("errexit", Some('e')),
+    ("nounset", Some('u')),
+    ("xtrace", Some('x')),
+    ("pipefail", None),
+    ("menucomplete", None),
+    ("fuzzycomplete", None),
+    ("shellintegration", None),
+    ("vi", None),
+    ("emacs", None),
+    ("clipboard", None),
+    ("autocd", None),
+    ("correct", None),
+];
+
+impl ShellOptions {
+    pub(crate) fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "errexit" => Some(&mut self.errexit),
+            "nounset" => Some(&mut self.nounset),
+            "xtrace" => Some(&mut self.xtrace),
+            "pipefail" => Some(&mut self.pipefail),
+            "menucomplete" => Some(&mut self.menucomplete),
+            "fuzzycomplete" => Some(&mut self.fuzzycomplete),
+            "shellintegration" => Some(&mut self.shellintegration),
+            "vi" => Some(&mut self.vi),
+            "emacs" => Some(&mut self.emacs),
+            "clipboard" => Some(&mut self.clipboard),
+            "autocd" => Some(&mut self.autocd),
+            "correct" => Some(&mut self.correct),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn get(&self, name: &str) -> bool {
+        match name {
+            "errexit" => self.errexit,
+            "nounset" => self.nounset,
+            "xtrace" => self.xtrace,
+            "pipefail" => self.pipefail,
+            "menucomplete" => self.menucomplete,
+            "fuzzycomplete" => self.fuzzycomplete,
+            "shellintegration" => self.shellintegration,
+            "vi" => self.vi,
+            "emacs" => self.emacs,
+            "clipboard" => self.clipboard,
+            "autocd" => self.autocd,
+            "correct" => self.correct,
+            _ => false,
+        }
+    }
+
+    /// The flags of the options that are on, as shown by `$-`.
+    pub(crate) fn flags(&self) -> String {
+        SHELL_OPTIONS
+            .iter()
+            .filter_map(|&(name, flag)| flag.filter(|_| self.get(name)))
+            .collect()
+    }
+}
+
+/// `set` with no arguments lists the shell variables. Otherwise `-o name` and
+/// `-e`, `-u` and `-x` turn options on, `+o` and `+e` and so on turn them off,
+/// and `-o` or `+o` alone lists them. Any remaining arguments, or all those
+/// after `--`, replace the positional parameters.
+fn set_builtin(
+    vars: &Variables,
+    options: &mut ShellOptions,
+    positional: &mut Vec<String>,
+    args: &[&str],
+    io: &mut BuiltinIo,
+) -> io::Result<i32> {
+    if args.is_empty() {
+        let mut listed: Vec<_> = vars.iter().collect();
+        listed.sort();
+        for (name, value) in listed {
+            writeln!(io.stdout, "{}={}", name, quote_if_needed(value))?;
+        }
+        return Ok(0);
+    }
+
+    let mut args = args.iter().copied().peekable();
+    let mut params = None;
+    while let Some(arg) = args.next() {
+        if arg == "--" || arg == "-" {
+            params = Some(args.by_ref().collect::<Vec<_>>());
+            break;
+        }
+        let enable = match arg.chars().next() {
+            Some('-') if arg.len() > 1 => true,
+            Some('+') if arg.len() > 1 => false,
+            _ => {
+                params = Some(std::iter::once(arg).chain(args.by_ref()).collect());
+                break;
+            }
+        };
+        for flag in arg[1..].chars() {
+            if flag == 'o' {
+                let Some(name) = args.next_if(|name| !name.starts_with(['-', '+'])) else {
+                    for (name, _) in SHELL_OPTIONS {
+                        if enable {
+                            writeln!(
+                                io.stdout,
+                                "{:<15}\t{}",
+                                name,
+                                if options.get(name) { "on" } else { "off" }
+                            )?;
+                        } else {
+                            writeln!(
+                                io.stdout,
+                                "set {}o {}",
+                                if options.get(name) { '-' } else { '+' },
+                                name
+                            )?;
+                        }
+                    }
+                    continue;
+                };
+                let Some(option) = options.get_mut(name) else {
+                    writeln!(io.stderr, "set: {}: invalid option name", name)?;
+                    return Ok(1);
+                };
+                *option = enable;
+                // Only one editing mode can be on.
+                match name {
+                    "vi" if enable => options.emacs = false,
+                    "emacs" if enable => options.vi = false,
+                    _ => {}
+                }
+                continue;
+            }
+            let Some(&(name, _)) = SHELL_OPTIONS
+                .iter()
+                .find(|&&(_, letter)| letter == Some(flag))
+            else {
+                writeln!(io.stderr, "set: {}{}: invalid option", &arg[..1], flag)?;
+                writeln!(
+                    io.stderr,
+                    "set: usage: set [-eux] [-o option] [--] [arg ...]"
+                )?;
+                return Ok(2);
+            };
+            if let Some(option) = options.get_mut(name) {
+                *option = enable;
+            }
+        }
+    }
+    if let Some(params) = params {
+        *positional = params.iter().map(|param| param.to_string()).collect();
+    }
+    Ok(0)
+}
+
+/// Evaluates each argument as an arithmetic expression. Succeeds if the last
+/// one is non-zero.
+fn let_builtin(vars: &mut Variables, args: &[&str], io: &mut BuiltinIo) -> io::Result<i32> {
+    if args.is_empty() {
+        writeln!(io.stderr, "let: expression expected")?;
+        return Ok(1);
+    }
+    let mut value = 0;
+    for expr in args {
+        match eval_arithmetic(expr, vars) {
+            Ok(result) => value = result,
+            Err(message) => {
+                writeln!(io.stderr, "let: {}: {}", expr, message)?;
+                return Ok(1);
+            }
+        }
+    }
+    Ok((value == 0) as i32)
+}
+
+/// Drops the first `n` positional parameters (one by default). Fails without
+/// changing anything if there are fewer than `n`.
+fn shift_builtin(
+    positional: &mut Vec<String>,
+    args: &[&str],
+    io: &mut BuiltinIo,
+) -> io::Result<i32> {
+    let count = match args.first() {
+        None => 1,
+        Some(arg) => match arg.parse::<usize>() {
+            Ok(count) => count,
+            Err(_) => {
+                writeln!(io.stderr, "shift: {}: numeric argument required", arg)?;
+                return Ok(1);
+            }
+        },
+    };
+    if count > positional.len() {
+        return Ok(1);
+    }
+    positional.drain(..count);
+    Ok(0)
+}
+
+/// Lists every signal with its number, five to a row, for `trap -l` and `kill -l`.
+fn write_signal_list(io: &mut BuiltinIo) -> io::Result<()> {
+    let names: Vec<String> = (1..SIGNAL_LIMIT as i32)
+        .filter(|&number| Signal::try_from(number).is_ok())
+        .map(|number| format!("{:2}) {}", number, signal_name(number)))
+        .collect();
+    for row in names.chunks(5) {
+        writeln!(io.stdout, "{}", row.join("\t"))?;
+    }
+    Ok(())
+}
+
+/// Sets, resets, or lists signal traps. `trap action sig...` runs `action` when
+/// a signal arrives, an empty action ignores it, and `-` (or a lone signal)
+/// restores the default. `-l` lists the signal names and `-p` the traps.
+fn trap_builtin(
+    traps: &mut BTreeMap<i32, String>,
+    job_control: bool,
+    args: &[&str],
+    io: &mut BuiltinIo,
+) -> io::Result<i32> {
+    let print_trap = |io: &mut BuiltinIo, number: i32, action: &str| {
+        writeln!(
+            io.stdout,
+            "trap -- {} {}",
+            single_quote(action),
+            signal_name(number)
+        )
+    };
+    let args = match args.first() {
+        Some(&"--") => &args[1..],
+        Some(&"-l") => {
+            write_signal_list(io)?;
+            return Ok(0);
+        }
+        Some(&"-p") | None => {
+            let mut status = 0;
+            if args.len() <= 1 {
+                for (&number, action) in traps.iter() {
+                    print_trap(io, number, action)?;
+                }
+            }
+            for spec in args.iter().skip(1) {
+                match parse_signal_spec(spec) {
+                    Some(number) => {
+                        if let Some(action) = traps.get(&number) {
+                            print_trap(io, number, action)?;
+                        }
+                    }
+                    None => {
+                        writeln!(io.stderr, "trap: {}: invalid signal specification", spec)?;
+                        status = 1;
+                    }
+                }
+            }
+            return Ok(status);
+        }
+        Some(flag) if flag.starts_with('-') && flag.len() > 1 => {
+            writeln!(io.stderr, "trap: {}: invalid option", flag)?;
+            writeln!(
+                io.stderr,
+                "trap: usage: trap [-lp] [[action] signal_spec ...]"
+            )?;
+            return Ok(2);
+        }
+        Some(_) => args,
+    };
+    // A lone signal, or a first word that is a number, resets rather than sets.
+    let (action, specs) = match args {
+        [] => return Ok(0),
+        [_] => (None, args),
+        [first, rest @ ..] if *first == "-" => (None, rest),
+        [first, ..] if first.parse::<u32>().is_ok() => (None, args),
+        [first, rest @ ..] => (Some(*first), rest),
+    };
+
+    let mut status = 0;
+    for spec in specs {
+        let Some(number) = parse_signal_spec(spec) else {
+            writeln!(io.stderr, "trap: {}: invalid signal specification", spec)?;
+            status = 1;
+            continue;
+        };
+        let handler = match (action, Signal::try_from(number)) {
+            (_, Err(_)) => None,
+            (Some(""), Ok(_)) => Some(SigHandler::SigIgn),
+            (Some(_), Ok(_)) => Some(SigHandler::Handler(record_trapped_signal)),
+            (None, Ok(Signal::SIGCHLD)) => Some(SigHandler::Handler(handle_sigchld)),
+            (None, Ok(sig)) if job_control && JOB_CONTROL_SIGNALS.contains(&sig) => {
+                Some(SigHandler::SigIgn)
+            }
+            (None, Ok(_)) => Some(SigHandler::SigDfl),
+        };
+        if let (Some(handler), Ok(sig)) = (handler, Signal::try_from(number)) {
+            let handling = SigAction::new(handler, SaFlags::SA_RESTART, SigSet::empty());
+            if unsafe { sigaction(sig, &handling) }.is_err() {
+                writeln!(io.stderr, "trap: {}: cannot trap signal", spec)?;
+                status = 1;
+                continue;
+            }
+        }
+        match action {
+            Some(action) => traps.insert(number, action.to_string()),
+            None => traps.remove(&number),
+        };
+    }
+    Ok(status)
+}
+
+/// Reads one line for `read`, a byte at a time so that nothing after the
+/// newline is consumed. Each byte is paired with whether a backslash escaped
+/// it; unless `raw`, a backslash-newline joins the next line. Returns `None` at
+/// end of input when nothing was read.
+pub(crate) fn read_line_bytes(fd: RawFd, raw: bool) -> io::Result<Option<Vec<(u8, bool)>>> {
+    let mut line = Vec::new();
+    let mut escaped = false;
+    let mut byte = 0u8;
+    loop {
+        let count = unsafe { libc::read(fd, (&mut byte as *mut u8).cast(), 1) };
+        if count < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(error);
+        }
+        if count == 0 {
+            return Ok((!line.is_empty() || escaped).then_some(line));
+        }
+        match byte {
+            b'\\' if !raw && !escaped => escaped = true,
+            b'\n' if escaped => escaped = false,
+            b'\n' => return Ok(Some(line)),
+            _ => {
+                line.push((byte, escaped));
+                escaped = false;
+            }
+        }
+    }
+}
+
+/// Splits a line read by `read` into at most `count` fields at the characters
+/// of `ifs`. Runs of IFS whitespace count as one separator and are trimmed
+/// from both ends; the last field takes the rest of the line. Escaped bytes
+/// never separate fields.
+fn split_read_fields(line: &[(u8, bool)], ifs: &str, count: usize) -> Vec<String> {
+    let is_separator = |&(byte, escaped): &(u8, bool)| !escaped && ifs.as_bytes().contains(&byte);
+    let is_space = |entry: &(u8, bool)| is_separator(entry) && entry.0.is_ascii_whitespace();
+    let text = |entries: &[(u8, bool)]| {
+        String::from_utf8_lossy(&entries.iter().map(|&(byte, _)| byte).collect::<Vec<u8>>())
+            .into_owned()
+    };
+
+    let mut fields = Vec::new();
+    let mut rest = &line[line.iter().take_while(|entry| is_space(entry)).count()..];
+    while fields.len() + 1 < count && !rest.is_empty() {
+        let end = rest.iter().position(is_separator).unwrap_or(rest.len());
+        fields.push(text(&rest[..end]));
+        rest = &rest[end..];
+        rest = &rest[rest.iter().take_while(|entry| is_space(entry)).count()..];
+        if rest
+            .first()
+            .is_some_and(|entry| is_separator(entry) && !is_space(entry))
+        {
+            rest = &rest[1..];
+            rest = &rest[rest.iter().take_while(|entry| is_space(entry)).count()..];
+        }
+    }
+    let trailing = rest
+        .iter()
+        .rev()
+        .take_while(|entry| is_space(entry))
+        .count();
+    fields.push(text(&rest[..rest.len() - trailing]));
+    fields
+}
+
+/// `read [-rs] [-p prompt] [name ...]` reads a line from stdin and assigns its
+/// fields to the named variables, or the whole line to `REPLY`. Fails at end
+/// of input.
+fn read_builtin(vars: &mut Variables, args: &[&str], io: &mut BuiltinIo) -> io::Result<i32> {
+    let mut raw = false;
+    let mut silent = false;
+    let mut prompt = None;
+    let mut args = args.iter().copied();
+    let mut names = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            names.extend(args.by_ref());
+            break;
+        }
+        let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) else {
+            names.push(arg);
+            names.extend(args.by_ref());
+            break;
+        };
+        for (i, flag) in flags.char_indices() {
+            match flag {
+                'r' => raw = true,
+                's' => silent = true,
+                'p' => {
+                    let attached = &flags[i + 1..];
+                    prompt = if attached.is_empty() {
+                        args.next()
+                    } else {
+                        Some(attached)
+                    };
+                    if prompt.is_none() {
+                        writeln!(io.stderr, "read: -p: option requires an argument")?;
+                        return Ok(2);
+                    }
+                    break;
+                }
+                _ => {
+                    writeln!(io.stderr, "read: -{}: invalid option", flag)?;
+                    writeln!(io.stderr, "read: usage: read [-rs] [-p prompt] [name ...]")?;
+                    return Ok(2);
+                }
+            }
+        }
+    }
+    if let Some(name) = names.iter().find(|name| !is_valid_name(name)) {
+        writeln!(io.stderr, "read: `{}': not a valid identifier", name)?;
+        return Ok(1);
+    }
+
+    let fd = io
+        .stdin
+        .as_ref()
+        .map_or(libc::STDIN_FILENO, |file| file.as_raw_fd());
+    let input = unsafe { BorrowedFd::borrow_raw(fd) };
+    let terminal = isatty(input).unwrap_or(false);
+    if let Some(prompt) = prompt
+        && terminal
+    {
+        write!(io.stderr, "{}", prompt)?;
+        io.stderr.flush()?;
+    }
+    let saved_termios = if silent && terminal {
+        let saved = termios::tcgetattr(input).ok();
+        if let Some(saved) = &saved {
+            let mut quiet = saved.clone();
+            quiet.local_flags.remove(LocalFlags::ECHO);
+            let _ = termios::tcsetattr(input, SetArg::TCSAFLUSH, &quiet);
+        }
+        saved
+    } else {
+        None
+    };
+    let line = read_line_bytes(fd, raw);
+    if let Some(saved) = saved_termios {
+        let _ = termios::tcsetattr(input, SetArg::TCSAFLUSH, &saved);
+    }
+    let line = line?;
+
+    let entries = line.as_deref().unwrap_or_default();
+    let assigned = if names.is_empty() {
+        let bytes: Vec<u8> = entries.iter().map(|&(byte, _)| byte).collect();
+        vars.set("REPLY", String::from_utf8_lossy(&bytes).into_owned())
+    } else {
+        let ifs = vars.get("IFS").unwrap_or(" \t\n").to_string();
+        let mut fields = split_read_fields(entries, &ifs, names.len()).into_iter();
+        names
+            .iter()
+            .try_for_each(|name| vars.set(name, fields.next().unwrap_or_default()))
+    };
+    if let Err(error) = assigned {
+        writeln!(io.stderr, "read: {}", error)?;
+        return Ok(1);
+    }
+    Ok(if line.is_some() { 0 } else { 1 })
+}
+
+fn jobs_builtin(jobs: &mut JobTable, args: &[&str], io: &mut BuiltinIo) -> io::Result<i32> {
+    jobs.reap();
+    let current = jobs.current_id();
+    for job in &jobs.jobs {
+        if args.contains(&"-l") {
+            let pids: Vec<String> = job.pids.iter().map(|pid| pid.to_string()).collect();
+            writeln!(
+                io.stdout,
+                "{} ({})",
+                format_job(job, current),
+                pids.join(" ")
+            )?;
+        } else {
+            writeln!(io.stdout, "{}", format_job(job, current))?;
+        }
+    }
+    jobs.notify_done();
+    Ok(0)
+}
+
+fn fg_builtin(jobs: &mut JobTable, args: &[&str], io: &mut BuiltinIo) -> io::Result<i32> {
+    let index = match jobs.resolve(args.first().copied()) {
+        Ok(index) => index,
+        Err(e) => {
+            writeln!(io.stderr, "fg: {}", e)?;
+            return Ok(1);
+        }
+    };
+    let job = jobs.jobs.remove(index);
+    writeln!(io.stdout, "{}", job.command)?;
+    io.stdout.flush()?;
+
+    let _ = killpg(job.pgid, Signal::SIGCONT);
+    match wait_in_foreground(job.pgid, &job.pids, job.last_pid, job.last_status, false) {
+        JobWait::Finished(status) => Ok(status),
+        JobWait::Stopped {
+            remaining,
+            last_status,
+        } => {
+            jobs.stopped_in_foreground(job, remaining, last_status);
+            Ok(128 + Signal::SIGTSTP as i32)
+        }
+    }
+}
+
+fn bg_builtin(jobs: &mut JobTable, args: &[&str], io: &mut BuiltinIo) -> io::Result<i32> {
+    let index = match jobs.resolve(args.first().copied()) {
+        Ok(index) => index,
+        Err(e) => {
+            writeln!(io.stderr, "bg: {}", e)?;
+            return Ok(1);
+        }
+    };
+    let current = jobs.current_id();
+    let job = &mut jobs.jobs[index];
+    if job.state == JobState::Running {
+        writeln!(io.stderr, "bg: job {} already in background", job.id)?;
+        return Ok(0);
+    }
+    let _ = killpg(job.pgid, Signal::SIGCONT);
+    job.state = JobState::Running;
+    let marker = if Some(job.id) == current { '+' } else { ' ' };
+    writeln!(io.stdout, "[{}]{} {} &", job.id, marker, job.command)?;
+    Ok(0)
+}
+
+/// The process's file creation mask. Reading it means setting it, so it is put
+/// back straight away.
+fn current_umask() -> u32 {
+    unsafe {
+        let mask = libc::umask(0);
+        libc::umask(mask);
+        mask as u32
+    }
+}
+
+/// Applies a symbolic mode such as `u=rwx,g+w,o-rx` to the permissions a
+/// umask allows, which is its complement. Returns `None` if it is malformed.
+fn apply_symbolic_mode(mode: &str, allowed: u32) -> Option<u32> {
+    let mut allowed = allowed;
+    for clause in mode.split(',') {
+        let operator_at = clause.find(['=', '+', '-'])?;
+        let (who, rest) = clause.split_at(operator_at);
+        let mut classes = 0;
+        for c in who.chars() {
+            classes |= match c {
+                'u' => 0o700,
+                'g' => 0o070,
+                'o' => 0o007,
+                'a' => 0o777,
+                _ => return None,
+            };
+        }
+        if classes == 0 {
+            classes = 0o777;
+        }
+        let (operator, permissions) = rest.split_at(1);
+        let mut bits = 0;
+        for c in permissions.chars() {
+            bits |= match c {
+                'r' => 0o444,
+                'w' => 0o222,
+                'x' => 0o111,
+                _ => return None,
+            };
+        }
+        bits &= classes;
+        allowed = match operator {
+            "=" => (allowed & !classes) | bits,
+            "+" => allowed | bits,
+            _ => allowed & !bits,
+        };
+    }
+    Some(allowed)
+}
+
+/// `umask` prints the file creation mask in octal, or with `-S` as the
+/// permissions it allows; `-p` prints it as a `umask` command. With a mode,
+/// octal or symbolic, sets it for the shell and the commands it starts.
+fn umask_builtin(args: &[&str], io: &mut BuiltinIo) -> io::Result<i32> {
+    let (mut symbolic, mut reusable) = (false, false);
+    let mut rest = args;
+    while let Some((option, others)) = rest.split_first()
+        && option.len() > 1
+        && let Some(flags) = option.strip_prefix('-')
+        && flags.chars().all(|c| matches!(c, 'S' | 'p'))
+    {
+        rest = others;
+        symbolic |= flags.contains('S');
+        reusable |= flags.contains('p');
+    }
+    let mask = current_umask();
+    let Some(&mode) = rest.first() else {
+        let prefix = if reusable { "umask " } else { "" };
+        if symbolic {
+            let allowed = !mask & 0o777;
+            let classes: Vec<String> = [('u', 6), ('g', 3), ('o', 0)]
+                .iter()
+                .map(|&(class, shift)| {
+                    let bits = allowed >> shift;
+                    let permissions: String = [(4, 'r'), (2, 'w'), (1, 'x')]
+                        .iter()
+                        .filter(|&&(bit, _)| bits & bit != 0)
+                        .map(|&(_, c)| c)
+                        .collect();
+                    format!("{}={}", class, permissions)
+                })
+                .collect();
+            let flag = if reusable { "-S " } else { "" };
+            writeln!(io.stdout, "{}{}{}", prefix, flag, classes.join(","))?;
+        } else {
+            writeln!(io.stdout, "{}{:04o}", prefix, mask)?;
+        }
+        return Ok(0);
+    };
+    let new_mask = if mode.starts_with(|c: char| c.is_ascii_digit()) {
+        u32::from_str_radix(mode, 8)
+            .ok()
+            .filter(|&mask| mask <= 0o777)
+    } else {
+        apply_symbolic_mode(mode, !mask & 0o777).map(|allowed| !allowed & 0o777)
+    };
+    let Some(new_mask) = new_mask else {
+        let problem = if mode.starts_with(|c: char| c.is_ascii_digit()) {
+            "octal number out of range"
+        } else {
+            "invalid symbolic mode operator"
+        };
+        writeln!(io.stderr, "umask: {}: {}", mode, problem)?;
+        return Ok(1);
+    };
+    unsafe {
+        libc::umask(new_mask as libc::mode_t);
+    }
+    Ok(0)
+}
+
+/// Removes jobs from the table so that the shell neither reports nor hangs them
+/// up, or with `-h` only keeps them from being sent SIGHUP when the shell
+/// exits. Acts on the current job, the given ones, or with `-a` every job
+/// (`-r` only the running ones).
+fn disown_builtin(jobs: &mut JobTable, args: &[&str], io: &mut BuiltinIo) -> io::Result<i32> {
+    let (mut keep, mut all, mut running) = (false, false, false);
+    let mut specs = args;
+    while let Some((option, rest)) = specs.split_first()
+        && option.len() > 1
+        && let Some(flags) = option.strip_prefix('-')
+    {
+        specs = rest;
+        if flags == "-" {
+            break;
+        }
+        for flag in flags.chars() {
+            match flag {
+                'h' => keep = true,
+                'a' => all = true,
+                'r' => running = true,
+                _ => {
+                    writeln!(io.stderr, "disown: -{}: invalid option", flag)?;
+                    writeln!(io.stderr, "disown: usage: disown [-h] [-ar] [jobspec ...]")?;
+                    return Ok(2);
+                }
+            }
+        }
+    }
+    jobs.reap();
+    let mut ids = Vec::new();
+    let mut status = 0;
+    if all || (running && specs.is_empty()) {
+        ids.extend(
+            jobs.jobs
+                .iter()
+                .filter(|job| !running || job.state == JobState::Running)
+                .map(|job| job.id),
+        );
+    } else if specs.is_empty() {
+        match jobs.resolve(None) {
+            Ok(index) => ids.push(jobs.jobs[index].id),
+            Err(e) => {
+                writeln!(io.stderr, "disown: {}", e)?;
+                status = 1;
+            }
+        }
+    } else {
+        for &spec in specs {
+            match jobs.resolve(Some(spec)) {
+                Ok(index) => ids.push(jobs.jobs[index].id),
+                Err(e) => {
+                    writeln!(io.stderr, "disown: {}", e)?;
+                    status = 1;
+                }
+            }
+        }
+    }
+    if keep {
+        for job in jobs.jobs.iter_mut().filter(|job| ids.contains(&job.id)) {
+            job.no_hangup = true;
+        }
+    } else {
+        jobs.jobs.retain(|job| !ids.contains(&job.id));
+    }
+    Ok(status)
+}
+
+/// Sends a signal, SIGTERM by default, to processes and jobs. The signal is given
+/// as `-NAME`, `-NUMBER`, `-s NAME` or `-n NUMBER`; a job spec signals the job's
+/// whole process group, continuing it if it is stopped. `-l` lists signal names,
+/// or translates the given numbers (or exit statuses) and names.
+fn kill_builtin(jobs: &mut JobTable, args: &[&str], io: &mut BuiltinIo) -> io::Result<i32> {
+    let usage = "kill: usage: kill [-s sigspec | -n signum | -sigspec] pid | jobspec ... \
+                 or kill -l [sigspec]";
+    let mut number = Signal::SIGTERM as i32;
+    let mut targets = args;
+    match args.first().copied() {
+        None => {
+            writeln!(io.stderr, "{}", usage)?;
+            return Ok(2);
+        }
+        Some("-l" | "-L") => {
+            if args.len() == 1 {
+                write_signal_list(io)?;
+                return Ok(0);
+            }
+            let mut status = 0;
+            for spec in &args[1..] {
+                let translated = match spec.parse::<i32>() {
+                    Ok(code) => Signal::try_from(if code > 128 { code - 128 } else { code })
+                        .ok()
+                        .map(|signal| signal.as_str().trim_start_matches("SIG").to_string()),
+                    Err(_) => parse_signal_spec(spec)
+                        .filter(|&number| number != 0)
+                        .map(|number| number.to_string()),
+                };
+                match translated {
+                    Some(translated) => writeln!(io.stdout, "{}", translated)?,
+                    None => {
+                        writeln!(io.stderr, "kill: {}: invalid signal specification", spec)?;
+                        status = 1;
+                    }
+                }
+            }
+            return Ok(status);
+        }
+        Some(option @ ("-s" | "-n")) => {
+            let Some(spec) = args.get(1) else {
+                writeln!(io.stderr, "kill: {}: option requires an argument", option)?;
+                writeln!(io.stderr, "{}", usage)?;
+                return Ok(2);
+            };
+            let Some(parsed) = parse_signal_spec(spec) else {
+                writeln!(io.stderr, "kill: {}: invalid signal specification", spec)?;
+                return Ok(1);
+            };
+            number = parsed;
+            targets = &args[2..];
+        }
+        Some("--") => targets = &args[1..],
+        Some(option) if option.len() > 1 && option.starts_with('-') => {
+            let Some(parsed) = parse_signal_spec(&option[1..]) else {
+                writeln!(
+                    io.stderr,
+                    "kill: {}: invalid signal specification",
+                    &option[1..]
+                )?;
+                return Ok(1);
+            };
+            number = parsed;
+            targets = &args[1..];
+        }
+        Some(_) => {}
+    }
+    if targets.is_empty() {
+        writeln!(io.stderr, "{}", usage)?;
+        return Ok(2);
+    }
+
+    let signal = Signal::try_from(number).ok();
+    let mut status = 0;
+    for &target in targets {
+        let result = if target.starts_with('%') {
+            match jobs.resolve(Some(target)) {
+                Ok(index) => {
+                    let job = &jobs.jobs[index];
+                    let result = killpg(job.pgid, signal);
+                    if result.is_ok()
+                        && job.state == JobState::Stopped
+                        && signal != Some(Signal::SIGCONT)
+                    {
+                        let _ = killpg(job.pgid, Signal::SIGCONT);
+                    }
+                    result
+                }
+                Err(e) => {
+                    writeln!(io.stderr, "kill: {}", e)?;
+                    status = 1;
+                    continue;
+                }
+            }
+        } else {
+            match target.parse::<i32>() {
+                Ok(pid) => nix::sys::signal::kill(Pid::from_raw(pid), signal),
+                Err(_) => {
+                    writeln!(
+                        io.stderr,
+                        "kill: {}: arguments must be process or job IDs",
+                        target
+                    )?;
+                    status = 1;
+                    continue;
+                }
+            }
+        };
+        if let Err(errno) = result {
+            writeln!(io.stderr, "kill: ({}) - {}", target, errno.desc())?;
+            status = 1;
+        }
+    }
+    Ok(status)
+}
+
+/// Waits for background jobs and returns the status of the last one waited for.
+/// Without arguments it waits for every running job and succeeds; otherwise for
+/// the given pids and job specs. `-n` waits for whichever job finishes next.
+/// Fails with 127 for something that is not a job of this shell.
+fn wait_builtin(jobs: &mut JobTable, args: &[&str], io: &mut BuiltinIo) -> io::Result<i32> {
+    match args.first().copied() {
+        None => {
+            while let Some(index) = jobs
+                .jobs
+                .iter()
+                .position(|job| job.state == JobState::Running)
+            {
+                jobs.wait_for_job(index);
+            }
+            jobs.forget_done();
+            return Ok(0);
+        }
+        Some("-n") => return Ok(jobs.wait_for_next().unwrap_or(127)),
+        Some(option)
+            if option.len() > 1 && option.starts_with('-') && option.parse::<i32>().is_err() =>
+        {
+            writeln!(io.stderr, "wait: {}: invalid option", option)?;
+            writeln!(io.stderr, "wait: usage: wait [-n] [id ...]")?;
+            return Ok(2);
+        }
+        Some(_) => {}
+    }
+
+    let mut status = 0;
+    for &target in args {
+        if target.starts_with('%') {
+            status = match jobs.resolve(Some(target)) {
+                Ok(index) => jobs.wait_for_job(index),
+                Err(e) => {
+                    writeln!(io.stderr, "wait: {}", e)?;
+                    127
+                }
+            };
+            continue;
+        }
+        let Ok(pid) = target.parse::<i32>().map(Pid::from_raw) else {
+            writeln!(io.stderr, "wait: `{}': not a pid or valid job spec", target)?;
+            status = 1;
+            continue;
+        };
+        if let Some(index) = jobs.jobs.iter().position(|job| job.pids.contains(&pid)) {
+            status = jobs.wait_for_pid(pid).unwrap_or(127);
+            if matches!(jobs.jobs[index].state, JobState::Done(_)) {
+                jobs.take(index);
+            }
+        } else if let Some(index) = jobs
+            .jobs
+            .iter()
+            .position(|job| job.last_pid == pid && job.pids.is_empty())
+        {
+            status = jobs.take(index);
+        } else if let Some(index) = jobs
+            .finished
+            .iter()
+            .rposition(|&(finished, _)| finished == pid)
+        {
+            status = jobs.finished.remove(index).1;
+        } else {
+            writeln!(io.stderr, "wait: pid {} is not a child of this shell", pid)?;
+            status = 127;
+        }
+    }
+    Ok(status)
+}
+
+/// Changes the working directory and updates `$PWD` and `$OLDPWD`. Without an
+/// argument it goes to `$HOME`; `cd -` returns to `$OLDPWD` and prints it. A
+/// relative target not found in the working directory is looked up in
+/// `$CDPATH`, and the directory found is printed. `-P` resolves symlinks in
+/// the new `$PWD`; `-L`, the default, keeps them.
+fn cd_builtin(vars: &mut Variables, args: &[&str], io: &mut BuiltinIo) -> io::Result<i32> {
+    let mut physical = false;
+    let mut args = args;
+    while let Some((&option, rest)) = args.split_first() {
+        match option {
+            "-L" => physical = false,
+            "-P" => physical = true,
+            "--" => {
+                args = rest;
+                break;
+            }
+            _ if option.len() > 1 && option.starts_with('-') => {
+                writeln!(io.stderr, "cd: {}: invalid option", option)?;
+                writeln!(io.stderr, "cd: usage: cd [-L|-P] [dir]")?;
+                return Ok(2);
+            }
+            _ => break,
+        }
+        args = rest;
+    }
+    let home;
+    let target = match args {
+        [] => match vars.get("HOME") {
+            Some(dir) => {
+                home = dir.to_string();
+                home.as_str()
+            }
+            None => {
+                writeln!(io.stderr, "cd: HOME not set")?;
+                return Ok(1);
+            }
+        },
+        [target] => *target,
+        _ => {
+            writeln!(io.stderr, "cd: too many arguments")?;
+            return Ok(1);
+        }
+    };
+    let path = if target == "-" {
+        match vars.get("OLDPWD") {
+            Some(old) => PathBuf::from(old),
+            None => {
+                writeln!(io.stderr, "cd: OLDPWD not set")?;
+                return Ok(1);
+            }
+        }
+    } else if let Some(found) = search_cdpath(vars, target) {
+        found
+    } else {
+        PathBuf::from(target)
+    };
+
+    let status = change_directory(vars, "cd", &path, physical, io)?;
+    if status == 0 && (target == "-" || path.as_os_str() != target) {
+        writeln!(io.stdout, "{}", vars.get("PWD").unwrap_or_default())?;
+    }
+    Ok(status)
+}
+
+/// Looks for a relative `cd` target in the directories of `$CDPATH`. Only a
+/// match under a non-empty entry is returned, since an empty entry stands for
+/// the working directory, which `cd` tries anyway.
+pub(crate) fn search_cdpath(vars: &Variables, target: &str) -> Option<PathBuf> {
+    let first = target.split('/').next().unwrap_or_default();
+    if target.starts_with('/') || first == "." || first == ".." {
+        return None;
+    }
+    let cdpath = vars.get("CDPATH")?;
+    for dir in cdpath.split(':') {
+        if dir.is_empty() || dir == "." {
+            if Path::new(target).is_dir() {
+                return None;
+            }
+            continue;
+        }
+        let candidate = Path::new(dir).join(target);
+        if candidate.is_dir() {
+            return Some(candidate);
+        }
+    }
+    None
+}
+
+/// Changes to the visited directory with the highest frecency whose path
+/// contains the patterns in order, preferring one other than the working
+/// directory. With `-l` or no patterns, the matches are listed lowest first
+/// instead, and `-x` removes the working directory from the data file.
+fn z_builtin(
+    vars: &mut Variables,
+    name: &str,
+    args: &[&str],
+    io: &mut BuiltinIo,
+) -> io::Result<i32> {
+    let (mut list, mut remove) = (false, false);
+    let mut args = args;
+    while let Some((&option, rest)) = args.split_first() {
+        match option {
+            "-l" => list = true,
+            "-x" => remove = true,
+            "--" => {
+                args = rest;
+                break;
+            }
+            _ if option.len() > 1 && option.starts_with('-') => {
+                writeln!(io.stderr, "{}: {}: invalid option", name, option)?;
+                writeln!(io.stderr, "{}: usage: {} [-lx] [pattern ...]", name, name)?;
+                return Ok(2);
+            }
+            _ => break,
+        }
+        args = rest;
+    }
+    let Some(path) = data_file_path() else {
+        writeln!(io.stderr, "{}: HOME not set", name)?;
+        return Ok(1);
+    };
+    let mut visited = match read_visited(&path) {
+        Ok(visited) => visited,
+        Err(e) => {
+            writeln!(
+                io.stderr,
+                "{}: {}: {}",
+                name,
+                path.display(),
+                describe_io_error(&e)
+            )?;
+            return Ok(1);
+        }
+    };
+    let cwd = working_directory(vars);
+    if remove {
+        visited.retain(|directory| directory.path != cwd);
+        if let Err(e) = write_visited(&path, &visited) {
+            writeln!(
+                io.stderr,
+                "{}: {}: {}",
+                name,
+                path.display(),
+                describe_io_error(&e)
+            )?;
+            return Ok(1);
+        }
+        return Ok(0);
+    }
+    let matches = matching_directories(&visited, args);
+    if list || args.is_empty() {
+        for (frecency, directory) in matches.iter().rev() {
+            writeln!(
+                io.stdout,
+                "{:<10} {}",
+                format!("{:.1}", frecency),
+                directory
+            )?;
+        }
+        return Ok(if matches.is_empty() { 1 } else { 0 });
+    }
+    let Some((_, target)) = matches
+        .iter()
+        .find(|(_, directory)| *directory != cwd)
+        .or(matches.first())
+    else {
+        writeln!(
+            io.stderr,
+            "{}: {}: no matching directory",
+            name,
+            args.join(" ")
+        )?;
+        return Ok(1);
+    };
+    change_directory(vars, name, Path::new(target), false, io)
+}
+
+/// Changes the working directory to `path` and updates `$PWD` and `$OLDPWD`.
+/// Unless `physical`, `path` is taken relative to the logical `$PWD`, so `..`
+/// goes back through a symlink rather than to the parent of its target, and
+/// `$PWD` keeps the symlinks; otherwise `$PWD` is the resolved directory.
+/// Errors are reported on behalf of the builtin `name`.
+fn change_directory(
+    vars: &mut Variables,
+    name: &str,
+    path: &Path,
+    physical: bool,
+    io: &mut BuiltinIo,
+) -> io::Result<i32> {
+    let previous = working_directory(vars);
+    let logical = (!physical)
+        .then(|| normalize_path(&Path::new(&previous).join(path)))
+        .filter(|logical| env::set_current_dir(logical).is_ok());
+    if logical.is_none()
+        && let Err(e) = env::set_current_dir(path)
+    {
+        writeln!(
+            io.stderr,
+            "{}: {}: {}",
+            name,
+            path.display(),
+            describe_io_error(&e)
+        )?;
+        return Ok(1);
+    }
+    let current = logical.or_else(|| env::current_dir().ok());
+    let updated = vars.set("OLDPWD", previous).and_then(|()| match current {
+        Some(current) => vars.set("PWD", current.display().to_string()),
+        None => Ok(()),
+    });
+    if let Err(error) = updated {
+        writeln!(io.stderr, "{}: {}", name, error)?;
+        return Ok(1);
+    }
+    Ok(0)
+}
+
+/// Resolves `.` and `..` components of an absolute path without looking at
+/// the file system.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            std::path::Component::ParentDir => {
+                normalized.pop();
+            }
+            std::path::Component::Normal(name) => normalized.push(name),
+            _ => {}
+        }
+    }
+    normalized
+}
+
+/// Whether `pwd` is an absolute path to the working directory, so that it can
+/// stand for it as the logical working directory.
+pub(crate) fn is_current_directory(pwd: &str) -> bool {
+    if !pwd.starts_with('/') {
+        return false;
+    }
+    match (fs::metadata(pwd), fs::metadata(".")) {
+        (Ok(pwd), Ok(current)) => pwd.dev() == current.dev() && pwd.ino() == current.ino(),
+        _ => false,
+    }
+}
+
+/// Prints the working directory: the logical `$PWD` while it still names it,
+/// or with `-P` the directory with all symlinks resolved.
+fn pwd_builtin(vars: &Variables, args: &[&str], io: &mut BuiltinIo) -> io::Result<i32> {
+    let mut physical = false;
+    for &option in args {
+        match option {
+            "-L" => physical = false,
+            "-P" => physical = true,
+            _ => {
+                writeln!(io.stderr, "pwd: {}: invalid option", option)?;
+                writeln!(io.stderr, "pwd: usage: pwd [-LP]")?;
+                return Ok(2);
+            }
+        }
+    }
+    match vars.get("PWD") {
+        Some(pwd) if !physical && is_current_directory(pwd) => writeln!(io.stdout, "{}", pwd)?,
+        _ => match env::current_dir() {
+            Ok(current) => writeln!(io.stdout, "{}", current.display())?,
+            Err(e) => {
+                writeln!(io.stderr, "pwd: {}", describe_io_error(&e))?;
+                return Ok(1);
+            }
+        },
+    }
+    Ok(0)
+}
+
+/// The working directory as the shell tracks it in `$PWD`.
+pub(crate) fn working_directory(vars: &Variables) -> String {
+    match vars.get("PWD") {
+        Some(pwd) if is_current_directory(pwd) => pwd.to_string(),
+        _ => env::current_dir()
+            .map(|dir| dir.display().to_string())
+            .unwrap_or_default(),
+    }
+}
+
+/// Parses a `+N` or `-N` argument of `pushd`, `popd` and `dirs` into an index of
+/// the directory stack, whose entries are counted from the top with `+N` and
+/// from the bottom with `-N`. `len` counts the working directory at the top.
+fn stack_index(arg: &str, len: usize) -> Option<usize> {
+    let (from_top, count) = match arg.split_at_checked(1)? {
+        ("+", count) => (true, count),
+        ("-", count) => (false, count),
+        _ => return None,
+    };
+    let n = count.parse::<usize>().ok().filter(|&n| n < len)?;
+    Some(if from_top { n } else { len - 1 - n })
+}
+
+/// Prints the directory stack, starting with the working directory. `-v`
+/// numbers the entries one per line, `-p` prints one per line, `-l` does not
+/// abbreviate `$HOME` to `~`, `-c` clears the stack, and `+N`/`-N` print a
+/// single entry.
+fn dirs_builtin(
+    vars: &Variables,
+    stack: &mut Vec<String>,
+    args: &[&str],
+    io: &mut BuiltinIo,
+) -> io::Result<i32> {
+    let (mut numbered, mut per_line, mut long) = (false, false, false);
+    let mut entry = None;
+    for &arg in args {
+        match arg {
+            "-c" => {
+                stack.clear();
+                return Ok(0);
+            }
+            "-v" => numbered = true,
+            "-p" => per_line = true,
+            "-l" => long = true,
+            _ => match stack_index(arg, stack.len() + 1) {
+                Some(index) => entry = Some(index),
+                None if arg.starts_with(['+', '-']) && arg[1..].parse::<usize>().is_ok() => {
+                    writeln!(
+                        io.stderr,
+                        "dirs: {}: directory stack index out of range",
+                        arg
+                    )?;
+                    return Ok(1);
+                }
+                None => {
+                    writeln!(io.stderr, "dirs: {}: invalid option", arg)?;
+                    writeln!(io.stderr, "dirs: usage: dirs [-clpv] [+N] [-N]")?;
+                    return Ok(2);
+                }
+            },
+        }
+    }
+
+    let home = vars.get("HOME");
+    let show = |dir: &str| {
+        if long {
+            dir.to_string()
+        } else {
+            abbreviate_home(dir, home)
+        }
+    };
+    let dirs: Vec<String> = std::iter::once(working_directory(vars))
+        .chain(stack.iter().cloned())
+        .collect();
+    if let Some(index) = entry {
+        writeln!(io.stdout, "{}", show(&dirs[index]))?;
+    } else if numbered {
+        for (i, dir) in dirs.iter().enumerate() {
+            writeln!(io.stdout, "{:2}  {}", i, show(dir))?;
+        }
+    } else if per_line {
+        for dir in &dirs {
+            writeln!(io.stdout, "{}", show(dir))?;
+        }
+    } else {
+        let line: Vec<String> = dirs.iter().map(|dir| show(dir)).collect();
+        writeln!(io.stdout, "{}", line.join(" "))?;
+    }
+    Ok(0)
+}
+
+/// Pushes the working directory onto the directory stack and changes to `dir`.
+/// Without an argument the top two entries are swapped; `+N`/`-N` rotate the
+/// stack so that entry becomes the top. Prints the new stack on success.
+fn pushd_builtin(
+    vars: &mut Variables,
+    stack: &mut Vec<String>,
+    args: &[&str],
+    io: &mut BuiltinIo,
+) -> io::Result<i32> {
+    let current = working_directory(vars);
+    let mut dirs: Vec<String> = std::iter::once(current.clone())
+        .chain(stack.iter().cloned())
+        .collect();
+    match args {
+        [] => {
+            if stack.is_empty() {
+                writeln!(io.stderr, "pushd: no other directory")?;
+                return Ok(1);
+            }
+            dirs.swap(0, 1);
+        }
+        [arg]
+            if arg.len() > 1
+                && arg.starts_with(['+', '-'])
+                && arg[1..].parse::<usize>().is_ok() =>
+        {
+            let Some(index) = stack_index(arg, dirs.len()) else {
+                writeln!(
+                    io.stderr,
+                    "pushd: {}: directory stack index out of range",
+                    arg
+                )?;
+                return Ok(1);
+            };
+            dirs.rotate_left(index);
+        }
+        [dir] => dirs.insert(0, dir.to_string()),
+        _ => {
+            writeln!(io.stderr, "pushd: too many arguments")?;
+            return Ok(1);
+        }
+    }
+
+    if dirs[0] != current {
+        let status = change_directory(vars, "pushd", Path::new(&dirs[0]), false, io)?;
+        if status != 0 {
+            return Ok(status);
+        }
+    }
+    *stack = dirs.split_off(1);
+    dirs_builtin(vars, stack, &[], io)
+}
+
+/// Removes the top of the directory stack and changes to the new top, or with
+/// `+N`/`-N` removes that entry instead. Prints the new stack on success.
+fn popd_builtin(
+    vars: &mut Variables,
+    stack: &mut Vec<String>,
+    args: &[&str],
+    io: &mut BuiltinIo,
+) -> io::Result<i32> {
+    if stack.is_empty() {
+        writeln!(io.stderr, "popd: directory stack empty")?;
+        return Ok(1);
+    }
+    let index = match args {
+        [] => 0,
+        [arg] => match stack_index(arg, stack.len() + 1) {
+            Some(index) => index,
+            None if arg.starts_with(['+', '-']) && arg[1..].parse::<usize>().is_ok() => {
+                writeln!(
+                    io.stderr,
+                    "popd: {}: directory stack index out of range",
+                    arg
+                )?;
+                return Ok(1);
+            }
+            None => {
+                writeln!(io.stderr, "popd: {}: invalid argument", arg)?;
+                writeln!(io.stderr, "popd: usage: popd [+N | -N]")?;
+                return Ok(2);
+            }
+        },
+        _ => {
+            writeln!(io.stderr, "popd: too many arguments")?;
+            return Ok(1);
+        }
+    };
+
+    if index == 0 {
+        let status = change_directory(vars, "popd", Path::new(&stack[0]), false, io)?;
+        if status != 0 {
+            return Ok(status);
+        }
+        stack.remove(0);
+    } else {
+        stack.remove(index - 1);
+    }
+    dirs_builtin(vars, stack, &[], io)
+}
+
+/// What the arguments of a builtin name, so that completion can offer them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ArgumentKind {
+    Files,
+    Directories,
+    /// A command and its arguments, as for `exec`.
+    Command,
+    /// Command names, as for `type`.
+    Commands,
+    Builtins,
+    Variables,
+    Aliases,
+    Jobs,
+    /// Job specs and the IDs of the user's processes.
+    Processes,
+    /// The directories `z` can jump to whose paths contain the word.
+    VisitedDirectories,
+}
+
+/// A command the shell runs itself rather than as a separate program.
+pub(crate) trait Builtin: Sync {
+    fn name(&self) -> &'static str;
+
+    /// The synopsis `help` prints, such as `cd [-L|-P] [dir]`.
+    fn usage(&self) -> &'static str;
+
+    /// A description of what the builtin does, for `help`.
+    fn description(&self) -> &'static str;
+
+    /// What to complete the builtin's arguments with.
+    fn arguments(&self) -> ArgumentKind {
+        ArgumentKind::Files
+    }
+
+    /// Runs the builtin for `command`, the simple command naming it, and
+    /// returns its status.
+    fn run(&self, shell: &mut Shell, command: &SimpleCommand) -> i32;
+}
+
+/// A builtin registered with its help text and a function that runs it.
+struct BuiltinCommand {
+    name: &'static str,
+    usage: &'static str,
+    description: &'static str,
+    arguments: ArgumentKind,
+    run: fn(&mut Shell, &[&str], &mut BuiltinIo) -> io::Result<i32>,
+}
+
+impl Builtin for BuiltinCommand {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn usage(&self) -> &'static str {
+        self.usage
+    }
+
+    fn description(&self) -> &'static str {
+        self.description
+    }
+
+    fn arguments(&self) -> ArgumentKind {
+        self.arguments
+    }
+
+    /// Runs the builtin with the words after its name. Output goes through the
+    /// command's redirections, and its prefix assignments are in effect until
+    /// it returns.
+    fn run(&self, shell: &mut Shell, command: &SimpleCommand) -> i32 {
+        let mut io = match open_redirections(&command.redirections).and_then(BuiltinIo::new) {
+            Ok(io) => io,
+            Err(error) => return shell.report_error(&error),
+        };
+        let args: Vec<&str> = command.words[1..].iter().map(|s| s.as_str()).collect();
+        // Prefix assignments such as `IFS=: read a b` last for this command only.
+        let saved = match shell.vars.set_temporarily(&command.assignments) {
+            Ok(saved) => saved,
+            Err(error) => return shell.report_error(&error),
+        };
+        let result = (self.run)(shell, &args, &mut io).and_then(|status| {
+            io.stdout.flush()?;
+            Ok(status)
+        });
+        shell.vars.restore(saved);
+        result.unwrap_or_else(|e| {
+            eprintln!("{}: write error: {}", self.name, describe_io_error(&e));
+            1
+        })
+    }
+}
+
+/// A builtin given its whole command rather than only its arguments, so that
+/// it can apply the command's redirections itself.
+struct RawBuiltin {
+    name: &'static str,
+    usage: &'static str,
+    description: &'static str,
+    arguments: ArgumentKind,
+    run: fn(&mut Shell, &SimpleCommand) -> i32,
+}
+
+impl Builtin for RawBuiltin {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn usage(&self) -> &'static str {
+        self.usage
+    }
+
+    fn description(&self) -> &'static str {
+        self.description
+    }
+
+    fn arguments(&self) -> ArgumentKind {
+        self.arguments
+    }
+
+    fn run(&self, shell: &mut Shell, command: &SimpleCommand) -> i32 {
+        (self.run)(shell, command)
+    }
+}
+
+/// Every builtin, in the order `help` lists them. `type`, `help` and
+/// completion all look builtins up here, so adding one is a single entry.
+pub(crate) static BUILTINS: [&dyn Builtin; 44] = [
+    &RawBuiltin {
+        name: ".",
+        usage: ". filename [arg ...]",
+        description: "Runs the commands in a file in the current shell. Same as `source`.",
+        arguments: ArgumentKind::Files,
+        run: |shell, command| {
+            shell.run_redirected(command, |shell, args, io| {
+                shell.source_builtin(".", args, io)
+            })
+        },
+    },
+    &BuiltinCommand {
+        name: "alias",
+        usage: "alias [-p] [name[=value] ...]",
+        description: "Defines aliases, or prints them when given only names or nothing.",
+        arguments: ArgumentKind::Aliases,
+        run: |shell, args, io| alias_builtin(&mut shell.aliases, args, io),
+    },
+    &BuiltinCommand {
+        name: "allowenv",
+        usage: "allowenv [-r] [dir]",
+        description: "Allows the .shellenv file of dir, by default the working directory, or the\n\
+                      nearest one above it, to be loaded on entering the directory at the prompt,\n\
+                      and loads it. -r revokes it. A file changed since it was allowed must be\n\
+                      allowed again.",
+        arguments: ArgumentKind::Directories,
+        run: |shell, args, io| allowenv_builtin(shell, args, io),
+    },
+    &BuiltinCommand {
+        name: "bg",
+        usage: "bg [job_spec ...]",
+        description: "Resumes stopped jobs in the background, by default the current job.",
+        arguments: ArgumentKind::Jobs,
+        run: |shell, args, io| bg_builtin(&mut shell.jobs, args, io),
+    },
+    &BuiltinCommand {
+        name: "bind",
+        usage: "bind [-lp] [-r keyseq] [keyseq:function-name | keyseq:\"text\" ...]",
+        description: "Binds a key sequence such as \"\\C-g\" to a line editor function, listed by\n\
+                      -l, or to text to type, which runs the line if it ends in \\n. -p lists the\n\
+                      bindings and -r removes one.",
+        arguments: ArgumentKind::Files,
+        run: |shell, args, io| bind_builtin(&mut shell.key_bindings, args, io),
+    },
+    &BuiltinCommand {
+        name: "cd",
+        usage: "cd [-L|-P] [dir]",
+        description: "Changes the working directory, by default to $HOME. `cd -` returns to\n\
+                      $OLDPWD, and relative directories are also looked up in $CDPATH.",
+        arguments: ArgumentKind::Directories,
+        run: |shell, args, io| match shell.correct_cd_target(args) {
+            Some(Correction::Accepted(target)) => cd_builtin(&mut shell.vars, &[&target], io),
+            Some(Correction::Edit) => Ok(1),
+            None => cd_builtin(&mut shell.vars, args, io),
+        },
+    },
+    &BuiltinCommand {
+        name: "command",
+        usage: "command [-pVv] command [arg ...]",
+        description: "Runs a builtin or program, skipping aliases. With -v or -V, describes how\n\
+                      each name would be resolved instead.",
+        arguments: ArgumentKind::Command,
+        run: |shell, args, io| {
+            command_builtin(&shell.aliases, &shell.functions, &shell.vars, args, io)
+        },
+    },
+    &BuiltinCommand {
+        name: "complete",
+        usage: "complete [-dfcpr] [-W wordlist] [-C command] [name ...]",
+        description: "Sets how the arguments of the named commands are completed, or prints or\n\
+                      removes the rules with -p and -r.",
+        arguments: ArgumentKind::Commands,
+        run: |shell, args, io| complete_builtin(&mut shell.completion_specs, args, io),
+    },
+    &BuiltinCommand {
+        name: "declare",
+        usage: "declare [-fFgiprx] [name[=value] ...]",
+        description: "Sets variables and their attributes: -i integer, -r read-only and -x\n\
+                      exported, with + instead of - to take one away. Variables are local inside\n\
+                      a function unless -g is given. -p prints variables and -f or -F functions.",
+        arguments: ArgumentKind::Variables,
+        run: |shell, args, io| {
+            declare_builtin("declare", &mut shell.vars, &shell.functions, args, io)
+        },
+    },
+    &BuiltinCommand {
+        name: "dirs",
+        usage: "dirs [-clpv] [+N] [-N]",
+        description: "Prints the directory stack, or clears it with -c.",
+        arguments: ArgumentKind::Files,
+        run: |shell, args, io| dirs_builtin(&shell.vars, &mut shell.dir_stack, args, io),
+    },
+    &BuiltinCommand {
+        name: "disown",
+        usage: "disown [-h] [-ar] [jobspec ...]",
+        description: "Removes jobs from the job table, by default the current job, so that they\n\
+                      keep running after the shell exits. -h keeps them in the table but not hung\n\
+                      up.",
+        arguments: ArgumentKind::Jobs,
+        run: |shell, args, io| disown_builtin(&mut shell.jobs, args, io),
+    },
+    &BuiltinCommand {
+        name: "echo",
+        usage: "echo [-neE] [arg ...]",
+        description: "Prints the arguments separated by spaces. -n leaves out the newline and -e\n\
+                      interprets backslash escapes.",
+        arguments: ArgumentKind::Files,
+        run: |_, args, io| echo_builtin(args, io),
+    },
+    &RawBuiltin {
+        name: "eval",
+        usage: "eval [arg ...]",
+        description: "Joins the arguments with spaces and runs the result as shell commands.",
+        arguments: ArgumentKind::Command,
+        run: |shell, command| {
+            shell.run_redirected(command, |shell, args, io| shell.eval_builtin(args, io))
+        },
+    },
+    &RawBuiltin {
+        name: "exec",
+        usage: "exec [command [arg ...]]",
+        description: "Replaces the shell with the command. Without one, its redirections apply to\n\
+                      the shell itself.",
+        arguments: ArgumentKind::Command,
+        run: |shell, command| shell.exec_builtin(command),
+    },
+    &BuiltinCommand {
+        name: "exit",
+        usage: "exit [n]",
+        description: "Exits the shell with status n, by default that of the last command.",
+        arguments: ArgumentKind::Files,
+        run: |shell, args, io| shell.exit_builtin(args, io),
+    },
+    &BuiltinCommand {
+        name: "export",
+        usage: "export [-np] [name[=value] ...]",
+        description: "Marks variables to be passed to commands the shell runs, or with -n stops\n\
+                      passing them. Without names, prints the exported variables.",
+        arguments: ArgumentKind::Variables,
+        run: |shell, args, io| export_builtin(&mut shell.vars, args, io),
+    },
+    &BuiltinCommand {
+        name: "fc",
+        usage: "fc [-e ename] [-lnr] [first] [last] or fc -s [pat=rep] [command]",
+        description: "Lists history entries with -l, re-runs one with -s, or otherwise edits\n\
+                      entries in $FCEDIT and runs the result.",
+        arguments: ArgumentKind::Files,
+        run: |shell, args, io| shell.fc_builtin(args, io),
+    },
+    &BuiltinCommand {
+        name: "fg",
+        usage: "fg [job_spec]",
+        description: "Brings a job to the foreground, by default the current job.",
+        arguments: ArgumentKind::Jobs,
+        run: |shell, args, io| fg_builtin(&mut shell.jobs, args, io),
+    },
+    &BuiltinCommand {
+        name: "hash",
+        usage: "hash [-lr] [-p pathname] [-dt] [name ...]",
+        description: "Remembers where commands were found in PATH. Without arguments, lists them\n\
+                      with how often they ran; -r forgets them all, -d the named ones, -t prints\n\
+                      their paths, and -p sets one.",
+        arguments: ArgumentKind::Commands,
+        run: |shell, args, io| hash_builtin(&mut shell.hashed_commands, &shell.vars, args, io),
+    },
+    &BuiltinCommand {
+        name: "help",
+        usage: "help [-s] [pattern ...]",
+        description: "Describes the builtins whose names start with a pattern, or lists them all.\n\
+                      -s prints only the usage.",
+        arguments: ArgumentKind::Builtins,
+        run: |_, args, io| help_builtin(args, io),
+    },
+    &BuiltinCommand {
+        name: "history",
+        usage: "history [-c] [-d offset] [-r|-w [file]] [n]",
+        description: "Lists the last n commands, clears the list with -c, deletes an entry with\n\
+                      -d, or reads or writes the history file with -r and -w.",
+        arguments: ArgumentKind::Files,
+        run: |shell, args, io| {
+            let persist = shell.interactive.then(history_file_path).flatten();
+            let before = shell.history.clone();
+            let status = history_builtin(&mut shell.history, persist, args, io);
+            shell.history_changed |= shell.history != before;
+            status
+        },
+    },
+    &BuiltinCommand {
+        name: "j",
+        usage: "j [-lx] [pattern ...]",
+        description: "The same as z.",
+        arguments: ArgumentKind::VisitedDirectories,
+        run: |shell, args, io| z_builtin(&mut shell.vars, "j", args, io),
+    },
+    &BuiltinCommand {
+        name: "jobs",
+        usage: "jobs [-l]",
+        description: "Lists the jobs, with their process IDs with -l.",
+        arguments: ArgumentKind::Jobs,
+        run: |shell, args, io| jobs_builtin(&mut shell.jobs, args, io),
+    },
+    &BuiltinCommand {
+        name: "kill",
+        usage: "kill [-s sigspec | -n signum | -sigspec] pid | jobspec ... or kill -l [sigspec]",
+        description: "Sends a signal, by default SIGTERM, to processes or jobs, or lists signal\n\
+                      names with -l.",
+        arguments: ArgumentKind::Processes,
+        run: |shell, args, io| kill_builtin(&mut shell.jobs, args, io),
+    },
+    &BuiltinCommand {
+        name: "let",
+        usage: "let arg [arg ...]",
+        description: "Evaluates each argument as an arithmetic expression. The status is 1 if the\n\
+                      last one is 0, and 0 otherwise.",
+        arguments: ArgumentKind::Variables,
+        run: |shell, args, io| let_builtin(&mut shell.vars, args, io),
+    },
+    &BuiltinCommand {
+        name: "local",
+        usage: "local [name[=value] ...]",
+        description: "Declares variables that last until the function being run returns, hiding\n\
+                      any of the same name meanwhile. Without names, prints the function's\n\
+                      locals.",
+        arguments: ArgumentKind::Variables,
+        run: |shell, args, io| local_builtin(&mut shell.vars, args, io),
+    },
+    &BuiltinCommand {
+        name: "popd",
+        usage: "popd [+N | -N]",
+        description: "Removes a directory from the stack, by default the top one, changing to the\n\
+                      new top.",
+        arguments: ArgumentKind::Files,
+        run: |shell, args, io| popd_builtin(&mut shell.vars, &mut shell.dir_stack, args, io),
+    },
+    &BuiltinCommand {
+        name: "pushd",
+        usage: "pushd [dir | +N | -N]",
+        description: "Changes to a directory and pushes it on the directory stack, or rotates the\n\
+                      stack to bring an entry to the top.",
+        arguments: ArgumentKind::Directories,
+        run: |shell, args, io| pushd_builtin(&mut shell.vars, &mut shell.dir_stack, args, io),
+    },
+    &BuiltinCommand {
+        name: "pwd",
+        usage: "pwd [-LP]",
+        description: "Prints the working directory, with symbolic links resolved with -P.",
+        arguments: ArgumentKind::Files,
+        run: |shell, args, io| pwd_builtin(&shell.vars, args, io),
+    },
+    &BuiltinCommand {
+        name: "readonly",
+        usage: "readonly [-p] [name[=value] ...]",
+        description: "Makes variables read-only, so that they can no longer be assigned or unset.\n\
+                      Without names, prints the read-only variables.",
+        arguments: ArgumentKind::Variables,
+        run: |shell, args, io| {
+            let args: Vec<&str> = ["-gr"].into_iter().chain(args.iter().copied()).collect();
+            declare_builtin("readonly", &mut shell.vars, &shell.functions, &args, io)
+        },
+    },
+    &BuiltinCommand {
+        name: "read",
+        usage: "read [-rs] [-p prompt] [name ...]",
+        description: "Reads a line from standard input and splits it into the named variables, by\n\
+                      default REPLY.",
+        arguments: ArgumentKind::Variables,
+        run: |shell, args, io| read_builtin(&mut shell.vars, args, io),
+    },
+    &BuiltinCommand {
+        name: "rehash",
+        usage: "rehash",
+        description: "Forgets the cached locations of programs, so that PATH is searched again.",
+        arguments: ArgumentKind::Files,
+        run: |shell, _, _| {
+            forget_cached_commands();
+            shell.hashed_commands.entries.clear();
+            Ok(0)
+        },
+    },
+    &BuiltinCommand {
+        name: "return",
+        usage: "return [n]",
+        description: "Ends the function or sourced file being run with status n, by default that\n\
+                      of the last command.",
+        arguments: ArgumentKind::Files,
+        run: |shell, args, io| shell.return_builtin(args, io),
+    },
+    &BuiltinCommand {
+        name: "set",
+        usage: "set [-eux] [-o option] [--] [arg ...]",
+        description: "Sets shell options, or the positional parameters after --. Without\n\
+                      arguments, prints the variables.",
+        arguments: ArgumentKind::Files,
+        run: |shell, args, io| {
+            set_builtin(
+                &shell.vars,
+                &mut shell.options,
+                &mut shell.positional,
+                args,
+                io,
+            )
+        },
+    },
+    &BuiltinCommand {
+        name: "shift",
+        usage: "shift [n]",
+        description: "Drops the first n positional parameters, by default one.",
+        arguments: ArgumentKind::Files,
+        run: |shell, args, io| shift_builtin(&mut shell.positional, args, io),
+    },
+    &RawBuiltin {
+        name: "source",
+        usage: "source filename [arg ...]",
+        description: "Runs the commands in a file in the current shell, with the arguments as the\n\
+                      positional parameters.",
+        arguments: ArgumentKind::Files,
+        run: |shell, command| {
+            shell.run_redirected(command, |shell, args, io| {
+                shell.source_builtin("source", args, io)
+            })
+        },
+    },
+    &BuiltinCommand {
+        name: "trap",
+        usage: "trap [-lp] [[action] signal_spec ...]",
+        description: "Runs an action when the shell receives a signal or exits. An empty action\n\
+                      ignores the signal and `-` restores its default.",
+        arguments: ArgumentKind::Files,
+        run: |shell, args, io| trap_builtin(&mut shell.traps, shell.terminal.is_some(), args, io),
+    },
+    &BuiltinCommand {
+        name: "type",
+        usage: "type [-at] name [name ...]",
+        description: "Describes how each name would be run: as an alias, keyword, function,\n\
+                      builtin or file.",
+        arguments: ArgumentKind::Commands,
+        run: |shell, args, io| {
+            type_builtin(&shell.aliases, &shell.functions, &shell.vars, args, io)
+        },
+    },
+    &BuiltinCommand {
+        name: "typeset",
+        usage: "typeset [-fFgiprx] [name[=value] ...]",
+        description: "Sets variables and their attributes. Same as `declare`.",
+        arguments: ArgumentKind::Variables,
+        run: |shell, args, io| {
+            declare_builtin("typeset", &mut shell.vars, &shell.functions, args, io)
+        },
+    },
+    &BuiltinCommand {
+        name: "umask",
+        usage: "umask [-p] [-S] [mode]",
+        description: "Sets the permissions taken away from files the shell and its commands\n\
+                      create, as an octal number or symbolically like u=rwx,g=rx,o=. Without a\n\
+                      mode, prints it, symbolically with -S.",
+        arguments: ArgumentKind::Files,
+        run: |_, args, io| umask_builtin(args, io),
+    },
+    &BuiltinCommand {
+        name: "unalias",
+        usage: "unalias [-a] name [name ...]",
+        description: "Removes aliases, or all of them with -a.",
+        arguments: ArgumentKind::Aliases,
+        run: |shell, args, io| unalias_builtin(&mut shell.aliases, args, io),
+    },
+    &BuiltinCommand {
+        name: "unset",
+        usage: "unset [-f] [-v] name [name ...]",
+        description: "Removes variables, or functions with -f.",
+        arguments: ArgumentKind::Variables,
+        run: |shell, args, io| unset_builtin(&mut shell.vars, &mut shell.functions, args, io),
+    },
+    &BuiltinCommand {
+        name: "wait",
+        usage: "wait [-n] [id ...]",
+        description: "Waits for jobs or processes, by default all of them, and returns the status\n\
+                      of the last. -n waits for the next one to finish.",
+        arguments: ArgumentKind::Processes,
+        run: |shell, args, io| wait_builtin(&mut shell.jobs, args, io),
+    },
+    &BuiltinCommand {
+        name: "z",
+        usage: "z [-lx] [pattern ...]",
+        description: "Changes to the most frecent directory visited at the prompt whose path\n\
+                      contains the patterns in order. -l, or no pattern, lists the matches with\n\
+                      their frecency instead, and -x forgets the working directory. Kept in\n\
+                      $_Z_DATA, by default ~/.z.",
+        arguments: ArgumentKind::VisitedDirectories,
+        run: |shell, args, io| z_builtin(&mut shell.vars, "z", args, io),
+    },
+];
+
+/// The builtin called `name`, if there is one.
+pub(crate) fn find_builtin(name: &str) -> Option<&'static dyn Builtin> {
+    BUILTINS
+        .iter()
+        .copied()
+        .find(|builtin| builtin.name() == name)
+}
+
+pub(crate) fn is_builtin(name: &str) -> bool {
+    find_builtin(name).is_some()
+}
+
+/// `help` describes the builtins whose names start with any of the patterns, or
+/// lists the usage of all of them. `-s` prints only the usage.
+fn help_builtin(args: &[&str], io: &mut BuiltinIo) -> io::Result<i32> {
+    let (short, patterns) = match args.split_first() {
+        Some((&"-s", patterns)) => (true, patterns),
+        _ => (false, args),
+    };
+    if patterns.is_empty() {
+        for builtin in &BUILTINS {
+            writeln!(io.stdout, "{}", builtin.usage())?;
+        }
+        return Ok(0);
+    }
+    let mut status = 0;
+    for pattern in patterns {
+        let mut found = false;
+        for builtin in BUILTINS
+            .iter()
+            .filter(|builtin| builtin.name().starts_with(pattern))
+        {
+            found = true;
+            writeln!(io.stdout, "{}: {}", builtin.name(), builtin.usage())?;
+            if !short {
+                for line in builtin.description().lines() {
+                    writeln!(io.stdout, "    {}", line)?;
+                }
+            }
+        }
+        if !found {
+            writeln!(io.stderr, "help: no help topics match `{}'", pattern)?;
+            status = 1;
+        }
+    }
+    Ok(status)
+}
+
+impl Shell {
+    /// Lists, edits or re-runs commands from the history. `fc -l [first
+    /// [last]]` lists them (`-n` without numbers, `-r` newest first); `fc -s
+    /// [old=new] [command]` runs a command again after replacing `old`; and
+    /// otherwise the commands are opened in the editor (`-e`, or `$FCEDIT`,
+    /// `$EDITOR`, `vi`) and run once it exits successfully. Commands are given
+    /// by number, negative offset or prefix, and default to the previous one.
+    /// The edited or re-run commands replace the `fc` line in the history.
+    fn fc_builtin(&mut self, args: &[&str], io: &mut BuiltinIo) -> io::Result<i32> {
+        let (mut list, mut numbered, mut reverse, mut substitute) = (false, true, false, false);
+        let mut editor = None;
+        let mut operands = args;
+        while let Some((&option, rest)) = operands.split_first() {
+            if option == "--" {
+                operands = rest;
+                break;
+            }
+            if option.len() < 2 || !option.starts_with('-') || option[1..].parse::<usize>().is_ok()
+            {
+                break;
+            }
+            operands = rest;
+            for flag in option[1..].chars() {
+                match flag {
+                    'l' => list = true,
+                    'n' => numbered = false,
+                    'r' => reverse = true,
+                    's' => substitute = true,
+                    'e' => {
+                        let Some((&name, rest)) = operands.split_first() else {
+                            writeln!(io.stderr, "fc: -e: option requires an argument")?;
+                            return Ok(2);
+                        };
+                        editor = Some(name.to_string());
+                        operands = rest;
+                    }
+                    _ => {
+                        writeln!(io.stderr, "fc: -{}: invalid option", flag)?;
+                        writeln!(
+                            io.stderr,
+                            "fc: usage: fc [-e ename] [-lnr] [first] [last] \
+                             or fc -s [pat=rep] [command]"
+                        )?;
+                        return Ok(2);
+                    }
+                }
+            }
+        }
+
+        // The `fc` command itself is the newest entry of an interactive history.
+        let available =
+            self.history.len() - usize::from(self.interactive && !self.history.is_empty());
+        let history = &self.history[..available];
+        let find = |spec: &str| -> Option<usize> {
+            match spec.parse::<i64>() {
+                Ok(n) if n < 0 => history.len().checked_sub(n.unsigned_abs() as usize),
+                Ok(n) => (n as usize)
+                    .checked_sub(1)
+                    .map(|index| index.min(history.len().saturating_sub(1))),
+                Err(_) => history.iter().rposition(|entry| entry.starts_with(spec)),
+            }
+            .filter(|&index| index < history.len())
+        };
+
+        if substitute {
+            let (replacement, operands) = match operands.split_first() {
+                Some((pair, rest)) if pair.contains('=') => (pair.split_once('='), rest),
+                _ => (None, operands),
+            };
+            let Some(index) = find(operands.first().copied().unwrap_or("-1")) else {
+                writeln!(io.stderr, "fc: no command found")?;
+                return Ok(1);
+            };
+            let command = match replacement {
+                Some((old, new)) if !old.is_empty() => history[index].replacen(old, new, 1),
+                _ => history[index].clone(),
+            };
+            writeln!(io.stderr, "{}", command)?;
+            return Ok(self.run_from_history(&command));
+        }
+
+        let default_first = if list { "-16" } else { "-1" };
+        let first_spec = operands.first().copied().unwrap_or(default_first);
+        let last_spec = operands
+            .get(1)
+            .copied()
+            .unwrap_or(if list { "-1" } else { first_spec });
+        let range = match (find(first_spec), find(last_spec)) {
+            (Some(first), Some(last)) => first..=last,
+            (None, _) if list && operands.is_empty() && !history.is_empty() => {
+                0..=history.len() - 1
+            }
+            _ => {
+                writeln!(io.stderr, "fc: history specification out of range")?;
+                return Ok(1);
+            }
+        };
+        let (start, end) = (
+            *range.start().min(range.end()),
+            *range.start().max(range.end()),
+        );
+        let mut indices: Vec<usize> = (start..=end).collect();
+        if reverse != (range.start() > range.end()) {
+            indices.reverse();
+        }
+
+        if list {
+            for index in indices {
+                if numbered {
+                    writeln!(io.stdout, "{}\t{}", index + 1, history[index])?;
+                } else {
+                    writeln!(io.stdout, "\t{}", history[index])?;
+                }
+            }
+            return Ok(0);
+        }
+
+        let text: String = indices
+            .iter()
+            .map(|&index| format!("{}\n", history[index]))
+            .collect();
+        let editor = editor.unwrap_or_else(|| editor_command(&self.vars, &["FCEDIT", "EDITOR"]));
+        io.stdout.flush()?;
+        let edited = edit_text(&text, |path| {
+            self.run_line(&format!(
+                "{} {}",
+                editor,
+                single_quote(&path.display().to_string())
+            ));
+            self.last_status == 0
+        });
+        match edited {
+            Ok(Some(commands)) => {
+                write!(io.stderr, "{}", commands)?;
+                Ok(self.run_from_history(commands.trim_end_matches('\n')))
+            }
+            Ok(None) => Ok(self.last_status),
+            Err(e) => {
+                writeln!(io.stderr, "fc: {}", describe_io_error(&e))?;
+                Ok(1)
+            }
+        }
+    }
+
+    /// Runs commands taken from the history by `fc`, recording them in place of
+    /// the `fc` command that produced them.
+    fn run_from_history(&mut self, commands: &str) -> i32 {
+        if self.interactive && !commands.is_empty() {
+            if let Some(last) = self.history.last_mut() {
+                *last = commands.to_string();
+            }
+            if let Some(path) = history_file_path()
+                && let Err(e) = write_history_file(&path, &self.history)
+            {
+                eprintln!("history: {}: {}", path.display(), e);
+            }
+            self.history_changed = true;
+        }
+        self.run_lines(commands, None)
+    }
+
+    /// Runs `command` if it names a builtin and returns its status, or `None` if
+    /// the command is not a builtin.
+    pub(crate) fn run_builtin(&mut self, command: &SimpleCommand) -> Option<i32> {
+        let builtin = find_builtin(&command.words[0])?;
+        Some(builtin.run(self, command))
+    }
+
+    /// Runs a builtin that runs other commands with the words after its name.
+    /// The command's redirections are applied to the shell's own descriptors,
+    /// so that the commands it runs use them too, and its prefix assignments
+    /// are in effect until it returns.
+    fn run_redirected(
+        &mut self,
+        command: &SimpleCommand,
+        run: impl FnOnce(&mut Shell, &[&str], &mut BuiltinIo) -> io::Result<i32>,
+    ) -> i32 {
+        let saved_fds = match open_redirections(&command.redirections) {
+            Ok(actions) => redirect_shell(actions),
+            Err(error) => return self.report_error(&error),
+        };
+        let saved_vars = match self.vars.set_temporarily(&command.assignments) {
+            Ok(saved) => saved,
+            Err(error) => {
+                restore_shell_fds(saved_fds);
+                return self.report_error(&error);
+            }
+        };
+        let mut io = BuiltinIo {
+            stdin: None,
+            stdout: Output::Stdout,
+            stderr: Output::Stderr,
+        };
+        let args: Vec<&str> = command.words[1..].iter().map(|s| s.as_str()).collect();
+        let result = run(self, &args, &mut io).and_then(|status| {
+            io.stdout.flush()?;
+            Ok(status)
+        });
+        self.vars.restore(saved_vars);
+        restore_shell_fds(saved_fds);
+        result.unwrap_or_else(|e| {
+            eprintln!(
+                "{}: write error: {}",
+                command.words[0],
+                describe_io_error(&e)
+            );
+            1
+        })
+    }
+
+    /// Replaces the shell with the program named by `exec`'s arguments, with the
+    /// command's redirections applied. Without a program, the redirections are
+    /// applied to the shell itself and stay in effect. If the program cannot be
+    /// run, a non-interactive shell exits.
+    fn exec_builtin(&mut self, command: &SimpleCommand) -> i32 {
+        let actions = match open_redirections(&command.redirections) {
+            Ok(actions) => actions,
+            Err(error) => return self.report_error(&error),
+        };
+        let Some(program) = command.words.get(1) else {
+            drop(redirect_shell(actions));
+            return 0;
+        };
+        let status = match self.resolve_command(program) {
+            Ok(program_path) => {
+                let mut cmd = process::Command::new(&program_path);
+                cmd.arg0(program);
+                cmd.args(&command.words[2..]);
+                cmd.env_clear();
+                cmd.envs(self.vars.exported());
+                cmd.envs(
+                    command
+                        .assignments
+                        .iter()
+                        .map(|(name, value)| (name, value)),
+                );
+                prepare_child(&mut cmd, None, self.inherited_signal_resets());
+                apply_fd_actions(&mut cmd, actions);
+                let _ = io::stdout().flush();
+                let source = cmd.exec();
+                self.report_exec_error(&ShellError::Exec {
+                    command: program.clone(),
+                    source,
+                })
+            }
+            Err(error) => self.report_exec_error(&error),
+        };
+        if !self.interactive {
+            self.exit_requested = true;
+        }
+        status
+    }
+
+    /// Reports why `exec` could not run its program, as `exec: program: ...`.
+    fn report_exec_error(&self, error: &ShellError) -> i32 {
+        eprintln!("{}exec: {}", self.error_prefix(), error);
+        error.status()
+    }
+
+    fn exit_builtin(&mut self, args: &[&str], io: &mut BuiltinIo) -> io::Result<i32> {
+        if args.len() > 1 {
+            writeln!(io.stderr, "exit: too many arguments")?;
+            return Ok(1);
+        }
+        if self.interactive && !self.stopped_jobs_warned {
+            self.jobs.reap();
+            if self.jobs.has_stopped() {
+                writeln!(io.stderr, "There are stopped jobs.")?;
+                self.stopped_jobs_warned = true;
+                return Ok(1);
+            }
+        }
+        self.exit_requested = true;
+        let Some(&code) = args.first() else {
+            return Ok(self.last_status);
+        };
+        match code.trim().parse::<i64>() {
+            Ok(code) => Ok(code.rem_euclid(256) as i32),
+            Err(_) => {
+                writeln!(io.stderr, "exit: {}: numeric argument required", code)?;
+                Ok(2)
+            }
+        }
+    }
+
+    fn return_builtin(&mut self, args: &[&str], io: &mut BuiltinIo) -> io::Result<i32> {
+        if self.return_depth == 0 {
+            writeln!(
+                io.stderr,
+                "return: can only `return' from a function or sourced script"
+            )?;
+            return Ok(1);
+        }
+        if args.len() > 1 {
+            writeln!(io.stderr, "return: too many arguments")?;
+            return Ok(1);
+        }
+        let status = match args.first() {
+            None => self.last_status,
+            Some(code) => match code.trim().parse::<i64>() {
+                Ok(code) => code.rem_euclid(256) as i32,
+                Err(_) => {
+                    writeln!(io.stderr, "return: {}: numeric argument required", code)?;
+                    2
+                }
+            },
+        };
+        self.returning = true;
+        Ok(status)
+    }
+
+    fn eval_builtin(&mut self, args: &[&str], io: &mut BuiltinIo) -> io::Result<i32> {
+        if args.is_empty() {
+            return Ok(0);
+        }
+        io.stdout.flush()?;
+        self.run_lines(&args.join(" "), None);
+        Ok(self.last_status)
+    }
+
+    /// `source` and `.`, which is `name`: runs a file in the current shell,
+    /// with any further arguments as the positional parameters meanwhile.
+    fn source_builtin(&mut self, name: &str, args: &[&str], io: &mut BuiltinIo) -> io::Result<i32> {
+        let Some((&path, params)) = args.split_first() else {
+            writeln!(io.stderr, "{}: filename argument required", name)?;
+            return Ok(2);
+        };
+        io.stdout.flush()?;
+        if params.is_empty() {
+            return self.source_file(name, path, io);
+        }
+        let params = params.iter().map(|param| param.to_string()).collect();
+        let saved = mem::replace(&mut self.positional, params);
+        let status = self.source_file(name, path, io);
+        self.positional = saved;
+        status
+    }
+}
@@ -0,0 +1,1047 @@
+//! Tab completion and syntax highlighting for the line editor.
+
+use rustyline::completion::{Completer, Pair};
+use rustyline::config::CompletionType;
+use rustyline::highlight::{CmdKind, Highlighter};
+use rustyline::hint::Hinter;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Context, Helper, Result};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::process::{self, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use nix::unistd::geteuid;
+
+use crate::builtins::{ArgumentKind, BUILTINS, BuiltinIo, find_builtin, is_builtin};
+use crate::exec::{find_cached_command, is_executable, with_cached_commands};
+use crate::expansion::is_name_char;
+use crate::frecency::{data_file_path, matching_directories, read_visited};
+use crate::parser::{
+    RESERVED_WORDS, Token, incomplete_input, lex_command_line, parse_redirect_operator,
+};
+use crate::variables::{parse_assignment, single_quote};
+
+const COMMAND_COLOR: &str = "\x1b[32m";
+const UNKNOWN_COMMAND_COLOR: &str = "\x1b[31m";
+const STRING_COLOR: &str = "\x1b[33m";
+const OPERATOR_COLOR: &str = "\x1b[36m";
+const KEYWORD_COLOR: &str = "\x1b[35m";
+const RESET_COLOR: &str = "\x1b[0m";
+
+/// Put in front of the prompt with `set -o vi`, and swapped for
+/// `VI_COMMAND_INDICATOR` while the line editor is in command mode. Both are
+/// as wide, so that the line stays where the editor expects it.
+pub(crate) const VI_INSERT_INDICATOR: &str = "(ins)";
+const VI_COMMAND_INDICATOR: &str = "(cmd)";
+
+fn push_colored(out: &mut String, text: &str, color: &str) {
+    out.push_str(color);
+    out.push_str(text);
+    out.push_str(RESET_COLOR);
+}
+
+/// Appends `word` to `out` with its quoted parts colored as strings.
+fn highlight_quotes(out: &mut String, word: &str) {
+    let mut rest = word;
+    while let Some(open) = rest.find(['\'', '"', '\\']) {
+        let (plain, quoted) = rest.split_at(open);
+        out.push_str(plain);
+        let quote = quoted.chars().next().unwrap_or_default();
+        if quote == '\\' {
+            let escaped = quoted.chars().nth(1).map_or(1, |c| 1 + c.len_utf8());
+            out.push_str(&quoted[..escaped]);
+            rest = &quoted[escaped..];
+            continue;
+        }
+        let close = quoted[1..].find(quote).map_or(quoted.len(), |end| end + 2);
+        push_colored(out, &quoted[..close], STRING_COLOR);
+        rest = &quoted[close..];
+    }
+    out.push_str(rest);
+}
+
+/// Whether `name` would run something: a builtin, an executable path, or a
+/// program found in `path_var`.
+fn is_known_command(name: &str, path_var: &str) -> bool {
+    if is_builtin(name) {
+        return true;
+    }
+    if name.contains('/') {
+        return is_executable(name.as_ref());
+    }
+    find_cached_command(name, path_var).is_some()
+}
+
+/// Colors a command line for display: command names green if they can be run
+/// and red otherwise, reserved words magenta, quoted strings yellow, and
+/// operators and redirections cyan.
+fn highlight_command_line(line: &str, path_var: &str) -> String {
+    let mut out = String::with_capacity(line.len() * 2);
+    let mut last = 0;
+    let mut command_position = true;
+    for (token, span) in lex_command_line(line) {
+        out.push_str(&line[last..span.start]);
+        let text = &line[span.clone()];
+        last = span.end;
+        let word = match token {
+            Token::Word(word) => word,
+            Token::HereDoc(_) | Token::CloseParen => {
+                push_colored(&mut out, text, OPERATOR_COLOR);
+                continue;
+            }
+            _ => {
+                push_colored(&mut out, text, OPERATOR_COLOR);
+                command_position = true;
+                continue;
+            }
+        };
+        if parse_redirect_operator(&word).is_some() {
+            push_colored(&mut out, text, OPERATOR_COLOR);
+        } else if command_position && RESERVED_WORDS.contains(&word.as_str()) {
+            push_colored(&mut out, text, KEYWORD_COLOR);
+            command_position = matches!(word.as_str(), "if" | "then" | "elif" | "else" | "{");
+        } else if command_position && parse_assignment(&word).is_none() {
+            let color = if is_known_command(&word, path_var) {
+                COMMAND_COLOR
+            } else {
+                UNKNOWN_COMMAND_COLOR
+            };
+            push_colored(&mut out, text, color);
+            command_position = false;
+        } else {
+            highlight_quotes(&mut out, text);
+        }
+    }
+    out.push_str(&line[last..]);
+    out
+}
+
+/// The kind of file a completion candidate names.
+#[derive(Clone, Copy, PartialEq)]
+enum FileKind {
+    Directory,
+    Symlink,
+    Executable,
+    File,
+}
+
+/// The SGR parameters `ls_colors`, in the format of `$LS_COLORS`, gives a file
+/// named `name` of kind `kind`. Types it doesn't mention get the usual `ls`
+/// colors: bold blue directories, cyan symlinks and green executables.
+fn file_color(ls_colors: &str, name: &str, kind: FileKind) -> Option<String> {
+    let (key, default) = match kind {
+        FileKind::Directory => ("di", Some("01;34")),
+        FileKind::Symlink => ("ln", Some("01;36")),
+        FileKind::Executable => ("ex", Some("01;32")),
+        FileKind::File => ("fi", None),
+    };
+    let mut color = None;
+    let mut extension_color = None;
+    for (pattern, sgr) in ls_colors
+        .split(':')
+        .filter_map(|entry| entry.split_once('='))
+    {
+        if pattern == key {
+            color = Some(sgr);
+        } else if let Some(suffix) = pattern.strip_prefix('*')
+            && kind == FileKind::File
+            && name.ends_with(suffix)
+        {
+            extension_color = Some(sgr);
+        }
+    }
+    extension_color
+        .or(color)
+        .or(default)
+        .filter(|sgr| !sgr.is_empty())
+        .map(str::to_string)
+}
+
+/// How `complete` says to complete the arguments of a command.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CompletionSpec {
+    /// `-d`: directory names.
+    directories: bool,
+    /// `-f`: file names.
+    files: bool,
+    /// `-c`: command names.
+    commands: bool,
+    /// `-W`: a list of words separated by whitespace.
+    words: Option<String>,
+    /// `-C`: a command printing the candidates, one per line.
+    command: Option<String>,
+}
+
+impl CompletionSpec {
+    /// The `complete` command that sets this rule for `name`.
+    pub(crate) fn describe(&self, name: &str) -> String {
+        let mut line = String::from("complete");
+        for (set, flag) in [
+            (self.directories, " -d"),
+            (self.files, " -f"),
+            (self.commands, " -c"),
+        ] {
+            if set {
+                line.push_str(flag);
+            }
+        }
+        if let Some(words) = &self.words {
+            line.push_str(&format!(" -W {}", single_quote(words)));
+        }
+        if let Some(command) = &self.command {
+            line.push_str(&format!(" -C {}", single_quote(command)));
+        }
+        format!("{} {}", line, name)
+    }
+}
+
+/// Runs the `complete -C` command `generator` to complete `word`, an argument
+/// of `name` following `previous`, and returns the lines it prints. Like the
+/// shell's other children it gets `COMP_LINE` and `COMP_POINT` describing the
+/// line being edited.
+fn run_completion_command(
+    generator: &str,
+    name: &str,
+    word: &str,
+    previous: &str,
+    line: &str,
+    pos: usize,
+) -> Vec<String> {
+    let Ok(shell) = env::current_exe() else {
+        return Vec::new();
+    };
+    let output = process::Command::new(shell)
+        .arg("-c")
+        .arg(format!("{} \"$@\"", generator))
+        .arg(generator)
+        .args([name, word, previous])
+        .env("COMP_LINE", line)
+        .env("COMP_POINT", pos.to_string())
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output();
+    match output {
+        Ok(output) => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::to_string)
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Sets how the arguments of the named commands are completed: `-d` with
+/// directories, `-f` with files, `-c` with command names, `-W wordlist` with
+/// the given words and `-C command` with the lines printed by `command`, which
+/// is run with the command name, the word being completed and the word before
+/// it. `-r` removes the rules for the names, or all of them, and `-p` or no
+/// names prints them.
+pub(crate) fn complete_builtin(
+    specs: &mut BTreeMap<String, CompletionSpec>,
+    args: &[&str],
+    io: &mut BuiltinIo,
+) -> io::Result<i32> {
+    let mut spec = CompletionSpec::default();
+    let (mut print, mut remove) = (false, false);
+    let mut names = args;
+    while let Some((&option, rest)) = names.split_first() {
+        if option == "--" {
+            names = rest;
+            break;
+        }
+        if option.len() < 2 || !option.starts_with('-') {
+            break;
+        }
+        names = rest;
+        for flag in option[1..].chars() {
+            match flag {
+                'd' => spec.directories = true,
+                'f' => spec.files = true,
+                'c' => spec.commands = true,
+                'p' => print = true,
+                'r' => remove = true,
+                'W' | 'C' => {
+                    let Some((&value, rest)) = names.split_first() else {
+                        writeln!(
+                            io.stderr,
+                            "complete: -{}: option requires an argument",
+                            flag
+                        )?;
+                        return Ok(2);
+                    };
+                    names = rest;
+                    let target = if flag == 'W' {
+                        &mut spec.words
+                    } else {
+                        &mut spec.command
+                    };
+                    *target = Some(value.to_string());
+                }
+                'F' => {
+                    writeln!(
+                        io.stderr,
+                        "complete: -F: shell functions are not supported, use -C"
+                    )?;
+                    return Ok(2);
+                }
+                _ => {
+                    writeln!(io.stderr, "complete: -{}: invalid option", flag)?;
+                    writeln!(
+                        io.stderr,
+                        "complete: usage: complete [-dfcpr] [-W wordlist] [-C command] [name ...]"
+                    )?;
+                    return Ok(2);
+                }
+            }
+        }
+    }
+
+    if remove && names.is_empty() {
+        specs.clear();
+        return Ok(0);
+    }
+    if print || remove || names.is_empty() {
+        let mut status = 0;
+        let listed: Vec<String> = match names {
+            [] => specs.keys().cloned().collect(),
+            names => names.iter().map(|name| name.to_string()).collect(),
+        };
+        for name in &listed {
+            let found = if remove {
+                specs.remove(name)
+            } else {
+                specs.get(name).cloned()
+            };
+            match found {
+                Some(_) if remove => {}
+                Some(spec) => writeln!(io.stdout, "{}", spec.describe(name))?,
+                None => {
+                    writeln!(io.stderr, "complete: {}: no completion specification", name)?;
+                    status = 1;
+                }
+            }
+        }
+        return Ok(status);
+    }
+    for name in names {
+        specs.insert(name.to_string(), spec.clone());
+    }
+    Ok(0)
+}
+
+/// Completes, highlights and validates command lines for the line editor.
+#[derive(Default)]
+pub(crate) struct ShellCompleter {
+    /// Names of the shell's variables, refreshed before each line is read.
+    pub(crate) variables: Vec<String>,
+    /// The shell's `PATH`, refreshed with the variables.
+    pub(crate) path_var: String,
+    /// The shell's `LS_COLORS`, refreshed with the variables.
+    pub(crate) ls_colors: String,
+    /// The job table as `%N` specs with each job's state and command,
+    /// refreshed with the variables.
+    pub(crate) jobs: Vec<(String, String)>,
+    /// Names of the shell's aliases, refreshed with the variables.
+    pub(crate) aliases: Vec<String>,
+    /// Whether `set -o fuzzycomplete` is on, refreshed with the variables.
+    pub(crate) fuzzy: bool,
+    /// The shell's `complete` rules, refreshed with the variables.
+    pub(crate) completion_specs: BTreeMap<String, CompletionSpec>,
+    /// Colors of the candidates of the last completion, by their display text.
+    candidate_colors: RefCell<HashMap<String, String>>,
+    /// Set while the vi editing mode is in command mode, to show in the prompt.
+    pub(crate) vi_command_mode: Arc<AtomicBool>,
+    /// The line to run in place of the one the editor returns, when it was
+    /// accepted by a key `bind` bound to text ending in a newline.
+    pub(crate) macro_line: Arc<Mutex<Option<String>>>,
+    /// The lines of the command being read before the current line, while it
+    /// is continued at `PS2`.
+    pub(crate) previous_lines: Mutex<String>,
+    /// Set by the validator when the line it accepted leaves the command
+    /// incomplete, for `read_command` to read another line at `PS2`.
+    pub(crate) incomplete: AtomicBool,
+}
+
+impl ShellCompleter {
+    /// Adds the builtins whose names start with `word`.
+    fn complete_builtins(&self, word: &str, candidates: &mut Vec<Pair>) {
+        for builtin in BUILTINS.iter().map(|builtin| builtin.name()) {
+            if self.matches(word, builtin) {
+                candidates.push(Pair {
+                    display: builtin.to_string(),
+                    replacement: format!("{} ", builtin),
+                });
+            }
+        }
+    }
+
+    /// Adds the builtins and the programs in `PATH` whose names start with `word`.
+    fn complete_commands(
+        &self,
+        word: &str,
+        candidates: &mut Vec<Pair>,
+        colors: &mut HashMap<String, String>,
+    ) {
+        self.complete_builtins(word, candidates);
+
+        with_cached_commands(&self.path_var, |commands| {
+            for file_name in commands.keys().filter(|name| self.matches(word, name)) {
+                if !candidates.iter().any(|c| c.display == *file_name) {
+                    colors.extend(
+                        file_color(&self.ls_colors, file_name, FileKind::Executable)
+                            .map(|color| (file_name.clone(), color)),
+                    );
+                    candidates.push(Pair {
+                        display: file_name.clone(),
+                        replacement: format!("{} ", file_name),
+                    });
+                }
+            }
+        });
+    }
+
+    /// Adds the files, or only the directories, whose paths start with `word`.
+    /// Hidden files are included only if the name being completed starts with
+    /// a `.`.
+    fn complete_files(
+        &self,
+        word: &str,
+        directories_only: bool,
+        candidates: &mut Vec<Pair>,
+        colors: &mut HashMap<String, String>,
+    ) {
+        let (dir_path, file_prefix) = if word.contains('/') {
+            let path = std::path::Path::new(word);
+            if let Some(parent) = path.parent() {
+                let parent_str = if parent.as_os_str().is_empty() {
+                    "./"
+                } else {
+                    parent.to_str().unwrap_or("./")
+                };
+                let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+                (parent_str.to_string(), file_name.to_string())
+            } else {
+                ("./".to_string(), word.to_string())
+            }
+        } else {
+            ("./".to_string(), word.to_string())
+        };
+
+        if let Ok(entries) = fs::read_dir(&dir_path) {
+            for entry in entries.flatten() {
+                if let Ok(file_name) = entry.file_name().into_string()
+                    && self.matches(&file_prefix, &file_name)
+                    && (!file_name.starts_with('.') || file_prefix.starts_with('.'))
+                {
+                    let is_dir = entry.path().is_dir();
+                    if directories_only && !is_dir {
+                        continue;
+                    }
+                    let full_path = if word.contains('/') {
+                        if dir_path == "./" {
+                            file_name.clone()
+                        } else {
+                            format!("{}/{}", dir_path.trim_end_matches('/'), file_name)
+                        }
+                    } else {
+                        file_name.clone()
+                    };
+
+                    let display = if is_dir {
+                        format!("{}/", file_name)
+                    } else {
+                        file_name.clone()
+                    };
+                    let kind = match entry.file_type() {
+                        Ok(file_type) if file_type.is_symlink() => FileKind::Symlink,
+                        _ if is_dir => FileKind::Directory,
+                        _ if is_executable(&entry.path()) => FileKind::Executable,
+                        _ => FileKind::File,
+                    };
+                    colors.extend(
+                        file_color(&self.ls_colors, &file_name, kind)
+                            .map(|color| (display.clone(), color)),
+                    );
+
+                    let replacement = if is_dir {
+                        format!("{}/", full_path)
+                    } else {
+                        format!("{} ", full_path)
+                    };
+
+                    candidates.push(Pair {
+                        display,
+                        replacement,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Completes a variable name after the last `$` or `${` in `word`, which
+    /// starts at `start`. Returns `None` if the word doesn't end in one.
+    fn complete_variable(&self, word: &str, start: usize) -> Option<(usize, Vec<Pair>)> {
+        let dollar = word
+            .rfind('$')
+            .filter(|&dollar| !word[..dollar].ends_with('\\'))?;
+        let (braced, prefix) = match word[dollar + 1..].strip_prefix('{') {
+            Some(prefix) => (true, prefix),
+            None => (false, &word[dollar + 1..]),
+        };
+        if !prefix.chars().all(is_name_char) {
+            return None;
+        }
+        let candidates = self
+            .variables
+            .iter()
+            .filter(|name| self.matches(prefix, name))
+            .map(|name| Pair {
+                display: format!("${}", name),
+                replacement: if braced {
+                    format!("{}}}", name)
+                } else {
+                    name.clone()
+                },
+            })
+            .collect();
+        Some((
+            start + word.len() - prefix.len(),
+            self.ranked(prefix, candidates),
+        ))
+    }
+
+    /// Whether `candidate` completes `word`, as a prefix or, with
+    /// `fuzzycomplete`, as a fuzzy match.
+    pub(crate) fn matches(&self, word: &str, candidate: &str) -> bool {
+        match_score(word, candidate, self.fuzzy).is_some()
+    }
+
+    /// Sorts candidates by name, putting the best matches for `word` first when
+    /// matching fuzzily.
+    fn ranked(&self, word: &str, mut candidates: Vec<Pair>) -> Vec<Pair> {
+        candidates.sort_by(|a, b| a.display.cmp(&b.display));
+        if self.fuzzy {
+            let word = word.rsplit('/').next().unwrap_or_default();
+            candidates.sort_by_cached_key(|candidate| {
+                let name = candidate
+                    .display
+                    .trim_start_matches('$')
+                    .trim_end_matches('/');
+                Reverse(match_score(word, name, true))
+            });
+        }
+        candidates
+    }
+
+    /// Adds the arguments of a builtin that start with `word`.
+    fn complete_arguments(
+        &self,
+        arguments: ArgumentKind,
+        word: &str,
+        candidates: &mut Vec<Pair>,
+        colors: &mut HashMap<String, String>,
+    ) {
+        let names = match arguments {
+            ArgumentKind::Files => return self.complete_files(word, false, candidates, colors),
+            ArgumentKind::Directories => {
+                return self.complete_files(word, true, candidates, colors);
+            }
+            ArgumentKind::Command | ArgumentKind::Commands => {
+                return self.complete_commands(word, candidates, colors);
+            }
+            ArgumentKind::Builtins => return self.complete_builtins(word, candidates),
+            ArgumentKind::VisitedDirectories => {
+                let visited = data_file_path()
+                    .and_then(|path| read_visited(&path).ok())
+                    .unwrap_or_default();
+                for (_, directory) in matching_directories(&visited, &[word]) {
+                    candidates.push(Pair {
+                        replacement: format!("{} ", directory),
+                        display: directory,
+                    });
+                }
+                return;
+            }
+            ArgumentKind::Variables => &self.variables,
+            ArgumentKind::Aliases => &self.aliases,
+            ArgumentKind::Jobs | ArgumentKind::Processes => {
+                for (spec, description) in &self.jobs {
+                    if self.matches(word, spec) {
+                        candidates.push(Pair {
+                            display: format!("{:<7} {}", spec, description),
+                            replacement: format!("{} ", spec),
+                        });
+                    }
+                }
+                if arguments == ArgumentKind::Processes {
+                    for (pid, description) in own_processes() {
+                        if self.matches(word, &pid) {
+                            candidates.push(Pair {
+                                // Right-aligned so that they sort numerically.
+                                display: format!("{:>7} {}", pid, description),
+                                replacement: format!("{} ", pid),
+                            });
+                        }
+                    }
+                }
+                return;
+            }
+        };
+        for name in names.iter().filter(|name| self.matches(word, name)) {
+            candidates.push(Pair {
+                display: name.clone(),
+                replacement: format!("{} ", name),
+            });
+        }
+    }
+}
+
+impl Completer for ShellCompleter {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Result<(usize, Vec<Pair>)> {
+        let mut candidates = Vec::new();
+        let mut colors = self.candidate_colors.borrow_mut();
+        colors.clear();
+
+        let (start, quote, word) = completion_word(&line[..pos]);
+        if quote != Some('\'')
+            && let Some(completion) = self.complete_variable(&line[start..pos], start)
+        {
+            return Ok(completion);
+        }
+        let word = word.as_str();
+
+        let before_word = &line[..start];
+        // The words of the command being completed so far.
+        let command_words: Vec<&str> = before_word
+            .rsplit(['|', ';', '&', '(', '`'])
+            .next()
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect();
+        let command = command_words.first().copied();
+
+        if at_command_position(before_word) {
+            self.complete_commands(word, &mut candidates, &mut colors);
+        } else if after_redirection(before_word) {
+            self.complete_files(word, false, &mut candidates, &mut colors);
+        } else if let Some((name, spec)) =
+            command.and_then(|name| self.completion_specs.get_key_value(name))
+        {
+            let previous = command_words.last().copied().unwrap_or_default();
+            if spec.directories || spec.files {
+                self.complete_files(word, !spec.files, &mut candidates, &mut colors);
+            }
+            if spec.commands {
+                self.complete_commands(word, &mut candidates, &mut colors);
+            }
+            let mut words: Vec<String> = spec
+                .words
+                .iter()
+                .flat_map(|words| words.split_whitespace())
+                .map(str::to_string)
+                .collect();
+            if let Some(generator) = &spec.command {
+                words.extend(run_completion_command(
+                    generator, name, word, previous, line, pos,
+                ));
+            }
+            for generated in words {
+                if self.matches(word, &generated)
+                    && !candidates.iter().any(|c| c.display == generated)
+                {
+                    candidates.push(Pair {
+                        replacement: format!("{} ", generated),
+                        display: generated,
+                    });
+                }
+            }
+        } else if let Some(builtin) = command.and_then(find_builtin)
+            && !word.starts_with('-')
+        {
+            // Past the command name, a command's own arguments are files.
+            let mut arguments = builtin.arguments();
+            if arguments == ArgumentKind::Command
+                && command_words[1..].iter().any(|word| !word.starts_with('-'))
+            {
+                arguments = ArgumentKind::Files;
+            }
+            self.complete_arguments(arguments, word, &mut candidates, &mut colors);
+        } else if let Some(name @ ("ssh" | "scp" | "rsync")) = command
+            && !word.starts_with('-')
+            && !word.contains(':')
+        {
+            // Hosts, after any `user@`, and for copies local files too.
+            let host = word.rsplit('@').next().unwrap_or_default();
+            let user = &word[..word.len() - host.len()];
+            let suffix = if name == "ssh" { ' ' } else { ':' };
+            for known in ssh_hosts()
+                .into_iter()
+                .filter(|known| self.matches(host, known))
+            {
+                candidates.push(Pair {
+                    replacement: format!("{}{}{}", user, known, suffix),
+                    display: known,
+                });
+            }
+            if name != "ssh" && user.is_empty() {
+                self.complete_files(word, false, &mut candidates, &mut colors);
+            }
+        } else {
+            self.complete_files(word, false, &mut candidates, &mut colors);
+        }
+
+        for candidate in &mut candidates {
+            candidate.replacement = quote_completion(&candidate.replacement, quote);
+        }
+        Ok((start, self.ranked(word, candidates)))
+    }
+}
+
+/// Longest command line shown when completing process IDs.
+const PROCESS_DESCRIPTION_WIDTH: usize = 60;
+
+/// The IDs of the processes of the shell's user, other than the shell itself,
+/// with their command lines.
+fn own_processes() -> Vec<(String, String)> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let shell = process::id().to_string();
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let pid = entry.file_name().into_string().ok()?;
+            if !pid.bytes().all(|b| b.is_ascii_digit()) || pid == shell {
+                return None;
+            }
+            if entry.metadata().ok()?.uid() != geteuid().as_raw() {
+                return None;
+            }
+            let cmdline = fs::read(entry.path().join("cmdline")).ok()?;
+            let mut description = String::from_utf8_lossy(&cmdline)
+                .replace('\0', " ")
+                .trim_end()
+                .to_string();
+            if description.is_empty() {
+                // Kernel threads and zombies have no command line.
+                description = format!(
+                    "[{}]",
+                    fs::read_to_string(entry.path().join("comm"))
+                        .ok()?
+                        .trim_end()
+                );
+            }
+            if let Some((cut, _)) = description.char_indices().nth(PROCESS_DESCRIPTION_WIDTH) {
+                description.truncate(cut);
+            }
+            Some((pid, description))
+        })
+        .collect()
+}
+
+/// The host names in `~/.ssh/config` and `~/.ssh/known_hosts`, leaving out
+/// patterns and hashed entries.
+fn ssh_hosts() -> Vec<String> {
+    let Some(home) = env::var_os("HOME") else {
+        return Vec::new();
+    };
+    let ssh_dir = PathBuf::from(home).join(".ssh");
+    let mut hosts = Vec::new();
+    for line in fs::read_to_string(ssh_dir.join("config"))
+        .unwrap_or_default()
+        .lines()
+    {
+        let mut fields = line.split_whitespace();
+        if fields
+            .next()
+            .is_some_and(|keyword| keyword.eq_ignore_ascii_case("host"))
+        {
+            hosts.extend(
+                fields
+                    .filter(|host| !host.contains(['*', '?', '!']))
+                    .map(str::to_string),
+            );
+        }
+    }
+    for line in fs::read_to_string(ssh_dir.join("known_hosts"))
+        .unwrap_or_default()
+        .lines()
+    {
+        let mut fields = line.split_whitespace();
+        // Lines may start with a marker such as `@cert-authority`.
+        let names = match fields.next() {
+            Some(marker) if marker.starts_with('@') => fields.next(),
+            names => names,
+        };
+        for name in names.into_iter().flat_map(|names| names.split(',')) {
+            if name.starts_with(['#', '|']) {
+                break;
+            }
+            // Hosts on other ports are written `[host]:port`.
+            let host = name
+                .strip_prefix('[')
+                .and_then(|name| name.split_once("]:"))
+                .map_or(name, |(host, _)| host);
+            hosts.push(host.to_string());
+        }
+    }
+    hosts.sort();
+    hosts.dedup();
+    hosts
+}
+
+/// How well `candidate` matches the typed `word`, higher being better, or
+/// `None` if it doesn't. Without `fuzzy` only prefixes match. With it, the
+/// letters of `word` may also appear in order anywhere in `candidate`, or with
+/// two neighbouring letters swapped, as in `gti` for `git`.
+fn match_score(word: &str, candidate: &str, fuzzy: bool) -> Option<i64> {
+    if candidate.starts_with(word) {
+        return Some(i64::MAX);
+    }
+    if !fuzzy {
+        return None;
+    }
+    let letters: Vec<char> = word.chars().collect();
+    let swapped = (1..letters.len())
+        .filter_map(|i| {
+            let mut letters = letters.clone();
+            letters.swap(i - 1, i);
+            subsequence_score(&letters, candidate)
+        })
+        .max();
+    subsequence_score(&letters, candidate).max(swapped.map(|score| score - 15))
+}
+
+/// Scores `letters` found in order in `candidate`, favouring short candidates
+/// and runs of adjacent letters near the start.
+fn subsequence_score(letters: &[char], candidate: &str) -> Option<i64> {
+    let mut chars = candidate.chars().enumerate();
+    let mut score = -(candidate.chars().count() as i64);
+    let mut last = None;
+    for &letter in letters {
+        let (index, _) = chars.find(|&(_, ch)| ch == letter)?;
+        score += match last {
+            Some(last) if index == last + 1 => 10,
+            Some(last) => -((index - last) as i64),
+            None => -(index as i64),
+        };
+        last = Some(index);
+    }
+    Some(score)
+}
+
+/// Finds the word being completed at the end of `line`. Returns where it
+/// starts, the quote character it is left open in, if any, and its text with
+/// quotes and escapes removed. Words end at whitespace and operators, and a
+/// command substitution starts a new one even inside double quotes.
+fn completion_word(line: &str) -> (usize, Option<char>, String) {
+    let (mut start, mut quote, mut word) = (0, None, String::new());
+    let mut chars = line.char_indices();
+    while let Some((i, ch)) = chars.next() {
+        match (quote, ch) {
+            (Some('"'), '(' | '`') if ch == '`' || word.ends_with('$') => {
+                start = i + 1;
+                quote = None;
+                word.clear();
+            }
+            (Some(open), _) if ch == open => quote = None,
+            (Some('"'), '\\') => match chars.next() {
+                Some((_, next @ ('$' | '`' | '"' | '\\'))) => word.push(next),
+                Some((_, next)) => word.extend(['\\', next]),
+                None => {}
+            },
+            (Some(_), _) => word.push(ch),
+            (None, '\'' | '"') => quote = Some(ch),
+            (None, '\\') => word.extend(chars.next().map(|(_, next)| next)),
+            (None, _) if ch.is_whitespace() || "|&;<>()`".contains(ch) => {
+                start = i + ch.len_utf8();
+                word.clear();
+            }
+            (None, _) => word.push(ch),
+        }
+    }
+    (start, quote, word)
+}
+
+/// Whether the word after `before` is the target of a redirection.
+fn after_redirection(before: &str) -> bool {
+    let before = before.trim_end();
+    before.ends_with(['<', '>'])
+        || [">&", "<&", ">|"]
+            .iter()
+            .any(|operator| before.ends_with(operator))
+}
+
+/// Whether the word after `before` names a command: it starts the line or
+/// follows an operator, the opening of a subshell or command substitution, or
+/// a reserved word that precedes a command.
+fn at_command_position(before: &str) -> bool {
+    let before = before.trim_end();
+    if after_redirection(before) {
+        return false;
+    }
+    let last_word = before
+        .rsplit(char::is_whitespace)
+        .next()
+        .unwrap_or_default();
+    before.is_empty()
+        || before.ends_with(['|', ';', '&', '(', '`'])
+        || matches!(last_word, "if" | "then" | "elif" | "else" | "{" | "!")
+}
+
+/// Quotes a completion so that it reads back as the literal text, continuing
+/// the quote the word was left open in. A trailing space, added once a word is
+/// complete, closes that quote first.
+fn quote_completion(replacement: &str, quote: Option<char>) -> String {
+    let (text, finished) = match replacement.strip_suffix(' ') {
+        Some(text) => (text, true),
+        None => (replacement, false),
+    };
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.extend(quote);
+    for ch in text.chars() {
+        match (quote, ch) {
+            (Some('\''), '\'') => quoted.push_str("'\\''"),
+            (Some('"'), '"' | '\\' | '$' | '`') => quoted.extend(['\\', ch]),
+            (None, _) if ch.is_whitespace() || "\\'\"$`|&;<>()*?[]{}#~!".contains(ch) => {
+                quoted.extend(['\\', ch])
+            }
+            _ => quoted.push(ch),
+        }
+    }
+    if finished {
+        quoted.extend(quote);
+        quoted.push(' ');
+    }
+    quoted
+}
+
+impl Hinter for ShellCompleter {
+    type Hint = String;
+
+    fn hint(&self, _line: &str, _pos: usize, _ctx: &Context<'_>) -> Option<String> {
+        None
+    }
+}
+
+impl Highlighter for ShellCompleter {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        Cow::Owned(highlight_command_line(line, &self.path_var))
+    }
+
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(
+        &'s self,
+        prompt: &'p str,
+        _default: bool,
+    ) -> Cow<'b, str> {
+        match prompt.strip_prefix(VI_INSERT_INDICATOR) {
+            Some(rest) if self.vi_command_mode.load(Ordering::Relaxed) => {
+                Cow::Owned(format!("{}{}", VI_COMMAND_INDICATOR, rest))
+            }
+            _ => Cow::Borrowed(prompt),
+        }
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, _kind: CmdKind) -> bool {
+        true
+    }
+
+    fn highlight_candidate<'c>(
+        &self,
+        candidate: &'c str,
+        _completion: CompletionType,
+    ) -> Cow<'c, str> {
+        match self.candidate_colors.borrow().get(candidate) {
+            Some(color) => Cow::Owned(format!("\x1b[{}m{}{}", color, candidate, RESET_COLOR)),
+            None => Cow::Borrowed(candidate),
+        }
+    }
+}
+
+/// Decides whether the command being read is complete: whether quotes,
+/// `$(`, compound commands or here-documents are left open, or the line ends
+/// in `\`, `|`, `&&` or `||`. An incomplete command that already spans
+/// several lines in the editor, as when a block is pasted or recalled from
+/// history, is kept there: Enter adds a newline to it, so that the block is
+/// read as one command. A single incomplete line is accepted and marked
+/// `incomplete`, for `read_command` to continue at `PS2`.
+impl Validator for ShellCompleter {
+    fn validate(&self, ctx: &mut ValidationContext) -> Result<ValidationResult> {
+        let input = ctx.input();
+        // A key bound to text accepts the line with the text added instead.
+        let bound = self.macro_line.lock().ok().and_then(|line| line.clone());
+        let line = bound.as_deref().unwrap_or(input);
+        let previous = self
+            .previous_lines
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let incomplete = if previous.is_empty() {
+            incomplete_input(line)
+        } else {
+            incomplete_input(&format!("{}\n{}", previous, line))
+        };
+        if incomplete && bound.is_none() && input.contains('\n') {
+            return Ok(ValidationResult::Incomplete);
+        }
+        self.incomplete.store(incomplete, Ordering::Relaxed);
+        Ok(ValidationResult::Valid(None))
+    }
+}
+
+impl Helper for ShellCompleter {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_word_being_completed() {
+        assert_eq!(completion_word("ls Doc"), (3, None, "Doc".to_string()));
+        assert_eq!(completion_word("cat a|gr"), (6, None, "gr".to_string()));
+        assert_eq!(
+            completion_word("cd My\\ Do"),
+            (3, None, "My Do".to_string())
+        );
+        assert_eq!(
+            completion_word("cd 'My Do"),
+            (3, Some('\''), "My Do".to_string())
+        );
+        assert_eq!(
+            completion_word("cd \"My\\$Do"),
+            (3, Some('"'), "My$Do".to_string())
+        );
+        assert_eq!(
+            completion_word("cd \"a\"'b'c"),
+            (3, None, "abc".to_string())
+        );
+        assert_eq!(
+            completion_word("echo \"$(ls sr"),
+            (11, None, "sr".to_string())
+        );
+    }
+
+    #[test]
+    fn quotes_completions_to_read_back_literally() {
+        assert_eq!(quote_completion("My Documents/", None), "My\\ Documents/");
+        assert_eq!(quote_completion("a&b$c ", None), "a\\&b\\$c ");
+        assert_eq!(
+            quote_completion("My Documents/", Some('\'')),
+            "'My Documents/"
+        );
+        assert_eq!(quote_completion("it's ", Some('\'')), "'it'\\''s' ");
+        assert_eq!(
+            quote_completion("say \"$x\" ", Some('"')),
+            "\"say \\\"\\$x\\\"\" "
+        );
+    }
+}
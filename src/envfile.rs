@@ -0,0 +1,226 @@
+//! Per-directory environments. On entering a directory at the prompt, the
+//! nearest `.shellenv` file in it or above it is sourced, if it was allowed
+//! with `allowenv`, and the variables it exported are put back on leaving.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::builtins::{BuiltinIo, working_directory};
+use crate::error::describe_io_error;
+use crate::exec::Shell;
+use crate::variables::Variables;
+
+const ENV_FILE_NAME: &str = ".shellenv";
+
+/// The file listing the allowed environment files: `$SHELLENV_ALLOWED` if
+/// set, otherwise `~/.shellenv_allowed`.
+fn allow_list_path(vars: &Variables) -> Option<PathBuf> {
+    match vars.get("SHELLENV_ALLOWED") {
+        Some(path) if !path.is_empty() => Some(PathBuf::from(path)),
+        _ => vars
+            .get("HOME")
+            .map(|home| PathBuf::from(home).join(".shellenv_allowed")),
+    }
+}
+
+/// The FNV-1a hash of a file's contents, kept in the allow list so that a
+/// file changed after it was allowed has to be allowed again.
+fn content_hash(contents: &[u8]) -> u64 {
+    contents.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// The environment file that applies in `directory`: the nearest
+/// `.shellenv` in it or a directory above it, with symlinks resolved.
+fn find_env_file(directory: &Path) -> Option<PathBuf> {
+    let directory = fs::canonicalize(directory).ok()?;
+    directory
+        .ancestors()
+        .map(|dir| dir.join(ENV_FILE_NAME))
+        .find(|file| file.is_file())
+}
+
+/// The allowed files with the hashes of their allowed contents, one
+/// `hash path` line each. A missing list allows nothing.
+fn read_allow_list(path: &Path) -> Vec<(u64, PathBuf)> {
+    let contents = fs::read_to_string(path).unwrap_or_default();
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (hash, file) = line.split_once(' ')?;
+            Some((u64::from_str_radix(hash, 16).ok()?, PathBuf::from(file)))
+        })
+        .collect()
+}
+
+/// Allows `file` with its current contents, or with `allowed` false,
+/// removes it from the allow list.
+fn set_allowed(vars: &Variables, file: &Path, allowed: bool) -> io::Result<()> {
+    let Some(path) = allow_list_path(vars) else {
+        return Err(io::Error::other("HOME not set"));
+    };
+    let mut list = read_allow_list(&path);
+    list.retain(|(_, listed)| listed != file);
+    if allowed {
+        list.push((content_hash(&fs::read(file)?), file.to_path_buf()));
+    }
+    let contents: String = list
+        .iter()
+        .map(|(hash, file)| format!("{:016x} {}\n", hash, file.display()))
+        .collect();
+    fs::write(path, contents)
+}
+
+/// An environment file that was sourced, and the variables it exported with
+/// what they were before.
+#[derive(Debug)]
+pub(crate) struct LoadedEnv {
+    file: PathBuf,
+    hash: u64,
+    saved: Vec<(String, Option<(String, bool)>)>,
+}
+
+impl Shell {
+    /// Loads the environment file that applies in the working directory,
+    /// first unloading the one loaded before if it is a different file or has
+    /// changed. A file that is not allowed is reported and not loaded.
+    pub(crate) fn update_env_file(&mut self) {
+        let cwd = working_directory(&self.vars);
+        let found =
+            find_env_file(Path::new(&cwd)).and_then(|file| Some((fs::read(&file).ok()?, file)));
+        let hash = found.as_ref().map(|(contents, _)| content_hash(contents));
+        if let Some(loaded) = &self.loaded_env
+            && found.as_ref().map(|(_, file)| file) == Some(&loaded.file)
+            && hash == Some(loaded.hash)
+        {
+            return;
+        }
+        self.unload_env_file();
+        let (Some((contents, file)), Some(hash)) = (found, hash) else {
+            return;
+        };
+        let allowed = allow_list_path(&self.vars)
+            .map(|path| read_allow_list(&path))
+            .unwrap_or_default();
+        if !allowed.contains(&(hash, file.clone())) {
+            eprintln!(
+                "shellenv: {}: not allowed, run `allowenv` to load it",
+                file.display()
+            );
+            return;
+        }
+        eprintln!("shellenv: loading {}", file.display());
+        let before: HashMap<String, String> = self
+            .vars
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        let exported_before: HashMap<String, String> = self
+            .vars
+            .exported()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        let status = self.last_status;
+        self.return_depth += 1;
+        self.run_lines(
+            &String::from_utf8_lossy(&contents),
+            Some((&file.display().to_string(), 1)),
+        );
+        self.return_depth -= 1;
+        self.returning = false;
+        self.last_status = status;
+
+        let exported_after: HashMap<&String, &String> = self.vars.exported().collect();
+        let mut changed: Vec<&String> = exported_after
+            .iter()
+            .filter(|&(name, value)| exported_before.get(*name) != Some(*value))
+            .map(|(name, _)| *name)
+            .chain(
+                exported_before
+                    .keys()
+                    .filter(|name| !exported_after.contains_key(name)),
+            )
+            .collect();
+        changed.sort();
+        let saved = changed
+            .into_iter()
+            .map(|name| {
+                let previous = before
+                    .get(name)
+                    .map(|value| (value.clone(), exported_before.contains_key(name)));
+                (name.clone(), previous)
+            })
+            .collect();
+        self.loaded_env = Some(LoadedEnv { file, hash, saved });
+    }
+
+    /// Puts back the variables the loaded environment file exported.
+    pub(crate) fn unload_env_file(&mut self) {
+        let Some(loaded) = self.loaded_env.take() else {
+            return;
+        };
+        eprintln!("shellenv: unloading {}", loaded.file.display());
+        for (name, previous) in loaded.saved {
+            match previous {
+                Some((value, exported)) => {
+                    if let Err(error) = self.vars.set(&name, value) {
+                        eprintln!("shellenv: {}", error);
+                    }
+                    self.vars.set_exported(&name, exported);
+                }
+                None => self.vars.unset(&name),
+            }
+        }
+    }
+}
+
+/// Allows the environment file that applies in `dir`, by default the working
+/// directory, and loads it; with `-r`, revokes it instead.
+pub(crate) fn allowenv_builtin(
+    shell: &mut Shell,
+    args: &[&str],
+    io: &mut BuiltinIo,
+) -> io::Result<i32> {
+    let (revoke, args) = match args {
+        ["-r", rest @ ..] => (true, rest),
+        _ => (false, args),
+    };
+    let dir = match args {
+        [] => working_directory(&shell.vars),
+        [dir] if !dir.starts_with('-') => dir.to_string(),
+        _ => {
+            writeln!(io.stderr, "allowenv: usage: allowenv [-r] [dir]")?;
+            return Ok(2);
+        }
+    };
+    let Some(file) = find_env_file(Path::new(&dir)) else {
+        writeln!(
+            io.stderr,
+            "allowenv: {}: no {} file found",
+            dir, ENV_FILE_NAME
+        )?;
+        return Ok(1);
+    };
+    if let Err(e) = set_allowed(&shell.vars, &file, !revoke) {
+        writeln!(
+            io.stderr,
+            "allowenv: {}: {}",
+            file.display(),
+            describe_io_error(&e)
+        )?;
+        return Ok(1);
+    }
+    if !revoke {
+        shell.update_env_file();
+    } else if shell
+        .loaded_env
+        .as_ref()
+        .is_some_and(|loaded| loaded.file == file)
+    {
+        shell.unload_env_file();
+    }
+    Ok(0)
+}
@@ -0,0 +1,74 @@
+//! Errors that stop a command from running, and the status each leaves.
+
+use std::io;
+
+use nix::errno::Errno;
+use thiserror::Error;
+
+use crate::ExitStatus;
+
+/// Formats an I/O error the way shells do, without Rust's `(os error N)` suffix.
+pub(crate) fn describe_io_error(e: &io::Error) -> String {
+    let message = e.to_string();
+    match message.find(" (os error") {
+        Some(end) => message[..end].to_string(),
+        None => message,
+    }
+}
+
+/// Why the shell could not run a command. Each is reported once, by
+/// `Shell::report_error`, and the command's status is set from it.
+#[derive(Debug, Error)]
+pub(crate) enum ShellError {
+    #[error("{0}: command not found")]
+    CommandNotFound(String),
+    #[error("{0}: No such file or directory")]
+    NoSuchFile(String),
+    #[error("{0}: Is a directory")]
+    IsDirectory(String),
+    #[error("{0}: Permission denied")]
+    PermissionDenied(String),
+    /// Starting the program failed after it was found.
+    #[error("{command}: {}", describe_io_error(.source))]
+    Exec { command: String, source: io::Error },
+    /// A redirection's file, or a descriptor it copies, could not be opened.
+    #[error("{target}: {}", describe_io_error(.source))]
+    Redirect { target: String, source: io::Error },
+    #[error("here-document: {}", describe_io_error(.0))]
+    HereDoc(io::Error),
+    #[error("pipe: {}", describe_io_error(.0))]
+    Pipe(io::Error),
+    #[error("fork: {0}")]
+    Fork(Errno),
+    /// A script given to the shell could not be read.
+    #[error("{path}: {}", describe_io_error(.source))]
+    Script { path: String, source: io::Error },
+    /// A malformed `[[ ]]` expression.
+    #[error("{0}")]
+    Condition(String),
+    /// A malformed or failing `(( ))` expression.
+    #[error("{expr}: {message}")]
+    Arithmetic { expr: String, message: String },
+    /// An assignment to a variable made read-only.
+    #[error("{0}: readonly variable")]
+    Readonly(String),
+    /// Calling a function would nest function calls too deeply.
+    #[error("{0}: maximum function nesting level exceeded")]
+    FunctionDepth(String),
+}
+
+impl ShellError {
+    /// The status of a command that failed this way.
+    pub(crate) fn status(&self) -> ExitStatus {
+        match self {
+            ShellError::CommandNotFound(_)
+            | ShellError::NoSuchFile(_)
+            | ShellError::Script { .. } => 127,
+            ShellError::IsDirectory(_) | ShellError::PermissionDenied(_) => 126,
+            ShellError::Exec { source, .. } if source.kind() == io::ErrorKind::NotFound => 127,
+            ShellError::Exec { .. } => 126,
+            ShellError::Condition(_) => 2,
+            _ => 1,
+        }
+    }
+}
@@ -0,0 +1,1383 @@
+//! The `Shell` state and the execution of parsed commands.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{self, PipeReader, PipeWriter, Write};
+use std::iter;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Stdio};
+use std::rc::Rc;
+use std::sync::atomic::Ordering;
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+
+use nix::libc;
+use nix::sys::signal::{SigHandler, Signal, signal};
+use nix::unistd::{ForkResult, Pid, fork, setpgid};
+
+use crate::ExitStatus;
+use crate::arithmetic::eval_arithmetic;
+use crate::builtins::{
+    BuiltinIo, ShellOptions, is_builtin, is_current_directory, read_line_bytes,
+    strip_command_prefix,
+};
+use crate::completion::CompletionSpec;
+use crate::envfile::LoadedEnv;
+use crate::error::{ShellError, describe_io_error};
+use crate::expansion::{
+    Regex, expand_command, expand_redirection, expand_word, glob_match, is_match_variable,
+    test_file,
+};
+use crate::jobs::{
+    JOB_CONTROL_SIGNALS, Job, JobState, JobTable, JobWait, PENDING_TRAPS, give_terminal_to,
+    prepare_child, wait_for_pids, wait_in_foreground,
+};
+use crate::parser::{
+    AndOrList, Command, CompoundCommand, ConditionExpr, Connector, Function, Pipeline,
+    RedirectKind, Redirection, SimpleCommand, SyntaxError, UNEXPECTED_EOF, expand_aliases,
+    incomplete_input, lex_command_line, parse_command_list,
+};
+use crate::variables::{Variables, quote_if_needed};
+
+/// Whether `path` is a regular file with an execute bit set.
+pub(crate) fn is_executable(path: &std::path::Path) -> bool {
+    fs::metadata(path).is_ok_and(|attr| attr.is_file() && attr.permissions().mode() & 0o111 != 0)
+}
+
+/// Every executable named `program_name` in the directories of `path_var`, in
+/// `PATH` order.
+pub(crate) fn executables_in_path<'a>(
+    program_name: &'a str,
+    path_var: &'a str,
+) -> impl Iterator<Item = PathBuf> + 'a {
+    env::split_paths(path_var)
+        .map(move |path| path.join(program_name.trim()))
+        .filter(|program_path| is_executable(program_path))
+}
+
+/// Finds the first executable named `program_name` in `PATH`.
+pub(crate) fn find_executable_in_path(program_name: &str, path_var: &str) -> Option<PathBuf> {
+    executables_in_path(program_name, path_var).next()
+}
+
+/// Finds `program_name` like `find_executable_in_path`, but trying the command
+/// cache first, for highlighting, which looks the name up on every keystroke.
+pub(crate) fn find_cached_command(program_name: &str, path_var: &str) -> Option<PathBuf> {
+    with_cached_commands(path_var, |commands| {
+        if let Some(path) = commands.get(program_name)
+            && is_executable(path)
+        {
+            return Some(path.clone());
+        }
+        // Installed or removed since the cache was built.
+        let found = executables_in_path(program_name, path_var).next();
+        match &found {
+            Some(path) => commands.insert(program_name.to_string(), path.clone()),
+            None => commands.remove(program_name),
+        };
+        found
+    })
+}
+
+/// The executables in the directories of `PATH` by name, scanned once per
+/// value of `PATH` and again after `rehash`. Only completion, highlighting and
+/// spelling correction need every name; running a command looks it up with
+/// `find_executable_in_path` and the `hash` table instead.
+struct CommandCache {
+    /// The `PATH` the cache was built from, or `None` if it needs rebuilding.
+    path_var: Option<String>,
+    commands: BTreeMap<String, PathBuf>,
+}
+
+static COMMAND_CACHE: Mutex<CommandCache> = Mutex::new(CommandCache {
+    path_var: None,
+    commands: BTreeMap::new(),
+});
+
+/// Runs `f` on the cached executables of `path_var`, rebuilding the cache
+/// first if it was made for a different `PATH`.
+pub(crate) fn with_cached_commands<T>(
+    path_var: &str,
+    f: impl FnOnce(&mut BTreeMap<String, PathBuf>) -> T,
+) -> T {
+    let mut cache = COMMAND_CACHE.lock().unwrap_or_else(PoisonError::into_inner);
+    if cache.path_var.as_deref() != Some(path_var) {
+        let mut commands = BTreeMap::new();
+        for dir in env::split_paths(path_var) {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                if let Ok(name) = entry.file_name().into_string()
+                    && !commands.contains_key(&name)
+                    && is_executable(&entry.path())
+                {
+                    commands.insert(name, entry.path());
+                }
+            }
+        }
+        *cache = CommandCache {
+            path_var: Some(path_var.to_string()),
+            commands,
+        };
+    }
+    f(&mut cache.commands)
+}
+
+/// The commands the shell has looked up in `PATH`, with where they were found
+/// and how many times they were run, as `hash` lists them. The table is
+/// emptied whenever `PATH` changes.
+#[derive(Debug, Default)]
+pub(crate) struct CommandHash {
+    /// The `PATH` the entries were found in.
+    path_var: String,
+    pub(crate) entries: BTreeMap<String, (PathBuf, usize)>,
+}
+
+impl CommandHash {
+    /// Forgets the entries if they were found with a different `PATH`.
+    pub(crate) fn check_path(&mut self, path_var: &str) {
+        if self.path_var != path_var {
+            self.entries.clear();
+            self.path_var = path_var.to_string();
+        }
+    }
+}
+
+/// Makes the next lookup scan `PATH` again.
+pub(crate) fn forget_cached_commands() {
+    COMMAND_CACHE
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .path_var = None;
+}
+
+/// A descriptor change to make in the child before it executes.
+#[derive(Debug)]
+pub(crate) enum FdAction {
+    Open(RawFd, File),
+    Duplicate { source: RawFd, target: RawFd },
+    Close(RawFd),
+}
+
+impl FdAction {
+    /// The descriptor the action changes.
+    fn target(&self) -> RawFd {
+        match self {
+            FdAction::Open(fd, _) | FdAction::Close(fd) => *fd,
+            FdAction::Duplicate { target, .. } => *target,
+        }
+    }
+}
+
+/// Opens the files named by `redirections`, in order, and returns the descriptor
+/// changes they describe. Fails on the first file that could not be opened.
+pub(crate) fn open_redirections(
+    redirections: &[Redirection],
+) -> std::result::Result<Vec<FdAction>, ShellError> {
+    let mut actions = Vec::new();
+    for redirection in redirections {
+        let opened = match redirection.kind {
+            RedirectKind::Read => File::open(&redirection.target),
+            RedirectKind::Write { append } | RedirectKind::WriteBoth { append } => {
+                OpenOptions::new()
+                    .write(true)
+                    .append(append)
+                    .truncate(!append)
+                    .create(true)
+                    .open(&redirection.target)
+            }
+            RedirectKind::Duplicate(source) => {
+                actions.push(FdAction::Duplicate {
+                    source,
+                    target: redirection.fd,
+                });
+                continue;
+            }
+            RedirectKind::Close => {
+                actions.push(FdAction::Close(redirection.fd));
+                continue;
+            }
+            RedirectKind::HereDoc { .. } | RedirectKind::HereString => {
+                let reader =
+                    here_doc_reader(redirection.target.clone()).map_err(ShellError::HereDoc)?;
+                actions.push(FdAction::Open(redirection.fd, reader));
+                continue;
+            }
+        };
+        let file = opened.map_err(|source| ShellError::Redirect {
+            target: redirection.target.clone(),
+            source,
+        })?;
+        actions.push(FdAction::Open(redirection.fd, file));
+        if matches!(redirection.kind, RedirectKind::WriteBoth { .. }) {
+            actions.push(FdAction::Duplicate {
+                source: 1,
+                target: 2,
+            });
+        }
+    }
+    Ok(actions)
+}
+
+/// A pipe that reads back `text`. Text too large for the pipe buffer is written
+/// from a separate thread so that the reader can drain it.
+fn here_doc_reader(text: String) -> io::Result<File> {
+    const PIPE_CAPACITY: usize = 65536;
+    let (reader, mut writer) = io::pipe()?;
+    if text.len() < PIPE_CAPACITY {
+        writer.write_all(text.as_bytes())?;
+    } else {
+        thread::spawn(move || writer.write_all(text.as_bytes()));
+    }
+    Ok(File::from(OwnedFd::from(reader)))
+}
+
+/// Applies descriptor changes in the child just before it executes, after the
+/// pipeline's own stdin/stdout have been set up, so redirections take precedence
+/// over pipes.
+pub(crate) fn apply_fd_actions(cmd: &mut process::Command, actions: Vec<FdAction>) {
+    if actions.is_empty() {
+        return;
+    }
+    unsafe {
+        cmd.pre_exec(move || {
+            for action in &actions {
+                let (source, target) = match action {
+                    FdAction::Open(fd, file) => (file.as_raw_fd(), *fd),
+                    FdAction::Duplicate { source, target } => (*source, *target),
+                    FdAction::Close(fd) => {
+                        libc::close(*fd);
+                        continue;
+                    }
+                };
+                if source == target {
+                    // dup2 is a no-op here, but the descriptor must survive exec.
+                    if libc::fcntl(target, libc::F_SETFD, 0) == -1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                } else if libc::dup2(source, target) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Applies descriptor changes to the shell itself, for a compound command run
+/// without forking. Returns each changed descriptor with a saved copy of what it
+/// referred to before, if it was open, for `restore_shell_fds`.
+pub(crate) fn redirect_shell(actions: Vec<FdAction>) -> Vec<(RawFd, Option<OwnedFd>)> {
+    let _ = io::stdout().flush();
+    let mut saved = Vec::new();
+    for action in actions {
+        let target = action.target();
+        if let FdAction::Open(_, file) = &action
+            && file.as_raw_fd() == target
+        {
+            // The descriptor was closed, so the file was opened as it. It is
+            // kept open, and past `exec`.
+            saved.push((target, None));
+            unsafe {
+                libc::fcntl(target, libc::F_SETFD, 0);
+            }
+            mem::forget(action);
+            continue;
+        }
+        let copy = unsafe { libc::fcntl(target, libc::F_DUPFD_CLOEXEC, 10) };
+        saved.push((
+            target,
+            (copy >= 0).then(|| unsafe { OwnedFd::from_raw_fd(copy) }),
+        ));
+        unsafe {
+            match action {
+                FdAction::Open(_, file) => libc::dup2(file.as_raw_fd(), target),
+                FdAction::Duplicate { source, .. } if source != target => {
+                    libc::dup2(source, target)
+                }
+                FdAction::Duplicate { .. } => 0,
+                FdAction::Close(_) => libc::close(target),
+            };
+        }
+    }
+    saved
+}
+
+/// Undoes `redirect_shell`, in reverse order.
+pub(crate) fn restore_shell_fds(saved: Vec<(RawFd, Option<OwnedFd>)>) {
+    let _ = io::stdout().flush();
+    for (target, copy) in saved.into_iter().rev() {
+        unsafe {
+            match copy {
+                Some(copy) => libc::dup2(copy.as_raw_fd(), target),
+                None => libc::close(target),
+            };
+        }
+    }
+}
+
+/// How deeply function calls may nest before the shell refuses another, rather
+/// than running out of stack.
+const MAX_FUNCTION_DEPTH: usize = 256;
+
+/// The function called with a command's name and arguments when the command
+/// is not found, which can suggest where to get it.
+const NOT_FOUND_HANDLER: &str = "command_not_found_handle";
+
+#[derive(Debug, Default)]
+pub struct Shell {
+    pub(crate) vars: Variables,
+    pub(crate) aliases: BTreeMap<String, String>,
+    pub(crate) functions: BTreeMap<String, Rc<Function>>,
+    pub(crate) jobs: JobTable,
+    /// The controlling terminal when job control is enabled. Foreground jobs get
+    /// their own process group and are handed the terminal while they run.
+    pub(crate) terminal: Option<RawFd>,
+    /// Exit status of the most recently executed command, exposed as `$?`.
+    pub(crate) last_status: i32,
+    /// Set by the `exit` builtin to end the read loop.
+    pub(crate) exit_requested: bool,
+    /// Set when `exit` refused to leave because of stopped jobs, so that
+    /// a second `exit` straight after does.
+    pub(crate) stopped_jobs_warned: bool,
+    /// Set by the `return` builtin to end the innermost function or sourced file.
+    pub(crate) returning: bool,
+    /// How many functions and sourced files are running, which `return` may end.
+    pub(crate) return_depth: usize,
+    /// The file and line number being run by a script or `source`, used to
+    /// locate error messages.
+    location: Option<(String, usize)>,
+    /// The shell or script name, exposed as `$0`.
+    pub(crate) name: String,
+    /// The positional parameters `$1`, `$2`, ..., set from the script
+    /// arguments or with `set --`.
+    pub(crate) positional: Vec<String>,
+    /// The process id of the shell, exposed as `$$`. Subshells keep the value
+    /// of their parent.
+    pub(crate) pid: u32,
+    /// The process id of the most recent background job, exposed as `$!`.
+    pub(crate) last_background_pid: Option<Pid>,
+    /// Set when an expansion reports an error, such as division by zero in
+    /// `$((...))`, so that the command is not run.
+    pub(crate) failed_expansion: bool,
+    pub(crate) options: ShellOptions,
+    /// How many `if` conditions are being run, where `set -e` does not apply.
+    condition_depth: usize,
+    /// Whether commands are read from the terminal, so that errors like an
+    /// unset variable under `set -u` do not end the shell.
+    pub(crate) interactive: bool,
+    /// The action for each trapped signal number, with 0 for `EXIT`. An empty
+    /// action ignores the signal.
+    pub(crate) traps: BTreeMap<i32, String>,
+    /// The directory stack of `pushd` and `popd`, below the working directory
+    /// at its top.
+    pub(crate) dir_stack: Vec<String>,
+    /// Where the commands run so far were found in `PATH`.
+    pub(crate) hashed_commands: CommandHash,
+    /// Rules set by `complete` for completing the arguments of commands.
+    pub(crate) completion_specs: BTreeMap<String, CompletionSpec>,
+    /// The commands entered at the prompt, oldest first, mirroring the line
+    /// editor's history so that `history` can list and edit it.
+    pub(crate) history: Vec<String>,
+    /// Set when `history` changed the list, so that the line editor reloads it.
+    pub(crate) history_changed: bool,
+    /// Keys bound with `bind`, by key sequence as written, to the name of an
+    /// editor function or a quoted text to type.
+    pub(crate) key_bindings: BTreeMap<String, String>,
+    /// Set while `command_not_found_handle` runs, so that a command missing
+    /// inside it is reported rather than handled again.
+    handling_not_found: bool,
+    /// Spelling corrections the user chose to make by editing the line, as
+    /// the misspelt and corrected words. The rest of the line is not run.
+    pub(crate) corrections_to_edit: Vec<(String, String)>,
+    /// The `.shellenv` file loaded for the working directory.
+    pub(crate) loaded_env: Option<LoadedEnv>,
+}
+
+impl Shell {
+    pub fn new() -> Self {
+        let mut vars = Variables::from_env();
+        if !vars.get("PWD").is_some_and(is_current_directory)
+            && let Ok(cwd) = env::current_dir()
+        {
+            let _ = vars.set("PWD", cwd.display().to_string());
+        }
+        Shell {
+            vars,
+            name: "shell".to_string(),
+            pid: process::id(),
+            ..Shell::default()
+        }
+    }
+
+    /// Sets `$0`, the name the shell reports itself as in error messages.
+    pub fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    /// Replaces the positional parameters `$1`, `$2`, ...
+    pub fn set_positional(&mut self, params: Vec<String>) {
+        self.positional = params;
+    }
+
+    /// Finds `name` in `PATH`, going by the hash table while the remembered
+    /// file is still there, and counts the use.
+    fn find_executable(&mut self, name: &str) -> Option<PathBuf> {
+        let path_var = self.vars.get("PATH").unwrap_or_default();
+        self.hashed_commands.check_path(path_var);
+        if let Some((path, hits)) = self.hashed_commands.entries.get_mut(name)
+            && is_executable(path)
+        {
+            *hits += 1;
+            return Some(path.clone());
+        }
+        let path = find_executable_in_path(name, path_var)?;
+        self.hashed_commands
+            .entries
+            .insert(name.to_string(), (path.clone(), 1));
+        Some(path)
+    }
+
+    /// Finds the program to run for `name`: the file itself if the name contains
+    /// a `/`, otherwise an executable in `PATH`. Fails with 127 if nothing was
+    /// found and 126 if it cannot be executed.
+    pub(crate) fn resolve_command(
+        &mut self,
+        name: &str,
+    ) -> std::result::Result<PathBuf, ShellError> {
+        if !name.contains('/') {
+            return self
+                .find_executable(name)
+                .ok_or_else(|| ShellError::CommandNotFound(name.to_string()));
+        }
+        match fs::metadata(name) {
+            Err(_) => Err(ShellError::NoSuchFile(name.to_string())),
+            Ok(meta) if meta.is_dir() => Err(ShellError::IsDirectory(name.to_string())),
+            Ok(meta) if meta.permissions().mode() & 0o111 == 0 => {
+                Err(ShellError::PermissionDenied(name.to_string()))
+            }
+            Ok(_) => Ok(PathBuf::from(name)),
+        }
+    }
+
+    /// Runs a complete command line and returns its status.
+    pub fn run_line(&mut self, line: &str) -> ExitStatus {
+        let line = line.trim();
+        let tokens = expand_aliases(lex_command_line(line), &self.aliases, &mut Vec::new());
+        match parse_command_list(tokens) {
+            Ok(lists) => self.run_list(&lists),
+            Err(error) => {
+                self.report_syntax_error(line, &error);
+                self.last_status = 2;
+                2
+            }
+        }
+    }
+
+    /// Reports a syntax error in `line`, showing the line it is on with a
+    /// caret under the unexpected token.
+    fn report_syntax_error(&self, line: &str, error: &SyntaxError) {
+        if error.token == UNEXPECTED_EOF {
+            eprintln!(
+                "{}syntax error: unexpected end of file",
+                self.error_prefix()
+            );
+            return;
+        }
+        let offset = error.offset.unwrap_or(line.len());
+        let line_start = line[..offset].rfind('\n').map_or(0, |newline| newline + 1);
+        let prefix = match &self.location {
+            Some((name, first)) => format!(
+                "{}: line {}: ",
+                name,
+                first + line[..offset].matches('\n').count()
+            ),
+            None => String::new(),
+        };
+        eprintln!(
+            "{}syntax error near unexpected token `{}'",
+            prefix, error.token
+        );
+        let text = line[line_start..].lines().next().unwrap_or_default();
+        let column = line[line_start..offset].chars().count();
+        eprintln!("    {}", text);
+        eprintln!("    {}^", " ".repeat(column));
+    }
+
+    /// Runs the pipelines of `list` in order, skipping a pipeline after `&&` when
+    /// the previous one failed and after `||` when it succeeded. Returns the status
+    /// of the last pipeline that ran. Under `set -e`, a failure of the last
+    /// pipeline ends the shell unless it is part of a condition.
+    fn run_and_or(&mut self, list: &AndOrList) -> i32 {
+        let mut status = self.run_pipeline(&list.first, &list.text);
+        self.last_status = status;
+        let mut ran_last = true;
+        for (connector, pipeline) in &list.rest {
+            if self.interrupted() {
+                break;
+            }
+            ran_last = match connector {
+                Connector::And => status == 0,
+                Connector::Or => status != 0,
+            };
+            if ran_last {
+                status = self.run_pipeline(pipeline, &list.text);
+                self.last_status = status;
+            }
+        }
+        if status != 0 && ran_last && self.options.errexit && self.condition_depth == 0 {
+            self.exit_requested = true;
+        }
+        status
+    }
+
+    /// Starts `list` as a background job. A lone pipeline is spawned directly;
+    /// longer lists run in a forked copy of the shell.
+    fn run_in_background(&mut self, list: &AndOrList) -> i32 {
+        if list.rest.is_empty() {
+            let Some(stages) = self.expand_stages(&list.first) else {
+                return 1;
+            };
+            return self.spawn_pipeline(&stages, true, &list.text);
+        }
+
+        match self.fork_shell(None, None, None, true, |shell| shell.run_and_or(list)) {
+            Ok(child) => {
+                self.last_background_pid = Some(child);
+                let id = self.jobs.add(child, vec![child], list.text.clone());
+                println!("[{}] {}", id, child);
+                0
+            }
+            Err(e) => self.report_error(&ShellError::Fork(e)),
+        }
+    }
+
+    /// Whether `exit` or `return` is ending the commands being run, or the
+    /// line is being given back for editing a spelling correction.
+    fn interrupted(&self) -> bool {
+        self.exit_requested || self.returning || !self.corrections_to_edit.is_empty()
+    }
+
+    /// Runs each and-or list in turn, stopping early on `exit` or `return`, and
+    /// returns the status of the last one.
+    fn run_list(&mut self, lists: &[AndOrList]) -> i32 {
+        for list in lists {
+            self.last_status = if list.background {
+                self.run_in_background(list)
+            } else {
+                self.run_and_or(list)
+            };
+            self.run_pending_traps();
+            if self.interrupted() {
+                break;
+            }
+        }
+        self.last_status
+    }
+
+    /// Prints an expanded command for `set -x`: `PS4`, then the assignments and
+    /// words, quoted where needed.
+    fn trace_command(&mut self, command: &SimpleCommand) {
+        let ps4 = self.vars.get("PS4").unwrap_or("+ ").to_string();
+        let mut line = expand_word(&ps4, self).text;
+        let assignments = command
+            .assignments
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, quote_if_needed(value)));
+        let words = command.words.iter().map(|word| quote_if_needed(word));
+        line.push_str(&assignments.chain(words).collect::<Vec<_>>().join(" "));
+        eprintln!("{}", line);
+    }
+
+    /// Expands the simple commands of a pipeline; compound commands are expanded
+    /// as they run. Returns `None` if an expansion failed.
+    fn expand_stages(&mut self, pipeline: &Pipeline) -> Option<Vec<Command>> {
+        let stages = pipeline
+            .stages
+            .iter()
+            .map(|stage| match stage {
+                Command::Simple(command) => {
+                    let mut expanded = expand_command(command, self);
+                    strip_command_prefix(&mut expanded);
+                    if self.options.xtrace {
+                        self.trace_command(&expanded);
+                    }
+                    Command::Simple(expanded)
+                }
+                compound => compound.clone(),
+            })
+            .collect();
+        (!mem::take(&mut self.failed_expansion)).then_some(stages)
+    }
+
+    fn run_pipeline(&mut self, pipeline: &Pipeline, command_text: &str) -> i32 {
+        let Some(stages) = self.expand_stages(pipeline) else {
+            return 1;
+        };
+        if let [stage] = stages.as_slice()
+            && let Some(status) = self.run_in_shell(stage)
+        {
+            return status;
+        }
+        self.spawn_pipeline(&stages, false, command_text)
+    }
+
+    /// Runs a pipeline stage in the shell itself, unless it is a program or a
+    /// subshell, which return `None`.
+    pub(crate) fn run_in_shell(&mut self, stage: &Command) -> Option<i32> {
+        match stage {
+            Command::Simple(command) if command.words.is_empty() => {
+                Some(self.run_assignments(command))
+            }
+            Command::Simple(command) => match self.find_function(command) {
+                Some(function) => Some(self.call_function(&function, command)),
+                None => self
+                    .run_builtin(command)
+                    .or_else(|| self.auto_cd(command))
+                    .or_else(|| self.correct_command(command))
+                    .or_else(|| self.handle_not_found(command)),
+            },
+            Command::Compound(CompoundCommand::Subshell(_), _) => None,
+            Command::Compound(compound, redirections) => {
+                Some(self.run_compound(compound, redirections))
+            }
+            Command::Function(function) => {
+                self.functions
+                    .insert(function.name.clone(), function.clone());
+                Some(0)
+            }
+        }
+    }
+
+    /// The function a simple command calls, unless it was run through `command`.
+    fn find_function(&self, command: &SimpleCommand) -> Option<Rc<Function>> {
+        if command.skip_functions {
+            return None;
+        }
+        self.functions.get(command.words.first()?).cloned()
+    }
+
+    /// With `set -o autocd` in an interactive shell, changes to the directory a
+    /// command consisting of only its name names, unless a program of that
+    /// name is found in `PATH`.
+    fn auto_cd(&mut self, command: &SimpleCommand) -> Option<i32> {
+        let [name] = command.words.as_slice() else {
+            return None;
+        };
+        if !self.interactive
+            || !self.options.autocd
+            || command.skip_functions
+            || !Path::new(name).is_dir()
+        {
+            return None;
+        }
+        if !name.contains('/') && self.find_executable(name).is_some() {
+            return None;
+        }
+        self.run_builtin(&SimpleCommand {
+            words: vec!["cd".to_string(), name.clone()],
+            ..command.clone()
+        })
+    }
+
+    /// The `command_not_found_handle` function to call for a command whose
+    /// name is neither a builtin nor a program in `PATH`, if one is defined.
+    fn not_found_handler(&mut self, command: &SimpleCommand) -> Option<Rc<Function>> {
+        let name = command.words.first()?;
+        if self.handling_not_found {
+            return None;
+        }
+        let handler = self.functions.get(NOT_FOUND_HANDLER).cloned()?;
+        self.is_missing_command(name).then_some(handler)
+    }
+
+    /// Whether `name`, run without a `/`, is neither a builtin nor a program
+    /// in `PATH`.
+    pub(crate) fn is_missing_command(&mut self, name: &str) -> bool {
+        !name.contains('/') && !is_builtin(name) && self.find_executable(name).is_none()
+    }
+
+    /// Calls `command_not_found_handle` with the words of a command that was
+    /// not found, and returns its status as the command's.
+    fn handle_not_found(&mut self, command: &SimpleCommand) -> Option<i32> {
+        let handler = self.not_found_handler(command)?;
+        let call = SimpleCommand {
+            words: iter::once(NOT_FOUND_HANDLER.to_string())
+                .chain(command.words.iter().cloned())
+                .collect(),
+            ..command.clone()
+        };
+        self.handling_not_found = true;
+        let status = self.call_function(&handler, &call);
+        self.handling_not_found = false;
+        Some(status)
+    }
+
+    /// Runs a function with the command's arguments as the positional
+    /// parameters and its prefix assignments exported meanwhile. Variables
+    /// declared `local` inside it are put back when it returns.
+    fn call_function(&mut self, function: &Function, command: &SimpleCommand) -> i32 {
+        if self.vars.scope_depth() >= MAX_FUNCTION_DEPTH {
+            return self.report_error(&ShellError::FunctionDepth(function.name.clone()));
+        }
+        let saved_fds = match open_redirections(&command.redirections) {
+            Ok(actions) => redirect_shell(actions),
+            Err(error) => return self.report_error(&error),
+        };
+        let saved_vars = match self.vars.set_temporarily(&command.assignments) {
+            Ok(saved) => saved,
+            Err(error) => {
+                restore_shell_fds(saved_fds);
+                return self.report_error(&error);
+            }
+        };
+        let saved_positional = mem::replace(&mut self.positional, command.words[1..].to_vec());
+        self.vars.push_scope();
+        self.return_depth += 1;
+        let mut status = self.run_compound(&function.body, &function.redirections);
+        if mem::take(&mut self.returning) {
+            status = self.last_status;
+        }
+        self.return_depth -= 1;
+        self.vars.pop_scope();
+        self.positional = saved_positional;
+        self.vars.restore(saved_vars);
+        restore_shell_fds(saved_fds);
+        status
+    }
+
+    /// Runs a compound command in the shell itself, with its redirections applied
+    /// to the shell's own descriptors until it finishes.
+    fn run_compound(&mut self, compound: &CompoundCommand, redirections: &[Redirection]) -> i32 {
+        let redirections: Vec<Redirection> = redirections
+            .iter()
+            .map(|redirection| expand_redirection(redirection, self))
+            .collect();
+        if mem::take(&mut self.failed_expansion) {
+            return 1;
+        }
+        let saved = match open_redirections(&redirections) {
+            Ok(actions) => redirect_shell(actions),
+            Err(error) => return self.report_error(&error),
+        };
+
+        let status = match compound {
+            CompoundCommand::If {
+                branches,
+                otherwise,
+            } => {
+                let mut status = None;
+                for (condition, body) in branches {
+                    self.condition_depth += 1;
+                    self.run_list(condition);
+                    self.condition_depth -= 1;
+                    if self.interrupted() {
+                        break;
+                    }
+                    if self.last_status == 0 {
+                        status = Some(self.run_list(body));
+                        break;
+                    }
+                }
+                match (status, otherwise) {
+                    (Some(status), _) => status,
+                    (None, _) if self.interrupted() => self.last_status,
+                    (None, Some(body)) => self.run_list(body),
+                    (None, None) => 0,
+                }
+            }
+            CompoundCommand::Case { subject, items } => {
+                let subject: Vec<char> = expand_word(subject, self).text.chars().collect();
+                let body = items.iter().find_map(|(patterns, body)| {
+                    patterns
+                        .iter()
+                        .any(|pattern| {
+                            let pattern: Vec<char> =
+                                expand_word(pattern, self).pattern.chars().collect();
+                            glob_match(&pattern, &subject)
+                        })
+                        .then_some(body)
+                });
+                body.map_or(0, |body| self.run_list(body))
+            }
+            CompoundCommand::Conditional(expr) => match self.test_condition(expr) {
+                _ if mem::take(&mut self.failed_expansion) => 1,
+                Ok(result) => (!result) as i32,
+                Err(message) => self.report_error(&ShellError::Condition(message)),
+            },
+            CompoundCommand::Arithmetic(expr) => {
+                let expr = expand_word(expr, self).text;
+                match eval_arithmetic(&expr, &mut self.vars) {
+                    _ if mem::take(&mut self.failed_expansion) => 1,
+                    Ok(value) => (value == 0) as i32,
+                    Err(message) => self.report_error(&ShellError::Arithmetic {
+                        expr: expr.trim().to_string(),
+                        message,
+                    }),
+                }
+            }
+            // A subshell only gets here in the forked copy of the shell that runs it.
+            CompoundCommand::Subshell(body) | CompoundCommand::Group(body) => self.run_list(body),
+        };
+
+        restore_shell_fds(saved);
+        status
+    }
+
+    /// Evaluates the expression of a `[[ ]]` command. `&&` and `||` only
+    /// evaluate their right side when needed. A successful `=~` match sets
+    /// `BASH_REMATCH` to the matched text and `BASH_REMATCH_1`, `BASH_REMATCH_2`,
+    /// ... to the text of each group.
+    fn test_condition(&mut self, expr: &ConditionExpr) -> std::result::Result<bool, String> {
+        match expr {
+            ConditionExpr::Word(word) => Ok(!expand_word(word, self).text.is_empty()),
+            ConditionExpr::Not(expr) => Ok(!self.test_condition(expr)?),
+            ConditionExpr::And(lhs, rhs) => {
+                Ok(self.test_condition(lhs)? && self.test_condition(rhs)?)
+            }
+            ConditionExpr::Or(lhs, rhs) => {
+                Ok(self.test_condition(lhs)? || self.test_condition(rhs)?)
+            }
+            ConditionExpr::Unary(operator, word) => {
+                let operand = expand_word(word, self).text;
+                Ok(match operator.as_str() {
+                    "-n" => !operand.is_empty(),
+                    "-z" => operand.is_empty(),
+                    "-v" => self.vars.get(&operand).is_some(),
+                    _ => test_file(operator, &operand),
+                })
+            }
+            ConditionExpr::Binary(lhs, operator, rhs) => {
+                let lhs = expand_word(lhs, self).text;
+                let rhs = expand_word(rhs, self);
+                match operator.as_str() {
+                    "==" | "=" | "!=" => {
+                        let pattern: Vec<char> = rhs.pattern.chars().collect();
+                        let subject: Vec<char> = lhs.chars().collect();
+                        Ok(glob_match(&pattern, &subject) != (operator == "!="))
+                    }
+                    "=~" => {
+                        let regex = Regex::new(&rhs.pattern)
+                            .ok_or_else(|| format!("{}: invalid regular expression", rhs.text))?;
+                        let stale: Vec<String> = self
+                            .vars
+                            .iter()
+                            .map(|(name, _)| name)
+                            .filter(|name| is_match_variable(name))
+                            .cloned()
+                            .collect();
+                        if let Some(name) = stale.iter().find(|name| self.vars.is_readonly(name)) {
+                            return Err(format!("{}: cannot unset: readonly variable", name));
+                        }
+                        for name in stale {
+                            self.vars.unset(&name);
+                        }
+                        let Some(captures) = regex.captures(&lhs) else {
+                            return Ok(false);
+                        };
+                        for (i, capture) in captures.into_iter().enumerate() {
+                            let name = if i == 0 {
+                                "BASH_REMATCH".to_string()
+                            } else {
+                                format!("BASH_REMATCH_{}", i)
+                            };
+                            self.vars
+                                .set(&name, capture)
+                                .map_err(|error| error.to_string())?;
+                        }
+                        Ok(true)
+                    }
+                    "<" => Ok(lhs < rhs.text),
+                    ">" => Ok(lhs > rhs.text),
+                    "-nt" | "-ot" => {
+                        let modified = |path: &str| {
+                            fs::metadata(path)
+                                .and_then(|metadata| metadata.modified())
+                                .ok()
+                        };
+                        let (lhs, rhs) = (modified(&lhs), modified(&rhs.text));
+                        Ok(if operator == "-nt" {
+                            lhs > rhs
+                        } else {
+                            lhs < rhs
+                        })
+                    }
+                    "-ef" => match (fs::metadata(&lhs), fs::metadata(&rhs.text)) {
+                        (Ok(lhs), Ok(rhs)) => Ok(lhs.dev() == rhs.dev() && lhs.ino() == rhs.ino()),
+                        _ => Ok(false),
+                    },
+                    _ => {
+                        let lhs = eval_arithmetic(&lhs, &mut self.vars)
+                            .map_err(|e| format!("{}: {}", lhs, e))?;
+                        let rhs = eval_arithmetic(&rhs.text, &mut self.vars)
+                            .map_err(|e| format!("{}: {}", rhs.text, e))?;
+                        Ok(match operator.as_str() {
+                            "-eq" => lhs == rhs,
+                            "-ne" => lhs != rhs,
+                            "-lt" => lhs < rhs,
+                            "-le" => lhs <= rhs,
+                            "-gt" => lhs > rhs,
+                            _ => lhs >= rhs,
+                        })
+                    }
+                }
+            }
+        }
+    }
+
+    /// The job control signals a child should handle by default: all of them
+    /// except those ignored with `trap ''`, which stay ignored.
+    pub(crate) fn inherited_signal_resets(&self) -> Vec<Signal> {
+        JOB_CONTROL_SIGNALS
+            .into_iter()
+            .filter(|&sig| {
+                self.traps
+                    .get(&(sig as i32))
+                    .is_none_or(|action| !action.is_empty())
+            })
+            .collect()
+    }
+
+    /// Drops the traps a forked subshell does not inherit: only ignored signals
+    /// stay ignored.
+    fn clear_traps_in_subshell(&mut self) {
+        for (&number, action) in &self.traps {
+            if !action.is_empty()
+                && let Ok(sig) = Signal::try_from(number)
+            {
+                unsafe {
+                    let _ = signal(sig, SigHandler::SigDfl);
+                }
+            }
+        }
+        self.traps.retain(|_, action| action.is_empty());
+    }
+
+    /// Calls the function `name` with `args` if it is defined, as the
+    /// interactive shell does with `precmd` and `preexec` around each command.
+    /// `$?` is left as it was.
+    pub(crate) fn run_hook(&mut self, name: &str, args: &[String]) {
+        let Some(function) = self.functions.get(name).cloned() else {
+            return;
+        };
+        let command = SimpleCommand {
+            words: iter::once(name.to_string())
+                .chain(args.iter().cloned())
+                .collect(),
+            ..SimpleCommand::default()
+        };
+        let status = self.last_status;
+        self.call_function(&function, &command);
+        if !self.exit_requested {
+            self.last_status = status;
+        }
+    }
+
+    /// Runs the actions of trapped signals that arrived since the last check.
+    /// `$?` is left as it was unless the action exits.
+    fn run_pending_traps(&mut self) {
+        for (number, pending) in PENDING_TRAPS.iter().enumerate() {
+            if !pending.swap(false, Ordering::SeqCst) {
+                continue;
+            }
+            if let Some(action) = self
+                .traps
+                .get(&(number as i32))
+                .filter(|action| !action.is_empty())
+                .cloned()
+            {
+                let status = self.last_status;
+                self.run_lines(&action, None);
+                if !self.exit_requested {
+                    self.last_status = status;
+                }
+            }
+        }
+    }
+
+    /// Runs the `EXIT` trap as the shell ends with `status`, and returns the
+    /// status to exit with, which the trap may change by calling `exit`.
+    pub fn run_exit_trap(&mut self, status: ExitStatus) -> ExitStatus {
+        let Some(action) = self.traps.remove(&0).filter(|action| !action.is_empty()) else {
+            return status;
+        };
+        self.exit_requested = false;
+        self.last_status = status;
+        self.run_lines(&action, None);
+        if self.exit_requested {
+            self.last_status
+        } else {
+            status
+        }
+    }
+
+    /// Forks a copy of the shell that runs `run` and exits with its status. The
+    /// child reads from `stdin` and writes to `stdout` when they are given. With
+    /// job control, or for a background job, it joins `pgid` or leads a new
+    /// process group; a foreground child also takes the terminal.
+    fn fork_shell(
+        &mut self,
+        stdin: Option<OwnedFd>,
+        stdout: Option<OwnedFd>,
+        pgid: Option<Pid>,
+        background: bool,
+        run: impl FnOnce(&mut Shell) -> i32,
+    ) -> nix::Result<Pid> {
+        let own_group = background || self.terminal.is_some();
+        let group = pgid.unwrap_or(Pid::from_raw(0));
+        match unsafe { fork() }? {
+            ForkResult::Child => {
+                if own_group {
+                    let _ = setpgid(Pid::from_raw(0), group);
+                }
+                if !background && let Some(terminal) = self.terminal {
+                    unsafe {
+                        libc::tcsetpgrp(terminal, libc::getpgrp());
+                    }
+                }
+                for sig in self.inherited_signal_resets() {
+                    unsafe {
+                        let _ = signal(sig, SigHandler::SigDfl);
+                    }
+                }
+                self.clear_traps_in_subshell();
+                for (fd, target) in [(stdin, 0), (stdout, 1)] {
+                    if let Some(fd) = fd {
+                        unsafe {
+                            libc::dup2(fd.as_raw_fd(), target);
+                        }
+                    }
+                }
+                self.terminal = None;
+                let status = run(self);
+                let _ = io::stdout().flush();
+                std::process::exit(status);
+            }
+            ForkResult::Parent { child } => {
+                if own_group {
+                    let _ = setpgid(child, pgid.unwrap_or(child));
+                }
+                Ok(child)
+            }
+        }
+    }
+
+    /// Starts the program for a simple command with the given standard input and
+    /// output. Without a pipe, a later pipeline stage or a background job reads
+    /// from /dev/null. On failure the error is reported and its status returned.
+    fn spawn_command(
+        &mut self,
+        stage: &SimpleCommand,
+        stdin: Option<PipeReader>,
+        stdout: Option<PipeWriter>,
+        pgid: Option<Pid>,
+        background: bool,
+        later_stage: bool,
+    ) -> std::result::Result<Pid, i32> {
+        let Some(command) = stage.words.first() else {
+            return Err(0);
+        };
+        self.vars
+            .check_assignments(&stage.assignments)
+            .map_err(|error| self.report_error(&error))?;
+        let program_path = self
+            .resolve_command(command)
+            .map_err(|error| self.report_error(&error))?;
+
+        let mut cmd = process::Command::new(&program_path);
+        cmd.arg0(command);
+        cmd.env_clear();
+        cmd.envs(self.vars.exported());
+        cmd.envs(stage.assignments.iter().map(|(name, value)| (name, value)));
+        if let Some(stdin) = stdin {
+            cmd.stdin(stdin);
+        } else if later_stage || background {
+            cmd.stdin(Stdio::null());
+        }
+        if let Some(stdout) = stdout {
+            cmd.stdout(stdout);
+        }
+        if background || self.terminal.is_some() {
+            cmd.process_group(pgid.map_or(0, Pid::as_raw));
+        }
+        prepare_child(
+            &mut cmd,
+            self.terminal.filter(|_| !background),
+            self.inherited_signal_resets(),
+        );
+
+        let actions =
+            open_redirections(&stage.redirections).map_err(|error| self.report_error(&error))?;
+        apply_fd_actions(&mut cmd, actions);
+        cmd.args(&stage.words[1..]);
+
+        match cmd.spawn() {
+            Ok(child) => Ok(Pid::from_raw(child.id() as i32)),
+            Err(source) => Err(self.report_error(&ShellError::Exec {
+                command: command.clone(),
+                source,
+            })),
+        }
+    }
+
+    /// Runs every stage of a pipeline concurrently, connecting each stage's
+    /// stdout to the next stage's stdin. Simple commands run as programs, and
+    /// builtins, compound commands and functions in forked copies of the shell.
+    /// Foreground pipelines are waited for and the exit status of the last
+    /// stage is returned; background pipelines are put in their own process
+    /// group and registered in the job table.
+    pub(crate) fn spawn_pipeline(
+        &mut self,
+        stages: &[Command],
+        background: bool,
+        command_text: &str,
+    ) -> i32 {
+        let mut pids = Vec::new();
+        let mut pgid: Option<Pid> = None;
+        let mut previous_stdout: Option<PipeReader> = None;
+        let mut last_status = 0;
+        let mut failed_spawn = None;
+
+        for (i, stage) in stages.iter().enumerate() {
+            let is_last = i + 1 == stages.len();
+            let stdin = previous_stdout.take();
+            let stdout = if is_last {
+                None
+            } else {
+                match io::pipe() {
+                    Ok((reader, writer)) => {
+                        previous_stdout = Some(reader);
+                        Some(writer)
+                    }
+                    Err(e) => {
+                        last_status = self.report_error(&ShellError::Pipe(e));
+                        break;
+                    }
+                }
+            };
+
+            let spawned = match stage {
+                Command::Simple(command)
+                    if self.find_function(command).is_none()
+                        && !command.words.first().is_some_and(|name| is_builtin(name))
+                        && self.not_found_handler(command).is_none() =>
+                {
+                    self.spawn_command(command, stdin, stdout, pgid, background, i > 0)
+                }
+                _ => {
+                    let stdin = stdin.map(OwnedFd::from);
+                    let stdout = stdout.map(OwnedFd::from);
+                    self.fork_shell(stdin, stdout, pgid, background, |shell| match stage {
+                        Command::Compound(compound, redirections) => {
+                            shell.run_compound(compound, redirections)
+                        }
+                        _ => shell.run_in_shell(stage).unwrap_or_default(),
+                    })
+                    .map_err(|e| self.report_error(&ShellError::Fork(e)))
+                }
+            };
+            match spawned {
+                Ok(pid) => {
+                    if pgid.is_none() && !background && self.terminal.is_some() {
+                        give_terminal_to(pid);
+                    }
+                    pgid.get_or_insert(pid);
+                    pids.push(pid);
+                    if is_last {
+                        last_status = 0;
+                    }
+                }
+                Err(status) => {
+                    last_status = status;
+                    failed_spawn = Some(status);
+                }
+            }
+        }
+
+        let (Some(pgid), Some(&last_pid)) = (pgid, pids.last()) else {
+            return last_status;
+        };
+
+        if background {
+            self.last_background_pid = Some(last_pid);
+            let id = self.jobs.add(pgid, pids, command_text.to_string());
+            println!("[{}] {}", id, last_pid);
+            return 0;
+        }
+        let pipefail = self.options.pipefail;
+        let result = if self.terminal.is_some() {
+            wait_in_foreground(pgid, &pids, last_pid, last_status, pipefail)
+        } else {
+            wait_for_pids(&pids, last_pid, last_status, pipefail, false)
+        };
+        match result {
+            JobWait::Finished(status) => match failed_spawn {
+                Some(failed) if pipefail && status == 0 => failed,
+                _ => status,
+            },
+            JobWait::Stopped {
+                remaining,
+                last_status,
+            } => {
+                let job = Job {
+                    id: self.jobs.next_id(),
+                    pgid,
+                    pids,
+                    last_pid,
+                    last_status,
+                    command: command_text.to_string(),
+                    state: JobState::Running,
+                    no_hangup: false,
+                };
+                self.jobs.stopped_in_foreground(job, remaining, last_status);
+                128 + Signal::SIGTSTP as i32
+            }
+        }
+    }
+
+    /// Runs the commands of a script file line by line without prompting, and
+    /// returns the status of the last command.
+    pub fn run_script(&mut self, path: &str) -> ExitStatus {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(source) => {
+                let error = ShellError::Script {
+                    path: path.to_string(),
+                    source,
+                };
+                eprintln!("shell: {}", error);
+                return error.status();
+            }
+        };
+        let body = match contents.strip_prefix("#!") {
+            Some(rest) => rest.split_once('\n').map_or("", |(_, body)| body),
+            None => &contents,
+        };
+        let first_line = if contents.len() == body.len() { 1 } else { 2 };
+        self.run_lines(body, Some((path, first_line)))
+    }
+
+    /// Runs each command of `source` in turn, stopping early on `exit`, and returns
+    /// the status of the last command. A command continues onto following lines
+    /// while it is incomplete. When `origin` names the file and line the
+    /// source starts at, error messages are prefixed with the current line.
+    pub fn run_lines(&mut self, source: &str, origin: Option<(&str, usize)>) -> ExitStatus {
+        self.run_each_line(source.lines().map(str::to_string), origin)
+    }
+
+    /// Runs commands read from standard input, as when commands are piped to
+    /// the shell, and returns the status of the last one. Input is read a line
+    /// at a time without buffering, so that a command reading standard input
+    /// gets the lines after its own.
+    pub fn run_stdin(&mut self) -> ExitStatus {
+        let lines = iter::from_fn(|| {
+            let line = read_line_bytes(libc::STDIN_FILENO, true).ok()??;
+            Some(
+                String::from_utf8_lossy(
+                    &line.into_iter().map(|(byte, _)| byte).collect::<Vec<u8>>(),
+                )
+                .into_owned(),
+            )
+        });
+        let name = self.name.clone();
+        self.run_each_line(lines, Some((&name, 1)))
+    }
+
+    fn run_each_line(
+        &mut self,
+        lines: impl Iterator<Item = String>,
+        origin: Option<(&str, usize)>,
+    ) -> ExitStatus {
+        let saved = self.location.take();
+        let mut command = String::new();
+        let mut first_line = 0;
+        for (offset, line) in lines.enumerate() {
+            if command.is_empty() {
+                first_line = offset;
+            } else {
+                command.push('\n');
+            }
+            command.push_str(&line);
+            if incomplete_input(&command) {
+                continue;
+            }
+            self.location = origin.map(|(name, first)| (name.to_string(), first + first_line));
+            self.run_line(&command);
+            command.clear();
+            if self.interrupted() {
+                break;
+            }
+        }
+        if !command.is_empty() && !self.interrupted() {
+            self.location = origin.map(|(name, first)| (name.to_string(), first + first_line));
+            eprintln!(
+                "{}syntax error: unexpected end of file",
+                self.error_prefix()
+            );
+            self.last_status = 2;
+        }
+        self.location = saved;
+        self.last_status
+    }
+
+    /// The prefix for error messages reported by the shell itself: the file and
+    /// line being run, if any.
+    pub(crate) fn error_prefix(&self) -> String {
+        match &self.location {
+            Some((name, line)) => format!("{}: line {}: ", name, line),
+            None => String::new(),
+        }
+    }
+
+    /// Prints `error` after the current file and line, if any, and returns the
+    /// status it leaves.
+    pub(crate) fn report_error(&self, error: &ShellError) -> ExitStatus {
+        eprintln!("{}{}", self.error_prefix(), error);
+        error.status()
+    }
+
+    /// Runs the file at `path` in the current shell, so that the variables and
+    /// aliases it defines persist. `return` ends the file early. Errors are
+    /// reported as coming from `name`, the builtin that was called.
+    pub(crate) fn source_file(
+        &mut self,
+        name: &str,
+        path: &str,
+        io: &mut BuiltinIo,
+    ) -> io::Result<i32> {
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                self.return_depth += 1;
+                let status = self.run_lines(&contents, Some((path, 1)));
+                self.return_depth -= 1;
+                self.returning = false;
+                Ok(status)
+            }
+            Err(e) => {
+                writeln!(io.stderr, "{}: {}: {}", name, path, describe_io_error(&e))?;
+                Ok(1)
+            }
+        }
+    }
+
+    /// Handles a command without a command name: its assignments set shell
+    /// variables and its redirections are still performed.
+    fn run_assignments(&mut self, command: &SimpleCommand) -> i32 {
+        if let Err(error) = open_redirections(&command.redirections) {
+            return self.report_error(&error);
+        }
+        for (name, value) in &command.assignments {
+            if let Err(error) = self.vars.set(name, value.clone()) {
+                return self.report_error(&error);
+            }
+        }
+        0
+    }
+}
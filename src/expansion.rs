@@ -0,0 +1,1249 @@
+//! Word expansion: parameters, tildes, field splitting, globs and `[[ ]]` tests.
+
+use std::ffi::CString;
+use std::fs;
+use std::iter::Peekable;
+use std::mem;
+use std::os::unix::fs::FileTypeExt;
+use std::str::Chars;
+
+use nix::libc;
+use nix::unistd::{User, geteuid};
+
+use crate::arithmetic::eval_arithmetic;
+use crate::exec::Shell;
+use crate::parser::{RedirectKind, Redirection, SimpleCommand};
+use crate::variables::is_valid_name;
+
+/// The result of expanding a single word.
+#[derive(Debug, Default)]
+pub(crate) struct ExpandedWord {
+    pub(crate) text: String,
+    /// `text` with every quoted glob or regular expression character escaped by
+    /// a backslash.
+    pub(crate) pattern: String,
+    /// Whether the word contains an unquoted `*`, `?` or `[`.
+    has_glob: bool,
+    /// Whether any part of the word was quoted, or the field was ended by a
+    /// non-whitespace `IFS` character, so that it is kept as an argument even
+    /// if it expands to nothing.
+    quoted: bool,
+}
+
+impl ExpandedWord {
+    pub(crate) fn push(&mut self, ch: char, quoted: bool) {
+        self.text.push(ch);
+        if quoted
+            && matches!(
+                ch,
+                '*' | '?' | '[' | ']' | '\\' | '.' | '+' | '(' | ')' | '{' | '}' | '|' | '^' | '$'
+            )
+        {
+            self.pattern.push('\\');
+        } else if !quoted && matches!(ch, '*' | '?' | '[') {
+            self.has_glob = true;
+        }
+        self.pattern.push(ch);
+    }
+
+    pub(crate) fn push_str(&mut self, s: &str, quoted: bool) {
+        for ch in s.chars() {
+            self.push(ch, quoted);
+        }
+    }
+}
+
+/// Expands an unquoted tilde prefix at the start of `word`: `~` is the home
+/// directory, `~user` that user's home directory, and `~+`/`~-` are `$PWD` and
+/// `$OLDPWD`. Returns the expansion and the rest of the word, or `None` if the
+/// word has no tilde prefix or it cannot be resolved.
+fn expand_tilde<'a>(word: &'a str, shell: &Shell) -> Option<(String, &'a str)> {
+    let rest = word.strip_prefix('~')?;
+    let (prefix, rest) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    if prefix.contains(['\'', '"', '\\', '$']) {
+        return None;
+    }
+    let expansion = match prefix {
+        "" => match shell.vars.get("HOME") {
+            Some(home) => home.to_string(),
+            None => User::from_uid(geteuid()).ok()??.dir.display().to_string(),
+        },
+        "+" => shell.vars.get("PWD")?.to_string(),
+        "-" => shell.vars.get("OLDPWD")?.to_string(),
+        user => User::from_name(user).ok()??.dir.display().to_string(),
+    };
+    Some((expansion, rest))
+}
+
+/// Splits the fields of a word as its unquoted expansions are added, on the
+/// characters of `$IFS`.
+struct FieldSplitter {
+    fields: Vec<ExpandedWord>,
+    /// Separators, or `None` if expansions are not split.
+    ifs: Option<String>,
+    /// Whether an expansion ended in `IFS` whitespace, so that whatever follows
+    /// in the word starts a new field.
+    pending: bool,
+}
+
+impl FieldSplitter {
+    /// Ends the current field if an expansion left a separator pending.
+    fn flush(&mut self, result: &mut ExpandedWord) {
+        if mem::take(&mut self.pending) && (result.quoted || !result.text.is_empty()) {
+            self.fields.push(mem::take(result));
+        }
+    }
+
+    /// Adds the value of an unquoted expansion, splitting it into fields. Runs
+    /// of `IFS` whitespace separate fields and are dropped at either end; every
+    /// other `IFS` character ends a field, even an empty one.
+    fn push_unquoted(&mut self, result: &mut ExpandedWord, value: &str) {
+        let Some(ifs) = self.ifs.clone() else {
+            result.push_str(value, false);
+            return;
+        };
+        for ch in value.chars() {
+            if !ifs.contains(ch) {
+                self.flush(result);
+                result.push(ch, false);
+            } else if " \t\n".contains(ch) {
+                self.pending = true;
+            } else {
+                self.pending = false;
+                let mut field = mem::take(result);
+                field.quoted = true;
+                self.fields.push(field);
+            }
+        }
+    }
+}
+
+/// Performs tilde expansion, variable expansion and quote removal on a raw
+/// word. `$VAR` and `${VAR}` are expanded in unquoted text and inside double
+/// quotes, but not inside single quotes. With `split`, unquoted expansions are
+/// split into fields on `$IFS`. `$@` (and `$*` outside quotes) always produces
+/// a field per positional parameter, and `"$@"` with no parameters produces
+/// none.
+fn expand_word_fields(word: &str, shell: &mut Shell, split: bool) -> Vec<ExpandedWord> {
+    let ifs = match shell.vars.get("IFS") {
+        _ if !split => None,
+        Some("") => None,
+        Some(ifs) => Some(ifs.to_string()),
+        None => Some(" \t\n".to_string()),
+    };
+    let mut splitter = FieldSplitter {
+        fields: Vec::new(),
+        ifs,
+        pending: false,
+    };
+    let mut result = ExpandedWord::default();
+    let mut in_double_quotes = false;
+    let mut empty_quoted_at = false;
+    let word = match expand_tilde(word, shell) {
+        Some((home, rest)) => {
+            result.push_str(&home, true);
+            rest
+        }
+        None => word,
+    };
+    let mut chars = word.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        if ch != '$' {
+            splitter.flush(&mut result);
+        }
+        match ch {
+            '\\' if in_double_quotes => match chars.next() {
+                Some(next_ch @ ('\"' | '\\' | '$')) => result.push(next_ch, true),
+                Some(next_ch) => {
+                    result.push(ch, true);
+                    result.push(next_ch, true);
+                }
+                None => result.push(ch, true),
+            },
+            '\\' => {
+                result.quoted = true;
+                if let Some(next_ch) = chars.next() {
+                    result.push(next_ch, true);
+                }
+            }
+            '\'' if !in_double_quotes => {
+                result.quoted = true;
+                for next_ch in chars.by_ref() {
+                    if next_ch == '\'' {
+                        break;
+                    }
+                    result.push(next_ch, true);
+                }
+            }
+            '\"' => {
+                result.quoted = true;
+                in_double_quotes = !in_double_quotes;
+            }
+            '$' if chars.peek() == Some(&'@')
+                || (chars.peek() == Some(&'*') && !in_double_quotes) =>
+            {
+                chars.next();
+                if shell.positional.is_empty() && in_double_quotes {
+                    empty_quoted_at = true;
+                }
+                for (i, param) in shell.positional.iter().enumerate() {
+                    if i > 0 {
+                        splitter.pending = false;
+                        splitter.fields.push(mem::take(&mut result));
+                        result.quoted = in_double_quotes;
+                    }
+                    if in_double_quotes {
+                        result.push_str(param, true);
+                    } else {
+                        splitter.flush(&mut result);
+                        splitter.push_unquoted(&mut result, param);
+                    }
+                }
+            }
+            '$' => {
+                let value = if chars.clone().take(2).eq(['(', '(']) {
+                    expand_arithmetic(&mut chars, shell)
+                } else {
+                    expand_variable(&mut chars, shell)
+                };
+                if in_double_quotes {
+                    splitter.flush(&mut result);
+                    result.push_str(&value, true);
+                } else {
+                    splitter.push_unquoted(&mut result, &value);
+                }
+            }
+            _ => result.push(ch, in_double_quotes),
+        }
+    }
+
+    let mut fields = splitter.fields;
+    if !(empty_quoted_at && fields.is_empty() && result.text.is_empty()) {
+        fields.push(result);
+    }
+    fields
+}
+
+/// Expands a word where a single word is expected, such as a redirection
+/// target or an assignment value; multiple fields are joined by spaces.
+pub(crate) fn expand_word(word: &str, shell: &mut Shell) -> ExpandedWord {
+    let mut fields = expand_word_fields(word, shell, false).into_iter();
+    let mut result = fields.next().unwrap_or_default();
+    for field in fields {
+        result.push(' ', true);
+        result.text.push_str(&field.text);
+        result.pattern.push_str(&field.pattern);
+        result.has_glob |= field.has_glob;
+    }
+    result
+}
+
+pub(crate) fn is_name_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '_'
+}
+
+/// Expands the variable reference following a `$`. A `$` that does not start a
+/// reference is kept literally. `${...}` may apply an operator to the value;
+/// see `expand_parameter`.
+fn expand_variable(chars: &mut Peekable<Chars>, shell: &mut Shell) -> String {
+    let name = match chars.peek() {
+        Some('{') => {
+            chars.next();
+            let mut expr = String::new();
+            let mut depth = 0;
+            let mut closed = false;
+            while let Some(ch) = chars.next() {
+                match ch {
+                    '}' if depth == 0 => {
+                        closed = true;
+                        break;
+                    }
+                    '}' => depth -= 1,
+                    '{' => depth += 1,
+                    '\\' => {
+                        expr.push(ch);
+                        if let Some(next_ch) = chars.next() {
+                            expr.push(next_ch);
+                        }
+                        continue;
+                    }
+                    _ => {}
+                }
+                expr.push(ch);
+            }
+            if !closed {
+                return format!("${{{}", expr);
+            }
+            if parameter_name_len(&expr) != expr.len() {
+                return expand_parameter(&expr, shell);
+            }
+            expr
+        }
+        Some(&ch @ ('?' | '$' | '!' | '#' | '*' | '@' | '-' | '0'..='9')) => {
+            chars.next();
+            ch.to_string()
+        }
+        Some(&ch) if ch.is_ascii_alphabetic() || ch == '_' => {
+            let mut name = String::new();
+            while let Some(&ch) = chars.peek() {
+                if !is_name_char(ch) {
+                    break;
+                }
+                name.push(ch);
+                chars.next();
+            }
+            name
+        }
+        _ => return "$".to_string(),
+    };
+    if shell.options.nounset && !is_variable_set(&name, shell) {
+        return expansion_error(shell, &name, "unbound variable");
+    }
+    lookup_variable(&name, shell)
+}
+
+/// Reports an error in an expansion, which stops the command from running
+/// and ends a non-interactive shell. The expansion is empty.
+fn expansion_error(shell: &mut Shell, subject: &str, message: &str) -> String {
+    eprintln!("{}{}: {}", shell.error_prefix(), subject, message);
+    shell.failed_expansion = true;
+    shell.exit_requested |= !shell.interactive;
+    String::new()
+}
+
+/// The length of the parameter name at the start of a `${...}` expression: a
+/// variable name, a positional parameter number, or a special parameter.
+fn parameter_name_len(expr: &str) -> usize {
+    match expr.chars().next() {
+        Some('?' | '$' | '!' | '#' | '*' | '@' | '-') => 1,
+        Some('0'..='9') => expr
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(expr.len()),
+        Some(ch) if ch.is_ascii_alphabetic() || ch == '_' => {
+            expr.find(|c| !is_name_char(c)).unwrap_or(expr.len())
+        }
+        _ => 0,
+    }
+}
+
+/// Operators of `${name<op>word}`, longest first so that `:-` is not read as
+/// `:` and `##` is not read as `#`.
+const PARAMETER_OPERATORS: [&str; 14] = [
+    ":-", ":=", ":+", ":?", "-", "=", "+", "?", "##", "#", "%%", "%", "//", "/",
+];
+
+/// Expands a `${...}` expression that is more than a parameter name:
+/// `${#name}` is the length of the value; `${name-word}`, `${name=word}`,
+/// `${name+word}` and `${name?word}` use, assign, substitute or complain with
+/// `word` when the parameter is unset, or with a `:` also when it is empty;
+/// `${name#pat}`/`${name##pat}` and `${name%pat}`/`${name%%pat}` remove the
+/// shortest or longest matching prefix or suffix; and `${name/pat/rep}`
+/// replaces the first match of a pattern (every match with `//`, a match at
+/// the start with `/#`, or at the end with `/%`).
+fn expand_parameter(expr: &str, shell: &mut Shell) -> String {
+    if let Some(name) = expr.strip_prefix('#')
+        && !name.is_empty()
+        && parameter_name_len(name) == name.len()
+    {
+        if shell.options.nounset && !is_variable_set(name, shell) {
+            return expansion_error(shell, name, "unbound variable");
+        }
+        return match name {
+            "*" | "@" => shell.positional.len(),
+            _ => lookup_variable(name, shell).chars().count(),
+        }
+        .to_string();
+    }
+
+    let (name, rest) = expr.split_at(parameter_name_len(expr));
+    let Some(&operator) = PARAMETER_OPERATORS
+        .iter()
+        .find(|op| rest.starts_with(*op))
+        .filter(|_| !name.is_empty())
+    else {
+        return expansion_error(shell, &format!("${{{}}}", expr), "bad substitution");
+    };
+    let operand = &rest[operator.len()..];
+    let value = is_variable_set(name, shell).then(|| lookup_variable(name, shell));
+    let usable = value
+        .clone()
+        .filter(|value| !(operator.starts_with(':') && value.is_empty()));
+
+    match operator.trim_start_matches(':') {
+        "-" => usable.unwrap_or_else(|| expand_word(operand, shell).text),
+        "=" => match usable {
+            Some(value) => value,
+            None if parameter_name_len(name) == name.len() && is_valid_name(name) => {
+                let word = expand_word(operand, shell).text;
+                match shell.vars.set(name, word.clone()) {
+                    Ok(()) => word,
+                    Err(error) => expansion_error(shell, &format!("${}", name), &error.to_string()),
+                }
+            }
+            None => expansion_error(shell, &format!("${}", name), "cannot assign in this way"),
+        },
+        "+" => match usable {
+            Some(_) => expand_word(operand, shell).text,
+            None => String::new(),
+        },
+        "?" => match usable {
+            Some(value) => value,
+            None => {
+                let message = expand_word(operand, shell).text;
+                let message = if message.is_empty() {
+                    "parameter null or not set".to_string()
+                } else {
+                    message
+                };
+                expansion_error(shell, name, &message)
+            }
+        },
+        _ => {
+            let Some(value) = value.or_else(|| (!shell.options.nounset).then(String::new)) else {
+                return expansion_error(shell, name, "unbound variable");
+            };
+            match operator {
+                "#" | "##" | "%" | "%%" => {
+                    let pattern: Vec<char> = expand_word(operand, shell).pattern.chars().collect();
+                    remove_match(&value, &pattern, operator)
+                }
+                _ => {
+                    let (pattern, replacement) = split_substitution(operand);
+                    let replacement = expand_word(replacement, shell).text;
+                    let (anchor, pattern) = match pattern.chars().next() {
+                        Some(anchor @ ('#' | '%')) if operator == "/" => {
+                            (Some(anchor), &pattern[1..])
+                        }
+                        _ => (None, pattern),
+                    };
+                    let pattern: Vec<char> = expand_word(pattern, shell).pattern.chars().collect();
+                    replace_matches(&value, &pattern, &replacement, anchor, operator == "//")
+                }
+            }
+        }
+    }
+}
+
+/// Removes the shortest (`#`, `%`) or longest (`##`, `%%`) prefix (`#`) or
+/// suffix (`%`) of `value` that matches a glob pattern.
+fn remove_match(value: &str, pattern: &[char], operator: &str) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    let len = chars.len();
+    let split = match operator {
+        "#" => (0..=len).find(|&i| glob_match(pattern, &chars[..i])),
+        "##" => (0..=len).rev().find(|&i| glob_match(pattern, &chars[..i])),
+        "%" => (0..=len).rev().find(|&i| glob_match(pattern, &chars[i..])),
+        _ => (0..=len).find(|&i| glob_match(pattern, &chars[i..])),
+    };
+    match split {
+        Some(i) if operator.starts_with('#') => chars[i..].iter().collect(),
+        Some(i) => chars[..i].iter().collect(),
+        None => value.to_string(),
+    }
+}
+
+/// Splits the operand of `${name/pattern/replacement}` at the first unquoted
+/// `/`. Without one the replacement is empty.
+fn split_substitution(operand: &str) -> (&str, &str) {
+    let mut quote = None;
+    let mut chars = operand.char_indices();
+    while let Some((i, ch)) = chars.next() {
+        match ch {
+            '\\' if quote != Some('\'') => {
+                chars.next();
+            }
+            '\'' | '"' if quote.is_none() => quote = Some(ch),
+            _ if quote == Some(ch) => quote = None,
+            '/' if quote.is_none() => return (&operand[..i], &operand[i + 1..]),
+            _ => {}
+        }
+    }
+    (operand, "")
+}
+
+/// Replaces the longest match of a glob pattern in `value`: the first one, or
+/// every one if `all`. With an anchor of `#` or `%` the match must be at the
+/// start or end of the value. An empty pattern matches nothing.
+fn replace_matches(
+    value: &str,
+    pattern: &[char],
+    replacement: &str,
+    anchor: Option<char>,
+    all: bool,
+) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    let len = chars.len();
+    let longest_match = |start: usize| {
+        (start..=len)
+            .rev()
+            .find(|&end| glob_match(pattern, &chars[start..end]))
+    };
+    if let Some(anchor) = anchor {
+        let found = match anchor {
+            '#' => longest_match(0).map(|end| (0, end)),
+            _ => (0..=len)
+                .find(|&start| glob_match(pattern, &chars[start..]))
+                .map(|start| (start, len)),
+        };
+        let Some((start, end)) = found else {
+            return value.to_string();
+        };
+        let (before, after): (String, String) = (
+            chars[..start].iter().collect(),
+            chars[end..].iter().collect(),
+        );
+        return format!("{}{}{}", before, replacement, after);
+    }
+    if pattern.is_empty() {
+        return value.to_string();
+    }
+
+    let mut result = String::new();
+    let mut start = 0;
+    while start < len {
+        match longest_match(start).filter(|&end| end > start) {
+            Some(end) => {
+                result.push_str(replacement);
+                start = end;
+                if !all {
+                    break;
+                }
+            }
+            None => {
+                result.push(chars[start]);
+                start += 1;
+            }
+        }
+    }
+    result.extend(&chars[start..]);
+    result
+}
+
+/// Whether a variable or special parameter has a value, for `set -u`. `$@`
+/// and `$*` always do, even with no positional parameters.
+fn is_variable_set(name: &str, shell: &Shell) -> bool {
+    match name {
+        "?" | "$" | "#" | "*" | "@" | "-" | "0" => true,
+        "!" => shell.last_background_pid.is_some(),
+        _ if name.chars().all(|c| c.is_ascii_digit()) => name
+            .parse::<usize>()
+            .is_ok_and(|index| index <= shell.positional.len()),
+        _ => shell.vars.get(name).is_some(),
+    }
+}
+
+/// Expands the `((expression))` following a `$` to the value of the
+/// expression. Variables in the expression are expanded first. Errors are
+/// reported through `expansion_error`.
+fn expand_arithmetic(chars: &mut Peekable<Chars>, shell: &mut Shell) -> String {
+    let mut text = String::new();
+    let mut depth = 0;
+    for ch in chars.by_ref() {
+        match ch {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        text.push(ch);
+        if depth == 0 {
+            break;
+        }
+    }
+    let Some(expr) = text
+        .strip_prefix("((")
+        .and_then(|text| text.strip_suffix("))"))
+    else {
+        return format!("${}", text);
+    };
+    let expr = expand_word(expr, shell).text;
+    match eval_arithmetic(&expr, &mut shell.vars) {
+        Ok(value) => value.to_string(),
+        Err(message) => expansion_error(shell, expr.trim(), &message),
+    }
+}
+
+/// The value of a variable or special parameter: `$?` last status, `$$` the
+/// shell's pid, `$!` the last background job's pid, `$#` the number of
+/// positional parameters, `$*`/`$@` all of them, `$-` the option flags, `$0`
+/// the shell or script name and `$1`, `$2`, ... the positional parameters.
+fn lookup_variable(name: &str, shell: &Shell) -> String {
+    match name {
+        "?" => shell.last_status.to_string(),
+        "$" => shell.pid.to_string(),
+        "!" => shell
+            .last_background_pid
+            .map(|pid| pid.to_string())
+            .unwrap_or_default(),
+        "#" => shell.positional.len().to_string(),
+        "-" => shell.options.flags(),
+        "*" | "@" => shell.positional.join(" "),
+        "0" => shell.name.clone(),
+        _ if name.chars().all(|c| c.is_ascii_digit()) => match name.parse::<usize>() {
+            Ok(index) if index > 0 => shell.positional.get(index - 1).cloned().unwrap_or_default(),
+            _ => String::new(),
+        },
+        _ => shell.vars.get(name).unwrap_or_default().to_string(),
+    }
+}
+
+/// Performs brace expansion on a raw word, before any other expansion:
+/// `pre{a,b}post` becomes `preapost prebpost`, and `{1..10}`, `{01..10..2}`
+/// and `{a..e}` become sequences, padded with zeros if either end is. Braces
+/// may nest. Quoted braces and those of `${...}` and `$(...)` are left alone,
+/// as are braces with neither a comma nor a sequence inside.
+fn expand_braces(word: &str) -> Vec<String> {
+    let chars: Vec<char> = word.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '\'' => i = skip_quoted(&chars, i, '\''),
+            '"' => i = skip_quoted(&chars, i, '"'),
+            '$' if matches!(chars.get(i + 1), Some('{' | '(')) => {
+                i = skip_nested(&chars, i + 1);
+                continue;
+            }
+            '{' => {
+                if let Some((alternatives, end)) = brace_alternatives(&chars, i) {
+                    let prefix: String = chars[..i].iter().collect();
+                    let suffix: String = chars[end + 1..].iter().collect();
+                    return alternatives
+                        .iter()
+                        .flat_map(|alternative| {
+                            expand_braces(&format!("{}{}{}", prefix, alternative, suffix))
+                        })
+                        .collect();
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    vec![word.to_string()]
+}
+
+/// Returns the index of the quote closing the one at `start`, or the end of
+/// `chars` if it is not closed. Backslashes escape inside double quotes.
+fn skip_quoted(chars: &[char], start: usize, quote: char) -> usize {
+    let mut i = start + 1;
+    while i < chars.len() && chars[i] != quote {
+        if quote == '"' && chars[i] == '\\' {
+            i += 1;
+        }
+        i += 1;
+    }
+    i
+}
+
+/// Returns the index just past the bracket matching the `{` or `(` at `start`.
+fn skip_nested(chars: &[char], start: usize) -> usize {
+    let (open, close) = if chars[start] == '{' {
+        ('{', '}')
+    } else {
+        ('(', ')')
+    };
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '\'' | '"' => i = skip_quoted(chars, i, chars[i]),
+            ch if ch == open => depth += 1,
+            ch if ch == close => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    i
+}
+
+/// Splits the brace expression opening at `start` into its alternatives, or
+/// the elements of its sequence, and returns them with the index of the
+/// closing brace. Returns `None` if the braces do not form an expansion.
+fn brace_alternatives(chars: &[char], start: usize) -> Option<(Vec<String>, usize)> {
+    let mut depth = 0;
+    let mut commas = Vec::new();
+    let mut i = start + 1;
+    let end = loop {
+        match chars.get(i)? {
+            '\\' => i += 1,
+            '\'' => i = skip_quoted(chars, i, '\''),
+            '"' => i = skip_quoted(chars, i, '"'),
+            '$' if matches!(chars.get(i + 1), Some('{' | '(')) => {
+                i = skip_nested(chars, i + 1);
+                continue;
+            }
+            '{' => depth += 1,
+            '}' if depth == 0 => break i,
+            '}' => depth -= 1,
+            ',' if depth == 0 => commas.push(i),
+            _ => {}
+        }
+        i += 1;
+    };
+    if commas.is_empty() {
+        let body: String = chars[start + 1..end].iter().collect();
+        return brace_sequence(&body).map(|sequence| (sequence, end));
+    }
+    let mut alternatives = Vec::new();
+    let mut from = start + 1;
+    for to in commas.into_iter().chain([end]) {
+        alternatives.push(chars[from..to].iter().collect());
+        from = to + 1;
+    }
+    Some((alternatives, end))
+}
+
+/// The most words a `{first..last}` sequence expands to. Longer sequences are
+/// kept literally.
+const MAX_SEQUENCE_LENGTH: u64 = 1_000_000;
+
+/// Expands the body of a `{first..last}` or `{first..last..step}` sequence
+/// of integers or single letters.
+fn brace_sequence(body: &str) -> Option<Vec<String>> {
+    let parts: Vec<&str> = body.split("..").collect();
+    let (first, last, step) = match parts.as_slice() {
+        [first, last] => (*first, *last, 1),
+        [first, last, step] => (
+            *first,
+            *last,
+            step.parse::<i64>().ok()?.unsigned_abs().max(1),
+        ),
+        _ => return None,
+    };
+    if let (Ok(from), Ok(to)) = (first.parse::<i64>(), last.parse::<i64>()) {
+        let padded = |end: &str| {
+            end.trim_start_matches('-').len() > 1 && end.trim_start_matches('-').starts_with('0')
+        };
+        let width = if padded(first) || padded(last) {
+            first.len().max(last.len())
+        } else {
+            0
+        };
+        let values = sequence_values(from, to, step)?;
+        return Some(
+            values
+                .map(|value| {
+                    if value < 0 {
+                        format!(
+                            "-{:0width$}",
+                            value.unsigned_abs(),
+                            width = width.saturating_sub(1)
+                        )
+                    } else {
+                        format!("{:0width$}", value, width = width)
+                    }
+                })
+                .collect(),
+        );
+    }
+    let mut first_chars = first.chars();
+    let mut last_chars = last.chars();
+    match (
+        first_chars.next(),
+        first_chars.next(),
+        last_chars.next(),
+        last_chars.next(),
+    ) {
+        (Some(from), None, Some(to), None)
+            if from.is_ascii_alphabetic() && to.is_ascii_alphabetic() =>
+        {
+            Some(
+                sequence_values(from as i64, to as i64, step)?
+                    .map(|value| (value as u8 as char).to_string())
+                    .collect(),
+            )
+        }
+        _ => None,
+    }
+}
+
+/// The values from `from` to `to` inclusive, `step` apart, counting down if
+/// `to` is smaller, or `None` if there would be more than
+/// `MAX_SEQUENCE_LENGTH` of them.
+fn sequence_values(from: i64, to: i64, step: u64) -> Option<impl Iterator<Item = i64>> {
+    let count = (from.abs_diff(to) / step)
+        .checked_add(1)
+        .filter(|&count| count <= MAX_SEQUENCE_LENGTH)?;
+    let step = if to < from {
+        -i128::from(step)
+    } else {
+        i128::from(step)
+    };
+    Some((0..count).map(move |n| (i128::from(from) + i128::from(n) * step) as i64))
+}
+
+/// Expands every word of a command. Brace expansion comes first. Words with
+/// unquoted glob characters are replaced by the sorted list of matching paths,
+/// or kept literally if nothing matches. Unquoted words that expand to nothing
+/// are dropped, while quoted ones are kept as empty arguments.
+fn expand_words(words: &[String], shell: &mut Shell) -> Vec<String> {
+    let mut expanded_words = Vec::new();
+    let words: Vec<String> = words.iter().flat_map(|word| expand_braces(word)).collect();
+    for expanded in words
+        .iter()
+        .flat_map(|word| expand_word_fields(word, shell, true))
+    {
+        if expanded.has_glob {
+            let matches = expand_glob(&expanded.pattern);
+            if !matches.is_empty() {
+                expanded_words.extend(matches);
+                continue;
+            }
+        }
+        if expanded.quoted || !expanded.text.is_empty() {
+            expanded_words.push(expanded.text);
+        }
+    }
+    expanded_words
+}
+
+/// Expands the file name of a redirection, or the text of a here-document or
+/// here-string.
+pub(crate) fn expand_redirection(redirection: &Redirection, shell: &mut Shell) -> Redirection {
+    let target = match redirection.kind {
+        RedirectKind::HereDoc { expand: false } => redirection.target.clone(),
+        RedirectKind::HereDoc { expand: true } => expand_here_doc(&redirection.target, shell),
+        RedirectKind::HereString => expand_word(&redirection.target, shell).text + "\n",
+        _ => expand_word(&redirection.target, shell).text,
+    };
+    Redirection {
+        target,
+        ..redirection.clone()
+    }
+}
+
+/// Expands variables and arithmetic in the body of a here-document. Quotes
+/// are kept, and a backslash only escapes `$`, `` ` ``, `\` and newline.
+fn expand_here_doc(body: &str, shell: &mut Shell) -> String {
+    let mut result = String::new();
+    let mut chars = body.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => match chars.next() {
+                Some(next_ch @ ('$' | '`' | '\\')) => result.push(next_ch),
+                Some('\n') => {}
+                Some(next_ch) => {
+                    result.push(ch);
+                    result.push(next_ch);
+                }
+                None => result.push(ch),
+            },
+            '$' if chars.clone().take(2).eq(['(', '(']) => {
+                result.push_str(&expand_arithmetic(&mut chars, shell))
+            }
+            '$' => result.push_str(&expand_variable(&mut chars, shell)),
+            _ => result.push(ch),
+        }
+    }
+    result
+}
+
+/// Expands the words of a command and its redirections.
+pub(crate) fn expand_command(command: &SimpleCommand, shell: &mut Shell) -> SimpleCommand {
+    let redirections = command
+        .redirections
+        .iter()
+        .map(|redirection| expand_redirection(redirection, shell))
+        .collect();
+    let assignments = command
+        .assignments
+        .iter()
+        .map(|(name, value)| (name.clone(), expand_word(value, shell).text))
+        .collect();
+    SimpleCommand {
+        assignments,
+        words: expand_words(&command.words, shell),
+        redirections,
+        skip_functions: command.skip_functions,
+    }
+}
+
+/// Matches `name` against a glob pattern supporting `*`, `?`, bracket
+/// expressions (`[abc]`, `[a-z]`, `[!x]`) and backslash escapes.
+pub(crate) fn glob_match(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|skip| glob_match(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && glob_match(&pattern[1..], &name[1..]),
+        Some('[') => {
+            let Some(&ch) = name.first() else {
+                return false;
+            };
+            match match_bracket(&pattern[1..], ch) {
+                Some((matched, consumed)) => {
+                    matched && glob_match(&pattern[1 + consumed..], &name[1..])
+                }
+                None => name[0] == '[' && glob_match(&pattern[1..], &name[1..]),
+            }
+        }
+        Some('\\') if pattern.len() > 1 => {
+            name.first() == Some(&pattern[1]) && glob_match(&pattern[2..], &name[1..])
+        }
+        Some(&literal) => name.first() == Some(&literal) && glob_match(&pattern[1..], &name[1..]),
+    }
+}
+
+/// Matches `ch` against the bracket expression starting just after a `[`.
+/// Returns whether it matched and how many pattern characters the expression
+/// used, or `None` if the bracket is never closed.
+fn match_bracket(pattern: &[char], ch: char) -> Option<(bool, usize)> {
+    let mut i = 0;
+    let negated = matches!(pattern.first(), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    while i < pattern.len() {
+        let mut start = pattern[i];
+        if start == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+        if start == '\\' && i + 1 < pattern.len() {
+            i += 1;
+            start = pattern[i];
+        }
+        if i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']' {
+            let end = pattern[i + 2];
+            if start <= ch && ch <= end {
+                matched = true;
+            }
+            i += 3;
+        } else {
+            if start == ch {
+                matched = true;
+            }
+            i += 1;
+        }
+    }
+    None
+}
+
+/// A compiled POSIX extended regular expression, as matched by `=~` in
+/// `[[ ]]`.
+pub(crate) struct Regex {
+    regex: libc::regex_t,
+    /// An upper bound on the number of groups in the expression.
+    groups: usize,
+}
+
+impl Regex {
+    pub(crate) fn new(pattern: &str) -> Option<Self> {
+        let c_pattern = CString::new(pattern).ok()?;
+        let mut regex = mem::MaybeUninit::<libc::regex_t>::uninit();
+        if unsafe { libc::regcomp(regex.as_mut_ptr(), c_pattern.as_ptr(), libc::REG_EXTENDED) } != 0
+        {
+            return None;
+        }
+        Some(Regex {
+            regex: unsafe { regex.assume_init() },
+            groups: pattern.matches('(').count(),
+        })
+    }
+
+    /// The text of the leftmost match followed by that of each group, with an
+    /// empty string for groups that did not take part, or `None` if the
+    /// expression does not match.
+    pub(crate) fn captures(&self, text: &str) -> Option<Vec<String>> {
+        let c_text = CString::new(text).ok()?;
+        let mut matches = vec![
+            libc::regmatch_t {
+                rm_so: -1,
+                rm_eo: -1
+            };
+            self.groups + 1
+        ];
+        let result = unsafe {
+            libc::regexec(
+                &self.regex,
+                c_text.as_ptr(),
+                matches.len(),
+                matches.as_mut_ptr(),
+                0,
+            )
+        };
+        if result != 0 {
+            return None;
+        }
+        let captures = matches
+            .iter()
+            .map(
+                |m| match (usize::try_from(m.rm_so), usize::try_from(m.rm_eo)) {
+                    (Ok(start), Ok(end)) => text.get(start..end).unwrap_or_default().to_string(),
+                    _ => String::new(),
+                },
+            )
+            .collect();
+        Some(captures)
+    }
+}
+
+impl Drop for Regex {
+    fn drop(&mut self) {
+        unsafe { libc::regfree(&mut self.regex) };
+    }
+}
+
+/// Evaluates a file test of `[[ ]]` such as `-f path`.
+pub(crate) fn test_file(operator: &str, path: &str) -> bool {
+    let access = |mode| {
+        CString::new(path).is_ok_and(|path| unsafe { libc::access(path.as_ptr(), mode) } == 0)
+    };
+    if matches!(operator, "-h" | "-L") {
+        return fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_symlink());
+    }
+    let Ok(metadata) = fs::metadata(path) else {
+        return false;
+    };
+    let file_type = metadata.file_type();
+    match operator {
+        "-a" | "-e" => true,
+        "-f" => file_type.is_file(),
+        "-d" => file_type.is_dir(),
+        "-b" => file_type.is_block_device(),
+        "-c" => file_type.is_char_device(),
+        "-p" => file_type.is_fifo(),
+        "-S" => file_type.is_socket(),
+        "-s" => metadata.len() > 0,
+        "-r" => access(libc::R_OK),
+        "-w" => access(libc::W_OK),
+        "-x" => access(libc::X_OK),
+        _ => unreachable!("{} is not a file test", operator),
+    }
+}
+
+/// Whether `name` is one of the variables a `[[ =~ ]]` match sets:
+/// `BASH_REMATCH` or `BASH_REMATCH_N`.
+pub(crate) fn is_match_variable(name: &str) -> bool {
+    match name.strip_prefix("BASH_REMATCH") {
+        Some("") => true,
+        Some(rest) => rest
+            .strip_prefix('_')
+            .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit())),
+        None => false,
+    }
+}
+
+fn has_glob_chars(component: &str) -> bool {
+    let mut escaped = false;
+    for ch in component.chars() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '*' | '?' | '[' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+pub(crate) fn unescape_glob(component: &str) -> String {
+    let mut result = String::new();
+    let mut chars = component.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\\' {
+            if let Some(next_ch) = chars.next() {
+                result.push(next_ch);
+            }
+        } else {
+            result.push(ch);
+        }
+    }
+    result
+}
+
+/// Expands a glob pattern against the filesystem, one path component at a time,
+/// so patterns like `src/*.rs` or `*/mod.rs` match in subdirectories. Hidden
+/// entries only match when the component itself starts with a `.`.
+fn expand_glob(pattern: &str) -> Vec<String> {
+    let absolute = pattern.starts_with('/');
+    let mut paths = vec![if absolute {
+        "/".to_string()
+    } else {
+        String::new()
+    }];
+
+    for component in pattern.split('/').filter(|c| !c.is_empty()) {
+        let mut next_paths = Vec::new();
+        for base in &paths {
+            if !has_glob_chars(component) {
+                let candidate = format!("{}{}", base, unescape_glob(component));
+                if fs::symlink_metadata(&candidate).is_ok() {
+                    next_paths.push(candidate);
+                }
+                continue;
+            }
+
+            let dir = if base.is_empty() { "." } else { base.as_str() };
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            let pattern_chars: Vec<char> = component.chars().collect();
+            for entry in entries.flatten() {
+                let Ok(name) = entry.file_name().into_string() else {
+                    continue;
+                };
+                if name.starts_with('.') && !component.starts_with('.') {
+                    continue;
+                }
+                let name_chars: Vec<char> = name.chars().collect();
+                if glob_match(&pattern_chars, &name_chars) {
+                    next_paths.push(format!("{}{}", base, name));
+                }
+            }
+        }
+        paths = next_paths
+            .into_iter()
+            .map(|path| format!("{}/", path))
+            .collect();
+    }
+
+    let keep_trailing_slash = pattern.ends_with('/');
+    let mut matches: Vec<String> = paths
+        .into_iter()
+        .filter(|path| !keep_trailing_slash || std::path::Path::new(path).is_dir())
+        .map(|path| {
+            if keep_trailing_slash || path == "/" {
+                path
+            } else {
+                path.trim_end_matches('/').to_string()
+            }
+        })
+        .collect();
+    matches.sort();
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_number_sequences() {
+        assert_eq!(brace_sequence("1..4").unwrap(), ["1", "2", "3", "4"]);
+        assert_eq!(brace_sequence("3..-1").unwrap(), ["3", "2", "1", "0", "-1"]);
+        assert_eq!(brace_sequence("0..10..4").unwrap(), ["0", "4", "8"]);
+        assert_eq!(brace_sequence("10..0..-5").unwrap(), ["10", "5", "0"]);
+        assert_eq!(brace_sequence("08..11").unwrap(), ["08", "09", "10", "11"]);
+        assert_eq!(brace_sequence("-01..1").unwrap(), ["-01", "000", "001"]);
+    }
+
+    #[test]
+    fn expands_letter_sequences() {
+        assert_eq!(brace_sequence("a..e..2").unwrap(), ["a", "c", "e"]);
+        assert_eq!(brace_sequence("C..A").unwrap(), ["C", "B", "A"]);
+    }
+
+    #[test]
+    fn keeps_sequences_that_overflow_or_are_too_long_literal() {
+        assert_eq!(
+            brace_sequence("-9223372036854775808..9223372036854775807"),
+            None
+        );
+        assert_eq!(brace_sequence("1..100000000000"), None);
+        assert_eq!(brace_sequence("1..9223372036854775808"), None);
+        assert_eq!(
+            brace_sequence("-9223372036854775808..-9223372036854775808").unwrap(),
+            ["-9223372036854775808"]
+        );
+        assert_eq!(
+            brace_sequence("9223372036854775807..-9223372036854775808..-9223372036854775808")
+                .unwrap(),
+            ["9223372036854775807", "-1"]
+        );
+        assert_eq!(
+            brace_sequence("0..-9223372036854775808..9223372036854775807").unwrap(),
+            ["0", "-9223372036854775807"]
+        );
+    }
+
+    #[test]
+    fn keeps_other_bodies_literal() {
+        assert_eq!(brace_sequence("1..b"), None);
+        assert_eq!(brace_sequence("ab..c"), None);
+        assert_eq!(brace_sequence("1..2..3..4"), None);
+        assert_eq!(expand_braces("{1..x}"), ["{1..x}"]);
+        assert_eq!(
+            expand_braces("a{b,c}d{1..2}"),
+            ["abd1", "abd2", "acd1", "acd2"]
+        );
+    }
+
+    fn expand(word: &str) -> String {
+        let mut shell = Shell::new();
+        shell.vars.set("v", "dir/file.tar.gz".to_string()).unwrap();
+        shell.vars.set("empty", String::new()).unwrap();
+        expand_word(word, &mut shell).text
+    }
+
+    #[test]
+    fn removes_prefixes_and_suffixes() {
+        assert_eq!(expand("${v%.*}"), "dir/file.tar");
+        assert_eq!(expand("${v%%.*}"), "dir/file");
+        assert_eq!(expand("${v#*/}"), "file.tar.gz");
+        assert_eq!(expand("${v##*.}"), "gz");
+        assert_eq!(expand("${v%\\*}"), "dir/file.tar.gz");
+        assert_eq!(expand("${#v}"), "15");
+    }
+
+    #[test]
+    fn substitutes_defaults_and_replacements() {
+        assert_eq!(expand("${unset:-default}"), "default");
+        assert_eq!(expand("${empty:-default}"), "default");
+        assert_eq!(expand("${empty-default}"), "");
+        assert_eq!(expand("${v:+set}"), "set");
+        assert_eq!(expand("${unset:+set}"), "");
+        assert_eq!(expand("${v/file/name}"), "dir/name.tar.gz");
+        assert_eq!(expand("${v//a/A}"), "dir/file.tAr.gz");
+    }
+
+    #[test]
+    fn assigns_defaults() {
+        let mut shell = Shell::new();
+        assert_eq!(expand_word("${new:=value}", &mut shell).text, "value");
+        assert_eq!(shell.vars.get("new"), Some("value"));
+    }
+
+    fn fields(ifs: Option<&str>, words: &[&str]) -> Vec<String> {
+        let mut shell = Shell::new();
+        if let Some(ifs) = ifs {
+            shell.vars.set("IFS", ifs.to_string()).unwrap();
+        }
+        shell.vars.set("v", " a  b:c::d ".to_string()).unwrap();
+        shell.vars.set("t", "x:".to_string()).unwrap();
+        let words: Vec<String> = words.iter().map(|word| word.to_string()).collect();
+        expand_words(&words, &mut shell)
+    }
+
+    #[test]
+    fn splits_on_whitespace_and_other_separators() {
+        assert_eq!(fields(None, &["$v"]), ["a", "b:c::d"]);
+        assert_eq!(fields(Some(" :"), &["$v"]), ["a", "b", "c", "", "d"]);
+        assert_eq!(fields(Some(":"), &["$t$t"]), ["x", "x"]);
+        assert_eq!(
+            fields(Some(" :"), &["<${v}>"]),
+            ["<", "a", "b", "c", "", "d", ">"]
+        );
+        assert_eq!(fields(None, &["\"$v\""]), [" a  b:c::d "]);
+    }
+
+    #[test]
+    fn does_not_split_with_an_empty_ifs() {
+        assert_eq!(fields(Some(""), &["$v"]), [" a  b:c::d "]);
+    }
+
+    #[test]
+    fn keeps_quoted_empty_words_and_drops_unquoted_ones() {
+        assert_eq!(fields(None, &["\"\"", "$unset", "''"]), ["", ""]);
+        assert_eq!(fields(None, &["\"$@\""]), Vec::<String>::new());
+        assert_eq!(fields(None, &["\"$*\""]), [""]);
+    }
+}
@@ -0,0 +1,259 @@
+//! The directories visited at the prompt, ranked by how often and how
+//! recently they were visited, for `z` to jump to. They are kept in the
+//! format of the `z` script, so that its data file can be shared.
+
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// When the ranks add up to more than this, they are all scaled down and
+/// directories visited rarely are forgotten.
+const MAX_TOTAL_RANK: f64 = 9000.0;
+
+/// The file visited directories are kept in: `$_Z_DATA` if set, otherwise
+/// `~/.z`.
+pub(crate) fn data_file_path() -> Option<PathBuf> {
+    match env::var_os("_Z_DATA") {
+        Some(path) if !path.is_empty() => Some(PathBuf::from(path)),
+        _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(".z")),
+    }
+}
+
+/// A directory in the data file, written as `path|rank|time`.
+pub(crate) struct VisitedDirectory {
+    pub(crate) path: String,
+    /// How many times it was visited, less as the total is scaled down.
+    rank: f64,
+    /// When it was last visited, in seconds since the epoch.
+    time: u64,
+}
+
+impl VisitedDirectory {
+    /// The rank weighted by how long ago the directory was last visited.
+    pub(crate) fn frecency(&self, now: u64) -> f64 {
+        match now.saturating_sub(self.time) {
+            age if age < 3600 => self.rank * 4.0,
+            age if age < 86400 => self.rank * 2.0,
+            age if age < 604800 => self.rank / 2.0,
+            _ => self.rank / 4.0,
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+/// Reads the directories of a data file that still exist. A missing file
+/// has none, and malformed lines are skipped.
+pub(crate) fn read_visited(path: &Path) -> io::Result<Vec<VisitedDirectory>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let visited = contents.lines().filter_map(|line| {
+        let mut fields = line.rsplitn(3, '|');
+        let time = fields.next()?.parse().ok()?;
+        let rank = fields.next()?.parse().ok()?;
+        let path = fields.next()?.to_string();
+        Path::new(&path)
+            .is_dir()
+            .then_some(VisitedDirectory { path, rank, time })
+    });
+    Ok(visited.collect())
+}
+
+/// Replaces the data file with `visited`, writing a temporary file first so
+/// that another shell never reads it half written.
+pub(crate) fn write_visited(path: &Path, visited: &[VisitedDirectory]) -> io::Result<()> {
+    let temporary = PathBuf::from(format!("{}.{}", path.display(), process::id()));
+    let mut contents = Vec::new();
+    for directory in visited {
+        writeln!(
+            contents,
+            "{}|{}|{}",
+            directory.path, directory.rank, directory.time
+        )?;
+    }
+    fs::write(&temporary, contents)?;
+    fs::rename(&temporary, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temporary);
+    })
+}
+
+/// Counts a visit to `directory` in the data file at `path`. The home and
+/// root directories are not counted, since they are easy to get to.
+pub(crate) fn record_visit(path: &Path, directory: &str, home: Option<&str>) -> io::Result<()> {
+    if directory == "/" || Some(directory) == home {
+        return Ok(());
+    }
+    let mut visited = read_visited(path)?;
+    let now = now();
+    match visited.iter_mut().find(|visited| visited.path == directory) {
+        Some(visited) => {
+            visited.rank += 1.0;
+            visited.time = now;
+        }
+        None => visited.push(VisitedDirectory {
+            path: directory.to_string(),
+            rank: 1.0,
+            time: now,
+        }),
+    }
+    if visited.iter().map(|visited| visited.rank).sum::<f64>() > MAX_TOTAL_RANK {
+        for directory in &mut visited {
+            directory.rank *= 0.99;
+        }
+        visited.retain(|directory| directory.rank >= 1.0);
+    }
+    write_visited(path, &visited)
+}
+
+/// Whether `patterns` all occur in `path`, each after the one before.
+fn matches_in_order(path: &str, patterns: &[String]) -> bool {
+    let mut rest = path;
+    patterns
+        .iter()
+        .all(|pattern| match rest.find(pattern.as_str()) {
+            Some(start) => {
+                rest = &rest[start + pattern.len()..];
+                true
+            }
+            None => false,
+        })
+}
+
+/// The visited directories that `patterns` match, with their frecency, best
+/// first. Patterns match case-sensitively if any directory matches that way,
+/// and otherwise ignoring case.
+pub(crate) fn matching_directories(
+    visited: &[VisitedDirectory],
+    patterns: &[&str],
+) -> Vec<(f64, String)> {
+    let now = now();
+    let exact: Vec<String> = patterns.iter().map(|pattern| pattern.to_string()).collect();
+    let folded: Vec<String> = patterns
+        .iter()
+        .map(|pattern| pattern.to_lowercase())
+        .collect();
+    let find = |fold: bool| {
+        let mut matches: Vec<(f64, String)> = visited
+            .iter()
+            .filter(|directory| {
+                if fold {
+                    matches_in_order(&directory.path.to_lowercase(), &folded)
+                } else {
+                    matches_in_order(&directory.path, &exact)
+                }
+            })
+            .map(|directory| (directory.frecency(now), directory.path.clone()))
+            .collect();
+        matches.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        matches
+    };
+    let matches = find(false);
+    if matches.is_empty() {
+        find(true)
+    } else {
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A data file in a new directory of the test's own, with the directories
+    /// `a`, `b` and `c` next to it.
+    fn data_file(test: &str) -> (PathBuf, String) {
+        let dir = env::temp_dir().join(format!("shell-frecency-{}-{}", process::id(), test));
+        let _ = fs::remove_dir_all(&dir);
+        for name in ["a", "b", "c"] {
+            fs::create_dir_all(dir.join(name)).unwrap();
+        }
+        (dir.join("z"), dir.display().to_string())
+    }
+
+    fn ranks(path: &Path) -> Vec<(String, f64)> {
+        let visited = read_visited(path).unwrap();
+        visited
+            .into_iter()
+            .map(|directory| (directory.path, directory.rank))
+            .collect()
+    }
+
+    fn directory(path: &str, rank: f64, age: u64) -> VisitedDirectory {
+        VisitedDirectory {
+            path: path.to_string(),
+            rank,
+            time: now() - age,
+        }
+    }
+
+    #[test]
+    fn records_visits_except_to_home_and_root() {
+        let (path, dir) = data_file("record");
+        let (a, b) = (format!("{}/a", dir), format!("{}/b", dir));
+        for directory in [&a, &b, &a, "/", &dir] {
+            record_visit(&path, directory, Some(&dir)).unwrap();
+        }
+        assert_eq!(ranks(&path), [(a, 2.0), (b, 1.0)]);
+    }
+
+    #[test]
+    fn scales_ranks_down_past_the_maximum() {
+        let (path, dir) = data_file("scale");
+        let (a, b, c) = (
+            format!("{}/a", dir),
+            format!("{}/b", dir),
+            format!("{}/c", dir),
+        );
+        let data = format!("{}|8998|1\n{}|1|1\n{}|0.5|1\n", a, b, c);
+        fs::write(&path, data).unwrap();
+        record_visit(&path, &c, None).unwrap();
+        assert_eq!(ranks(&path), [(a, 8998.0 * 0.99), (c, 1.5 * 0.99)]);
+    }
+
+    #[test]
+    fn matches_patterns_in_order() {
+        let visited = [
+            directory("/src/shell", 1.0, 0),
+            directory("/src/other/shell", 1.0, 7200),
+            directory("/shell/src", 10.0, 0),
+        ];
+        let paths = |patterns: &[&str]| -> Vec<String> {
+            let matches = matching_directories(&visited, patterns);
+            matches.into_iter().map(|(_, path)| path).collect()
+        };
+        assert_eq!(
+            paths(&["shell"]),
+            ["/shell/src", "/src/shell", "/src/other/shell"]
+        );
+        assert_eq!(paths(&["src", "shell"]), ["/src/shell", "/src/other/shell"]);
+        assert_eq!(paths(&["other", "src"]), Vec::<String>::new());
+    }
+
+    #[test]
+    fn ignores_case_only_when_nothing_matches_exactly() {
+        let visited = [
+            directory("/home/Projects", 1.0, 0),
+            directory("/home/projects", 1.0, 0),
+        ];
+        let matches = matching_directories(&visited, &["Proj"]);
+        assert_eq!(matches, [(4.0, "/home/Projects".to_string())]);
+        let matches = matching_directories(&visited, &["PROJ"]);
+        assert_eq!(
+            matches,
+            [
+                (4.0, "/home/Projects".to_string()),
+                (4.0, "/home/projects".to_string())
+            ]
+        );
+    }
+}
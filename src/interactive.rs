@@ -0,0 +1,1524 @@
+//! The interactive loop: prompts, the line editor and history.
+
+use rustyline::config::{CompletionType, Configurer, EditMode};
+use rustyline::error::ReadlineError;
+use rustyline::history::{DefaultHistory, History};
+use rustyline::{
+    Anchor, At, Cmd, ConditionalEventHandler, Config, Editor, Event, EventContext, EventHandler,
+    ExternalPrinter, InputMode, KeyCode, KeyEvent, Modifiers, Movement, RepeatCount, Result, Word,
+};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::mem;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Stdio};
+use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use nix::unistd::{User, geteuid, gethostname};
+
+use crate::builtins::{BuiltinIo, working_directory};
+use crate::completion::{ShellCompleter, VI_INSERT_INDICATOR};
+use crate::error::describe_io_error;
+use crate::exec::{Shell, find_executable_in_path};
+use crate::frecency::{data_file_path, record_visit};
+use crate::jobs::{
+    CHILD_STATUS_CHANGED, JOB_CONTROL_SIGNALS, JobNotifier, WINDOW_RESIZED, init_job_control,
+    prepare_child, watch_window_size, window_size,
+};
+use crate::parser::lex_command_line;
+use crate::variables::Variables;
+
+const DEFAULT_HISTORY_SIZE: usize = 1000;
+
+/// The file history is persisted to: `$HISTFILE` if set, otherwise
+/// `~/.shell_history`.
+pub(crate) fn history_file_path() -> Option<PathBuf> {
+    match env::var_os("HISTFILE") {
+        Some(path) if !path.is_empty() => Some(PathBuf::from(path)),
+        _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(".shell_history")),
+    }
+}
+
+/// Maximum number of history entries kept in memory and in the history file,
+/// taken from `$HISTSIZE`.
+fn history_size() -> usize {
+    env::var("HISTSIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(DEFAULT_HISTORY_SIZE)
+}
+
+/// Reads the entries of a history file.
+fn read_history_file(path: &Path) -> Result<Vec<String>> {
+    let config = Config::builder().max_history_size(history_size())?.build();
+    let mut history = DefaultHistory::with_config(&config);
+    history.load(path)?;
+    Ok(history.iter().cloned().collect())
+}
+
+/// Replaces the contents of a history file with `entries`.
+pub(crate) fn write_history_file(path: &Path, entries: &[String]) -> Result<()> {
+    if entries.is_empty() {
+        File::create(path)?;
+        return Ok(());
+    }
+    let config = Config::builder().max_history_size(history_size())?.build();
+    let mut history = DefaultHistory::with_config(&config);
+    for entry in entries {
+        history.add(entry)?;
+    }
+    history.save(path)
+}
+
+/// Lists the command history with entry numbers, or the last `N` entries.
+/// `-c` clears it, `-d N` deletes entry N (counting from the end if negative),
+/// `-w` writes it to the history file and `-r` appends the file's entries to
+/// it. Changes are saved to `persist`, the history file of an interactive
+/// shell, so that it always matches the list.
+pub(crate) fn history_builtin(
+    history: &mut Vec<String>,
+    persist: Option<PathBuf>,
+    args: &[&str],
+    io: &mut BuiltinIo,
+) -> io::Result<i32> {
+    let file_arg = |index: usize| {
+        args.get(index)
+            .map(PathBuf::from)
+            .or_else(history_file_path)
+    };
+    match args.first().copied() {
+        None => {
+            for (i, entry) in history.iter().enumerate() {
+                writeln!(io.stdout, "{:5}  {}", i + 1, entry)?;
+            }
+            return Ok(0);
+        }
+        Some("-c") => history.clear(),
+        Some("-d") => {
+            let Some(&offset) = args.get(1) else {
+                writeln!(io.stderr, "history: -d: option requires an argument")?;
+                return Ok(2);
+            };
+            let index = match offset.parse::<i64>() {
+                Ok(n) if n < 0 => history.len().checked_sub(n.unsigned_abs() as usize),
+                Ok(n) => (n as usize).checked_sub(1),
+                Err(_) => None,
+            };
+            match index.filter(|&index| index < history.len()) {
+                Some(index) => {
+                    history.remove(index);
+                }
+                None => {
+                    writeln!(
+                        io.stderr,
+                        "history: {}: history position out of range",
+                        offset
+                    )?;
+                    return Ok(1);
+                }
+            }
+        }
+        Some(option @ ("-w" | "-r")) => {
+            let Some(path) = file_arg(1) else {
+                writeln!(io.stderr, "history: no history file")?;
+                return Ok(1);
+            };
+            let result = if option == "-w" {
+                write_history_file(&path, history)
+            } else {
+                read_history_file(&path).map(|entries| history.extend(entries))
+            };
+            if let Err(e) = result {
+                writeln!(io.stderr, "history: {}: {}", path.display(), e)?;
+                return Ok(1);
+            }
+            if option == "-w" {
+                return Ok(0);
+            }
+        }
+        Some(count) => {
+            let Ok(count) = count.parse::<usize>() else {
+                writeln!(io.stderr, "history: {}: numeric argument required", count)?;
+                return Ok(1);
+            };
+            let start = history.len().saturating_sub(count);
+            for (i, entry) in history.iter().enumerate().skip(start) {
+                writeln!(io.stdout, "{:5}  {}", i + 1, entry)?;
+            }
+            return Ok(0);
+        }
+    }
+    if let Some(path) = persist
+        && let Err(e) = write_history_file(&path, history)
+    {
+        writeln!(io.stderr, "history: {}: {}", path.display(), e)?;
+        return Ok(1);
+    }
+    Ok(0)
+}
+
+/// Finds the history entry an event designator refers to: `!` for the previous
+/// command, `N` for entry N, `-N` for the Nth previous one, or a prefix for the
+/// most recent command starting with it. `$` refers to the last word of the
+/// previous command instead.
+fn history_event<'a>(event: &str, history: &'a DefaultHistory) -> Option<&'a str> {
+    if event == "!" {
+        return history.iter().next_back().map(String::as_str);
+    }
+    if event == "$" {
+        let previous = history.iter().next_back()?;
+        let (_, span) = lex_command_line(previous).pop()?;
+        return previous.get(span);
+    }
+    if let Some(back) = event
+        .strip_prefix('-')
+        .and_then(|n| n.parse::<usize>().ok())
+    {
+        return back
+            .checked_sub(1)
+            .and_then(|back| history.iter().nth_back(back))
+            .map(String::as_str);
+    }
+    if let Ok(number) = event.parse::<usize>() {
+        return number
+            .checked_sub(1)
+            .and_then(|index| history.iter().nth(index))
+            .map(String::as_str);
+    }
+    history
+        .iter()
+        .rev()
+        .find(|entry| entry.starts_with(event))
+        .map(String::as_str)
+}
+
+/// Performs history expansion of `!!`, `!$`, `!N`, `!-N` and `!prefix` on `line`.
+/// Returns `None` when the line contains no history references. A `!` inside
+/// single quotes or after a backslash is left alone.
+fn expand_history(
+    line: &str,
+    history: &DefaultHistory,
+) -> std::result::Result<Option<String>, String> {
+    let mut expanded = String::new();
+    let mut changed = false;
+    let (mut in_single, mut in_double) = (false, false);
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        match c {
+            '\\' if !in_single => {
+                expanded.push(c);
+                if let Some(next) = rest.chars().next() {
+                    expanded.push(next);
+                    rest = &rest[next.len_utf8()..];
+                }
+                continue;
+            }
+            '\'' if !in_double => in_single = !in_single,
+            '"' if !in_single => in_double = !in_double,
+            '!' if !in_single => {
+                let length = if rest.starts_with(['!', '$']) {
+                    1
+                } else {
+                    let digits = rest.strip_prefix('-').unwrap_or(rest);
+                    let numeric = digits.len()
+                        - digits
+                            .trim_start_matches(|c: char| c.is_ascii_digit())
+                            .len();
+                    if numeric > 0 {
+                        rest.len() - digits.len() + numeric
+                    } else {
+                        rest.find(|c: char| c.is_whitespace() || ";|&()<>\"'=".contains(c))
+                            .unwrap_or(rest.len())
+                    }
+                };
+                if length > 0 {
+                    let event = &rest[..length];
+                    let entry = history_event(event, history)
+                        .ok_or_else(|| format!("!{}: event not found", event))?;
+                    expanded.push_str(entry);
+                    rest = &rest[length..];
+                    changed = true;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        expanded.push(c);
+    }
+    Ok(changed.then_some(expanded))
+}
+
+const DEFAULT_PROMPT: &str = "$ ";
+const DEFAULT_CONTINUATION_PROMPT: &str = "> ";
+
+/// Reads a complete command, prompting with `continuation` for more lines while
+/// the editor's validator finds the input incomplete. The lines are joined with
+/// newlines, so that the command goes into the history as one entry. Returns `None` if input ends
+/// before the command is complete. The first line starts out as `initial`.
+fn read_command(
+    rl: &mut Editor<ShellCompleter, DefaultHistory>,
+    prompt: &str,
+    continuation: &str,
+    initial: &str,
+) -> Result<Option<String>> {
+    // Each line starts out in insert mode.
+    let insert_mode = |rl: &Editor<ShellCompleter, DefaultHistory>| {
+        if let Some(helper) = rl.helper() {
+            helper.vi_command_mode.store(false, Ordering::Relaxed);
+        }
+    };
+    // A line accepted by a key bound to text is shown with the text added.
+    let bound_text = |rl: &Editor<ShellCompleter, DefaultHistory>, line: String| match rl
+        .helper()
+        .and_then(|helper| helper.macro_line.lock().ok()?.take())
+    {
+        Some(bound) => {
+            println!("{}", bound);
+            bound
+        }
+        None => line,
+    };
+    // The validator checks each line together with the lines read before it,
+    // and marks the command incomplete if it needs more.
+    let start_line = |rl: &Editor<ShellCompleter, DefaultHistory>, previous: &str| {
+        if let Some(helper) = rl.helper() {
+            *helper
+                .previous_lines
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) = previous.to_string();
+            helper.incomplete.store(false, Ordering::Relaxed);
+        }
+    };
+    let incomplete = |rl: &Editor<ShellCompleter, DefaultHistory>| {
+        rl.helper()
+            .is_some_and(|helper| helper.incomplete.load(Ordering::Relaxed))
+    };
+    start_line(rl, "");
+    insert_mode(rl);
+    let line = rl.readline_with_initial(prompt, (initial, ""))?;
+    let mut command = bound_text(rl, line);
+    while incomplete(rl) {
+        start_line(rl, &command);
+        insert_mode(rl);
+        match rl.readline(continuation) {
+            Ok(line) => {
+                let line = bound_text(rl, line);
+                command.push('\n');
+                command.push_str(&line);
+            }
+            Err(ReadlineError::Eof) => {
+                eprintln!("syntax error: unexpected end of file");
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(Some(command))
+}
+
+/// Replaces `home` at the start of `path` with `~`.
+pub(crate) fn abbreviate_home(path: &str, home: Option<&str>) -> String {
+    match home {
+        Some(home) if !home.is_empty() && home != "/" => match path.strip_prefix(home) {
+            Some("") => "~".to_string(),
+            Some(rest) if rest.starts_with('/') => format!("~{}", rest),
+            _ => path.to_string(),
+        },
+        _ => path.to_string(),
+    }
+}
+
+/// Expands the backslash escapes of a `PS1` or `PS2` prompt: `\u` user, `\h`/`\H` short
+/// and full host name, `\w`/`\W` working directory and its last component with
+/// `~` for home, `\$` (`#` for root), `\?` the last command's status, `\L` how
+/// long it ran, `\n` newline, `\e` escape and `\\`. `\[` and `\]` are accepted
+/// and dropped.
+fn expand_prompt(template: &str, shell: &Shell) -> String {
+    let mut prompt = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            prompt.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('u') => match shell.vars.get("USER") {
+                Some(user) => prompt.push_str(user),
+                None => {
+                    if let Ok(Some(user)) = User::from_uid(geteuid()) {
+                        prompt.push_str(&user.name);
+                    }
+                }
+            },
+            Some(escape @ ('h' | 'H')) => {
+                let host = gethostname()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                match escape {
+                    'h' => prompt.push_str(host.split('.').next().unwrap_or_default()),
+                    _ => prompt.push_str(&host),
+                }
+            }
+            Some(escape @ ('w' | 'W')) => {
+                let cwd = working_directory(&shell.vars);
+                let cwd = abbreviate_home(&cwd, shell.vars.get("HOME"));
+                match escape {
+                    'W' if cwd != "/" && cwd != "~" => {
+                        prompt.push_str(cwd.rsplit('/').next().unwrap_or_default())
+                    }
+                    _ => prompt.push_str(&cwd),
+                }
+            }
+            Some('$') => prompt.push(if geteuid().is_root() { '#' } else { '$' }),
+            Some('?') => prompt.push_str(&shell.last_status.to_string()),
+            Some('L') => {
+                let seconds = shell
+                    .vars
+                    .get("LAST_DURATION")
+                    .and_then(|seconds| seconds.parse().ok());
+                prompt.push_str(&format_duration(Duration::from_secs_f64(
+                    seconds.unwrap_or(0.0),
+                )));
+            }
+            Some('n') => prompt.push('\n'),
+            Some('e') => prompt.push('\x1b'),
+            Some('\\') => prompt.push('\\'),
+            Some('[' | ']') => {}
+            Some(other) => {
+                prompt.push('\\');
+                prompt.push(other);
+            }
+            None => prompt.push('\\'),
+        }
+    }
+    prompt
+}
+
+/// The OSC 7 sequence telling the terminal that the working directory is
+/// `cwd`, as a `file://` URL with the host name.
+fn report_directory(cwd: &str) -> String {
+    let host = gethostname()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut path = String::new();
+    for byte in cwd.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            path.push(byte as char);
+        } else {
+            path.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    format!("\x1b]7;file://{}{}\x1b\\", host, path)
+}
+
+/// Formats a command's run time for the prompt and `REPORTTIME`: seconds to
+/// two decimal places, with minutes and hours split off when it ran that long.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..60 => format!("{:.2}s", duration.as_secs_f64()),
+        60..3600 => format!("{}m{}s", seconds / 60, seconds % 60),
+        _ => format!("{}h{}m{}s", seconds / 3600, seconds / 60 % 60, seconds % 60),
+    }
+}
+
+/// Reports how long `command` ran on standard error if it took longer than
+/// `$REPORTTIME` seconds.
+fn report_time(shell: &Shell, command: &str, duration: Duration) {
+    let limit = shell
+        .vars
+        .get("REPORTTIME")
+        .and_then(|limit| limit.parse::<f64>().ok());
+    if limit.is_some_and(|limit| limit >= 0.0 && duration.as_secs_f64() > limit) {
+        let command = command.lines().next().unwrap_or_default();
+        eprintln!("{}  {} total", command, format_duration(duration));
+    }
+}
+
+/// The window title shown at the prompt when `TITLE` is unset.
+const DEFAULT_TITLE: &str = "\\w";
+
+/// The xterm sequence that sets the window title to `$TITLE`, expanded like
+/// `PS1`, followed by the command being run, if any. Returns `None` when
+/// `TITLE` is empty or the terminal has no title to set.
+fn window_title(shell: &Shell, command: Option<&str>) -> Option<String> {
+    if matches!(shell.vars.get("TERM"), None | Some("" | "dumb" | "linux")) {
+        return None;
+    }
+    let mut title = expand_prompt(shell.vars.get("TITLE").unwrap_or(DEFAULT_TITLE), shell);
+    if title.is_empty() {
+        return None;
+    }
+    if let Some(command) = command {
+        title.push_str(" \u{2014} ");
+        title.push_str(command.trim());
+    }
+    let title: String = title
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    Some(format!("\x1b]0;{}\x07", title))
+}
+
+/// Runs `command`, the value of `PROMPT_COMMAND_OUTPUT`, and returns what it
+/// prints to use as the prompt, so that prompt generators like starship can
+/// draw it. Like `complete -C` commands it is run by a new copy of the shell,
+/// which gets the last command's status in `SHELL_STATUS`, how long it ran in
+/// milliseconds in `SHELL_DURATION` and the number of jobs in `SHELL_JOBS`.
+/// Returns `None` if the command could not be run or failed.
+fn run_prompt_program(
+    command: &str,
+    status: i32,
+    duration: Duration,
+    jobs: usize,
+) -> Option<String> {
+    let shell = env::current_exe().ok()?;
+    let output = process::Command::new(shell)
+        .arg("-c")
+        .arg(command)
+        .env("SHELL_STATUS", status.to_string())
+        .env("SHELL_DURATION", duration.as_millis().to_string())
+        .env("SHELL_JOBS", jobs.to_string())
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    output.status.success().then(|| {
+        String::from_utf8_lossy(&output.stdout)
+            .trim_end_matches('\n')
+            .to_string()
+    })
+}
+
+/// Runs the commands in `$PROMPT_COMMAND` and then the `precmd` function, if
+/// defined, before the prompt is shown. `$?` is left as the last command set it.
+fn run_prompt_hooks(shell: &mut Shell) {
+    if let Some(command) = shell
+        .vars
+        .get("PROMPT_COMMAND")
+        .filter(|command| !command.is_empty())
+    {
+        let command = command.to_string();
+        let status = shell.last_status;
+        shell.run_lines(&command, None);
+        if shell.exit_requested {
+            return;
+        }
+        shell.last_status = status;
+    }
+    shell.run_hook("precmd", &[]);
+}
+
+/// Runs a startup file in the shell if it exists.
+fn run_startup_file(shell: &mut Shell, path: &Path) {
+    match fs::read_to_string(path) {
+        Ok(contents) => {
+            let name = path.display().to_string();
+            shell.run_lines(&contents, Some((&name, 1)));
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => eprintln!("shell: {}: {}", path.display(), describe_io_error(&e)),
+    }
+}
+
+/// Runs `~/.shell_profile`, or `~/.profile` if there is none, as login
+/// shells do before anything else. Returns the status to exit with if it ran
+/// `exit`.
+pub fn run_login_profile(shell: &mut Shell) -> Option<i32> {
+    if let Some(home) = shell.vars.get("HOME") {
+        let home = PathBuf::from(home);
+        let path = [".shell_profile", ".profile"]
+            .into_iter()
+            .map(|name| home.join(name))
+            .find(|path| path.exists())
+            .unwrap_or_else(|| home.join(".profile"));
+        run_startup_file(shell, &path);
+    }
+    shell.exit_requested.then_some(shell.last_status)
+}
+
+/// The startup file run by interactive shells: `$SHELL_RC` if set, otherwise
+/// `~/.shellrc`.
+fn rc_file_path(shell: &Shell) -> Option<PathBuf> {
+    match shell.vars.get("SHELL_RC") {
+        Some(path) if !path.is_empty() => Some(PathBuf::from(path)),
+        _ => shell
+            .vars
+            .get("HOME")
+            .map(|home| PathBuf::from(home).join(".shellrc")),
+    }
+}
+
+/// The editor named by the first of the variables `names` that is set, or `vi`.
+pub(crate) fn editor_command(vars: &Variables, names: &[&str]) -> String {
+    names
+        .iter()
+        .filter_map(|name| vars.get(name))
+        .find(|editor| !editor.is_empty())
+        .unwrap_or("vi")
+        .to_string()
+}
+
+/// Writes `text` to a new temporary file, runs `run_editor` on it, and returns
+/// the edited text, or `None` if the editor reported failure. The file is
+/// removed afterwards.
+pub(crate) fn edit_text(
+    text: &str,
+    run_editor: impl FnOnce(&Path) -> bool,
+) -> io::Result<Option<String>> {
+    static EDITS: AtomicUsize = AtomicUsize::new(0);
+    let name = format!(
+        "shell-edit-{}-{}.sh",
+        process::id(),
+        EDITS.fetch_add(1, Ordering::Relaxed)
+    );
+    let path = env::temp_dir().join(name);
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)?
+        .write_all(text.as_bytes())?;
+    let edited = if run_editor(&path) {
+        Some(fs::read_to_string(&path))
+    } else {
+        None
+    };
+    let _ = fs::remove_file(&path);
+    edited.transpose()
+}
+
+/// Ctrl-X Ctrl-E: opens the line being edited in `$VISUAL` or `$EDITOR` and
+/// replaces it with the result, ready to be run with Enter.
+struct EditLineInEditor {
+    /// The editor command, kept up to date with the shell's variables.
+    editor: Arc<Mutex<String>>,
+}
+
+impl ConditionalEventHandler for EditLineInEditor {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        let editor = self.editor.lock().ok()?.clone();
+        let edited = edit_text(&format!("{}\n", ctx.line()), |path| {
+            let mut cmd = process::Command::new("/bin/sh");
+            cmd.arg("-c")
+                .arg(format!("{} \"$1\"", editor))
+                .arg("sh")
+                .arg(path);
+            prepare_child(&mut cmd, None, JOB_CONTROL_SIGNALS.to_vec());
+            cmd.status().is_ok_and(|status| status.success())
+        });
+        match edited {
+            Ok(Some(line)) => Some(Cmd::Replace(
+                Movement::WholeBuffer,
+                Some(line.trim_end_matches('\n').to_string()),
+            )),
+            _ => Some(Cmd::Repaint),
+        }
+    }
+}
+
+/// Encodes `bytes` in base64, as OSC 52 carries text.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| {
+            group | (byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// How text killed in the line editor reaches the system clipboard.
+#[derive(Debug, Clone, Default, PartialEq)]
+enum Clipboard {
+    /// `set -o clipboard` is off, so only the line editor's kill ring is used.
+    #[default]
+    Off,
+    /// Killed text is sent to the terminal with OSC 52, and Ctrl-Y yanks from
+    /// the kill ring since the clipboard cannot be read back.
+    Terminal,
+    /// Programs that copy standard input to the clipboard and print it.
+    Programs {
+        copy: &'static [&'static str],
+        paste: &'static [&'static str],
+    },
+}
+
+/// Clipboard programs with the variable naming the display they need, if any.
+const CLIPBOARD_PROGRAMS: [(&str, &[&str], &[&str]); 3] = [
+    (
+        "WAYLAND_DISPLAY",
+        &["wl-copy"],
+        &["wl-paste", "--no-newline"],
+    ),
+    (
+        "DISPLAY",
+        &["xclip", "-selection", "clipboard"],
+        &["xclip", "-selection", "clipboard", "-o"],
+    ),
+    ("", &["pbcopy"], &["pbpaste"]),
+];
+
+impl Clipboard {
+    /// The clipboard for `set -o clipboard`: the first of `CLIPBOARD_PROGRAMS`
+    /// that is installed and has its display, otherwise the terminal's.
+    fn for_shell(shell: &Shell) -> Self {
+        if !shell.options.clipboard {
+            return Clipboard::Off;
+        }
+        let path_var = shell.vars.get("PATH").unwrap_or_default();
+        CLIPBOARD_PROGRAMS
+            .iter()
+            .find(|&&(display, copy, _)| {
+                (display.is_empty()
+                    || shell
+                        .vars
+                        .get(display)
+                        .is_some_and(|display| !display.is_empty()))
+                    && find_executable_in_path(copy[0], path_var).is_some()
+            })
+            .map_or(Clipboard::Terminal, |&(_, copy, paste)| {
+                Clipboard::Programs { copy, paste }
+            })
+    }
+
+    fn copy(&self, text: &str) {
+        match self {
+            Clipboard::Off => {}
+            Clipboard::Terminal => {
+                print!("\x1b]52;c;{}\x07", base64(text.as_bytes()));
+                let _ = io::stdout().flush();
+            }
+            Clipboard::Programs { copy, .. } => {
+                let mut cmd = process::Command::new(copy[0]);
+                cmd.args(&copy[1..])
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null());
+                prepare_child(&mut cmd, None, JOB_CONTROL_SIGNALS.to_vec());
+                if let Ok(mut child) = cmd.spawn() {
+                    if let Some(mut stdin) = child.stdin.take() {
+                        let _ = stdin.write_all(text.as_bytes());
+                    }
+                    let _ = child.wait();
+                }
+            }
+        }
+    }
+
+    /// The text on the clipboard, if it can be read.
+    fn paste(&self) -> Option<String> {
+        let Clipboard::Programs { paste, .. } = self else {
+            return None;
+        };
+        let mut cmd = process::Command::new(paste[0]);
+        cmd.args(&paste[1..])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null());
+        prepare_child(&mut cmd, None, JOB_CONTROL_SIGNALS.to_vec());
+        let output = cmd.output().ok().filter(|output| output.status.success())?;
+        Some(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+/// Ctrl-W, Ctrl-U and Ctrl-K with `set -o clipboard`: copies the text the key
+/// kills to the clipboard before the line editor kills it as usual. Ctrl-Y
+/// pastes the clipboard in place of the kill ring, where it can be read.
+struct SyncClipboard {
+    /// Refreshed with the shell's options before each line is read.
+    clipboard: Arc<Mutex<Clipboard>>,
+}
+
+impl ConditionalEventHandler for SyncClipboard {
+    fn handle(
+        &self,
+        event: &Event,
+        n: RepeatCount,
+        positive: bool,
+        ctx: &EventContext,
+    ) -> Option<Cmd> {
+        let clipboard = self.clipboard.lock().ok()?.clone();
+        if clipboard == Clipboard::Off || n != 1 || !positive {
+            return None;
+        }
+        let (line, pos) = (ctx.line(), ctx.pos());
+        let line_start = line[..pos].rfind('\n').map_or(0, |newline| newline + 1);
+        let line_end = line[pos..]
+            .find('\n')
+            .map_or(line.len(), |newline| pos + newline);
+        let (killed, cmd) = match event.get(0)? {
+            // Ctrl-K kills in emacs mode and vi's command mode only.
+            KeyEvent(KeyCode::Char('K'), Modifiers::CTRL)
+                if ctx.mode() == EditMode::Emacs || ctx.input_mode() == InputMode::Command =>
+            {
+                (&line[pos..line_end], Cmd::Kill(Movement::EndOfLine))
+            }
+            KeyEvent(KeyCode::Char('U'), Modifiers::CTRL) => {
+                (&line[line_start..pos], Cmd::Kill(Movement::BeginningOfLine))
+            }
+            KeyEvent(KeyCode::Char('W'), Modifiers::CTRL) => {
+                let start = line[..pos]
+                    .trim_end()
+                    .trim_end_matches(|c: char| !c.is_whitespace())
+                    .len();
+                (
+                    &line[start..pos],
+                    Cmd::Kill(Movement::BackwardWord(1, Word::Big)),
+                )
+            }
+            KeyEvent(KeyCode::Char('Y'), Modifiers::CTRL) => {
+                return clipboard.paste().map(|text| Cmd::Insert(1, text));
+            }
+            _ => return None,
+        };
+        if !killed.is_empty() {
+            clipboard.copy(killed);
+        }
+        Some(cmd)
+    }
+}
+
+/// Follows the vi editing mode's switches between insert and command mode, so
+/// that the prompt can show which one is active. Every key comes here first;
+/// the mode it leads to is worked out from the key and the mode it is pressed
+/// in, and the key is then handled as usual.
+struct TrackViMode {
+    command_mode: Arc<AtomicBool>,
+}
+
+impl ConditionalEventHandler for TrackViMode {
+    fn handle(&self, event: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        if ctx.mode() != EditMode::Vi {
+            return None;
+        }
+        let key = event.get(0).copied();
+        let command_mode = match ctx.input_mode() {
+            InputMode::Command => !matches!(
+                key,
+                Some(KeyEvent(
+                    KeyCode::Char('a' | 'A' | 'c' | 'C' | 'i' | 'I' | 's' | 'S' | 'R'),
+                    Modifiers::NONE
+                )) | Some(KeyEvent(KeyCode::Char('R' | 'S'), Modifiers::CTRL))
+            ),
+            _ => key == Some(KeyEvent(KeyCode::Esc, Modifiers::NONE)),
+        };
+        self.command_mode.store(command_mode, Ordering::Relaxed);
+        None
+    }
+}
+
+/// The line editor functions `bind` can bind keys to, by their readline names.
+const EDITOR_FUNCTIONS: [(&str, Cmd); 31] = [
+    ("abort", Cmd::Abort),
+    ("accept-line", Cmd::AcceptLine),
+    ("backward-char", Cmd::Move(Movement::BackwardChar(1))),
+    ("backward-delete-char", Cmd::Kill(Movement::BackwardChar(1))),
+    ("backward-kill-line", Cmd::Kill(Movement::BeginningOfLine)),
+    (
+        "backward-kill-word",
+        Cmd::Kill(Movement::BackwardWord(1, Word::Emacs)),
+    ),
+    (
+        "backward-word",
+        Cmd::Move(Movement::BackwardWord(1, Word::Emacs)),
+    ),
+    ("beginning-of-history", Cmd::BeginningOfHistory),
+    ("beginning-of-line", Cmd::Move(Movement::BeginningOfLine)),
+    ("capitalize-word", Cmd::CapitalizeWord),
+    ("clear-screen", Cmd::ClearScreen),
+    ("complete", Cmd::Complete),
+    ("delete-char", Cmd::Kill(Movement::ForwardChar(1))),
+    ("downcase-word", Cmd::DowncaseWord),
+    ("end-of-history", Cmd::EndOfHistory),
+    ("end-of-line", Cmd::Move(Movement::EndOfLine)),
+    ("forward-char", Cmd::Move(Movement::ForwardChar(1))),
+    ("forward-search-history", Cmd::ForwardSearchHistory),
+    (
+        "forward-word",
+        Cmd::Move(Movement::ForwardWord(1, At::AfterEnd, Word::Emacs)),
+    ),
+    ("history-search-backward", Cmd::HistorySearchBackward),
+    ("history-search-forward", Cmd::HistorySearchForward),
+    ("kill-line", Cmd::Kill(Movement::EndOfLine)),
+    ("kill-whole-line", Cmd::Kill(Movement::WholeLine)),
+    (
+        "kill-word",
+        Cmd::Kill(Movement::ForwardWord(1, At::AfterEnd, Word::Emacs)),
+    ),
+    ("next-history", Cmd::NextHistory),
+    ("previous-history", Cmd::PreviousHistory),
+    ("quoted-insert", Cmd::QuotedInsert),
+    ("reverse-search-history", Cmd::ReverseSearchHistory),
+    ("transpose-chars", Cmd::TransposeChars),
+    ("undo", Cmd::Undo(1)),
+    ("yank", Cmd::Yank(1, Anchor::Before)),
+];
+
+/// Parses a `bind` key sequence such as `\C-x\C-e`. `\C-` and `\M-` add Control
+/// and Meta to the key after them, `\e` is Escape, which adds Meta to a key
+/// that follows it, and `\t`, `\n`, `\r`, `\a`, `\\`, `\"` and `\'` are as in C.
+pub(crate) fn parse_key_sequence(text: &str) -> std::result::Result<Vec<KeyEvent>, String> {
+    let escape = KeyEvent(KeyCode::Esc, Modifiers::NONE);
+    let mut keys = Vec::new();
+    let (mut control, mut meta) = (false, false);
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next() {
+                Some(modifier @ ('C' | 'M')) if chars.next_if_eq(&'-').is_some() => {
+                    match modifier {
+                        'C' => control = true,
+                        _ => meta = true,
+                    }
+                    continue;
+                }
+                Some('e') => '\x1b',
+                Some('t') => '\t',
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('a') => '\x07',
+                Some(c @ ('\\' | '"' | '\'')) => c,
+                Some(c) => return Err(format!("\\{}: unknown escape", c)),
+                None => return Err(format!("{}: unfinished escape", text)),
+            },
+            c => c,
+        };
+        let c = match mem::take(&mut control) {
+            true if c == '?' => '\x7f',
+            true if c.is_ascii() => (c.to_ascii_uppercase() as u8 & 0x1f) as char,
+            true => return Err(format!("\\C-{}: not a control key", c)),
+            false => c,
+        };
+        let alt = mem::take(&mut meta) || keys.last() == Some(&escape) && keys.pop().is_some();
+        keys.push(KeyEvent::new(
+            c,
+            if alt { Modifiers::ALT } else { Modifiers::NONE },
+        ));
+    }
+    if keys.is_empty() || control || meta {
+        return Err(format!("{}: incomplete key sequence", text));
+    }
+    Ok(keys)
+}
+
+/// Replaces the escapes of the text a key is bound to: `\n`, `\t`, `\r`,
+/// `\a`, `\e`, `\\`, `\"` and `\'`. Others are kept as written.
+fn unescape_bound_text(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('r') => unescaped.push('\r'),
+            Some('a') => unescaped.push('\x07'),
+            Some('e') => unescaped.push('\x1b'),
+            Some(c @ ('\\' | '"' | '\'')) => unescaped.push(c),
+            Some(c) => unescaped.extend(['\\', c]),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Splits a `bind` argument, `"keyseq": function-name` or `"keyseq": "text"`,
+/// into the key sequence and the action, checking both.
+fn parse_binding(arg: &str) -> std::result::Result<(String, String), String> {
+    let invalid = || {
+        format!(
+            "{}: expected \"keyseq\": function-name or \"keyseq\": \"text\"",
+            arg
+        )
+    };
+    let quoted = arg.strip_prefix('"').ok_or_else(invalid)?;
+    let mut end = None;
+    let mut escaped = false;
+    for (i, c) in quoted.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => {
+                end = Some(i);
+                break;
+            }
+            _ => {}
+        }
+    }
+    let end = end.ok_or_else(invalid)?;
+    let keys = &quoted[..end];
+    let action = quoted[end + 1..]
+        .trim_start()
+        .strip_prefix(':')
+        .ok_or_else(invalid)?
+        .trim();
+    parse_key_sequence(keys)?;
+    let is_text = action.len() >= 2 && action.starts_with('"') && action.ends_with('"');
+    if !is_text && !EDITOR_FUNCTIONS.iter().any(|&(name, _)| name == action) {
+        return Err(format!("{}: unknown function name", action));
+    }
+    Ok((keys.to_string(), action.to_string()))
+}
+
+/// Binds keys in the line editor. Each argument binds a key sequence to an
+/// editor function, as `"\C-a": beginning-of-line`, or to text to type, as
+/// `"\C-g": "git status\n"`, where a final newline runs the line. `-p` lists
+/// the bindings in that form, `-l` the function names, and `-r keyseq` removes
+/// a binding.
+pub(crate) fn bind_builtin(
+    bindings: &mut BTreeMap<String, String>,
+    args: &[&str],
+    io: &mut BuiltinIo,
+) -> io::Result<i32> {
+    let mut status = 0;
+    let mut args = args.iter().copied().peekable();
+    while let Some(option) = args.next_if(|arg| arg.starts_with('-') && arg.len() > 1) {
+        match option {
+            "--" => break,
+            "-l" => {
+                for (name, _) in EDITOR_FUNCTIONS {
+                    writeln!(io.stdout, "{}", name)?;
+                }
+            }
+            "-p" => {
+                for (keys, action) in bindings.iter() {
+                    writeln!(io.stdout, "\"{}\": {}", keys, action)?;
+                }
+            }
+            "-r" => {
+                let Some(keys) = args.next() else {
+                    writeln!(io.stderr, "bind: -r: option requires an argument")?;
+                    return Ok(2);
+                };
+                bindings.remove(keys.trim_matches('"'));
+            }
+            _ => {
+                writeln!(io.stderr, "bind: {}: invalid option", option)?;
+                writeln!(
+                    io.stderr,
+                    "bind: usage: bind [-lp] [-r keyseq] \
+                     [keyseq:function-name | keyseq:\"text\" ...]"
+                )?;
+                return Ok(2);
+            }
+        }
+    }
+    for arg in args {
+        match parse_binding(arg) {
+            Ok((keys, action)) => {
+                bindings.insert(keys, action);
+            }
+            Err(message) => {
+                writeln!(io.stderr, "bind: {}", message)?;
+                status = 1;
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// A key bound to text ending in a newline: the text is added to the line at
+/// the cursor and the line is accepted.
+struct RunBoundText {
+    text: String,
+    /// Where the resulting line is left for `read_command`.
+    line: Arc<Mutex<Option<String>>>,
+}
+
+impl ConditionalEventHandler for RunBoundText {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        let (before, after) = ctx.line().split_at(ctx.pos());
+        *self.line.lock().ok()? = Some(format!("{}{}{}", before, self.text, after));
+        Some(Cmd::AcceptLine)
+    }
+}
+
+/// What a key bound to `action` with `bind` does in the line editor.
+fn bound_key_handler(
+    action: &str,
+    macro_line: &Arc<Mutex<Option<String>>>,
+) -> Option<EventHandler> {
+    let Some(text) = action
+        .strip_prefix('"')
+        .and_then(|action| action.strip_suffix('"'))
+    else {
+        let (_, cmd) = EDITOR_FUNCTIONS.iter().find(|&&(name, _)| name == action)?;
+        return Some(EventHandler::Simple(cmd.clone()));
+    };
+    let text = unescape_bound_text(text);
+    Some(match text.strip_suffix('\n') {
+        Some(text) => EventHandler::Conditional(Box::new(RunBoundText {
+            text: text.to_string(),
+            line: Arc::clone(macro_line),
+        })),
+        None => EventHandler::Simple(Cmd::Insert(1, text)),
+    })
+}
+
+/// Binds the keys the shell adds to the line editor's own: Ctrl-X Ctrl-E to
+/// edit the line in an editor, the kill and yank keys to the clipboard, and
+/// every key to following the vi mode.
+fn bind_default_keys(
+    rl: &mut Editor<ShellCompleter, DefaultHistory>,
+    line_editor: &Arc<Mutex<String>>,
+    clipboard: &Arc<Mutex<Clipboard>>,
+    vi_command_mode: &Arc<AtomicBool>,
+) {
+    rl.bind_sequence(
+        Event::KeySeq(vec![KeyEvent::ctrl('X'), KeyEvent::ctrl('E')]),
+        EventHandler::Conditional(Box::new(EditLineInEditor {
+            editor: Arc::clone(line_editor),
+        })),
+    );
+    for key in ['W', 'U', 'K', 'Y'] {
+        rl.bind_sequence(
+            KeyEvent::ctrl(key),
+            EventHandler::Conditional(Box::new(SyncClipboard {
+                clipboard: Arc::clone(clipboard),
+            })),
+        );
+    }
+    rl.bind_sequence(
+        Event::Any,
+        EventHandler::Conditional(Box::new(TrackViMode {
+            command_mode: Arc::clone(vi_command_mode),
+        })),
+    );
+}
+
+/// Replaces the line editor's history with the shell's copy after `history`
+/// changed it. The history file was saved from that copy, so it is loaded from
+/// there; that also tells the editor that no entries are left to append.
+fn reload_history(
+    rl: &mut Editor<ShellCompleter, DefaultHistory>,
+    shell: &Shell,
+    path: Option<&Path>,
+) -> Result<()> {
+    rl.clear_history()?;
+    match path {
+        Some(path) if path.exists() => rl.load_history(path),
+        _ => {
+            for entry in &shell.history {
+                rl.add_history_entry(entry.as_str())?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Reads commands with the line editor until `exit` or end of input and returns
+/// the status the shell should exit with. Unless `read_rc` is false, the rc
+/// file is run first.
+pub fn run_interactive(shell: &mut Shell, read_rc: bool) -> Result<i32> {
+    let config = Config::builder()
+        .completion_type(CompletionType::List)
+        .max_history_size(history_size())?
+        .history_ignore_dups(true)?
+        .build();
+
+    let helper = ShellCompleter::default();
+    let vi_command_mode = Arc::clone(&helper.vi_command_mode);
+    let macro_line = Arc::clone(&helper.macro_line);
+    let mut rl: Editor<ShellCompleter, DefaultHistory> = Editor::with_config(config)?;
+    rl.set_helper(Some(helper));
+    let line_editor = Arc::new(Mutex::new(String::new()));
+    let clipboard = Arc::new(Mutex::new(Clipboard::Off));
+    bind_default_keys(&mut rl, &line_editor, &clipboard, &vi_command_mode);
+    // The `bind` bindings the line editor has, to notice when they change.
+    let mut bound_keys = BTreeMap::new();
+
+    let history_path = history_file_path();
+    if let Some(path) = &history_path
+        && path.exists()
+        && let Err(e) = rl.load_history(path)
+    {
+        eprintln!("history: {}: {}", path.display(), e);
+    }
+    shell.history = rl.history().iter().cloned().collect();
+
+    shell.interactive = true;
+    shell.options.emacs = true;
+    shell.terminal = init_job_control();
+    watch_window_size();
+    // Jobs that finish while a command is being typed are reported right
+    // away, above the line being edited.
+    let notifier = match rl.create_external_printer() {
+        Ok(mut printer) => JobNotifier::start(move |notice| {
+            let _ = printer.print(notice);
+        })
+        .ok(),
+        Err(_) => None,
+    };
+
+    if read_rc && let Some(path) = rc_file_path(shell) {
+        run_startup_file(shell, &path);
+        if shell.exit_requested {
+            return Ok(shell.last_status);
+        }
+    }
+
+    // With `set -o shellintegration`, the directory last reported to the
+    // terminal.
+    let mut reported_directory = None;
+    // The working directory at the last prompt.
+    let mut previous_directory = None;
+    // How long the last command took to run, also kept in `LAST_DURATION`.
+    let mut duration = Duration::ZERO;
+    // The last line with the spelling corrections chosen for editing, to
+    // start the next one with.
+    let mut edited_line = String::new();
+    shell.corrections_to_edit.clear();
+    loop {
+        if CHILD_STATUS_CHANGED.swap(false, Ordering::SeqCst) {
+            shell.jobs.reap();
+        }
+        shell.jobs.notify_done();
+        let cwd = working_directory(&shell.vars);
+        if previous_directory.as_ref() != Some(&cwd) {
+            // The new directory's environment is in place before `chpwd`.
+            shell.update_env_file();
+            if previous_directory.is_some() {
+                shell.run_hook("chpwd", &[]);
+            }
+            if let Some(path) = data_file_path()
+                && let Err(e) = record_visit(&path, &cwd, shell.vars.get("HOME"))
+            {
+                eprintln!("z: {}: {}", path.display(), describe_io_error(&e));
+            }
+            previous_directory = Some(cwd);
+        }
+        run_prompt_hooks(shell);
+        if shell.exit_requested {
+            break;
+        }
+        if let Some(title) = window_title(shell, None) {
+            print!("{}", title);
+        }
+        if shell.options.shellintegration {
+            // The prompt is drawn by the line editor, which would count
+            // markers inside it as text, so they are written around it.
+            let cwd = working_directory(&shell.vars);
+            if reported_directory.as_ref() != Some(&cwd) {
+                print!("{}", report_directory(&cwd));
+                reported_directory = Some(cwd);
+            }
+            print!("\x1b]133;A\x07");
+        } else {
+            reported_directory = None;
+        }
+        let _ = io::stdout().flush();
+        if WINDOW_RESIZED.swap(false, Ordering::SeqCst)
+            && let Some((columns, lines)) = shell.terminal.and_then(window_size)
+        {
+            let _ = shell.vars.set("COLUMNS", columns.to_string());
+            let _ = shell.vars.set("LINES", lines.to_string());
+        }
+
+        if let Some(helper) = rl.helper_mut() {
+            helper.variables = shell.vars.iter().map(|(name, _)| name.clone()).collect();
+            helper.variables.sort();
+            helper.path_var = shell.vars.get("PATH").unwrap_or_default().to_string();
+            helper.ls_colors = shell.vars.get("LS_COLORS").unwrap_or_default().to_string();
+            helper.completion_specs = shell.completion_specs.clone();
+            helper.fuzzy = shell.options.fuzzycomplete;
+            helper.jobs = (shell.jobs.jobs.iter())
+                .map(|job| {
+                    (
+                        format!("%{}", job.id),
+                        format!("{}  {}", job.state_label(), job.command),
+                    )
+                })
+                .collect();
+            helper.aliases = shell.aliases.keys().cloned().collect();
+        }
+        rl.set_edit_mode(if shell.options.vi {
+            EditMode::Vi
+        } else {
+            EditMode::Emacs
+        });
+        if shell.key_bindings != bound_keys {
+            for keys in bound_keys.keys() {
+                if let Ok(keys) = parse_key_sequence(keys) {
+                    rl.unbind_sequence(Event::KeySeq(keys));
+                }
+            }
+            bind_default_keys(&mut rl, &line_editor, &clipboard, &vi_command_mode);
+            for (keys, action) in &shell.key_bindings {
+                if let Ok(keys) = parse_key_sequence(keys)
+                    && let Some(handler) = bound_key_handler(action, &macro_line)
+                {
+                    rl.bind_sequence(Event::KeySeq(keys), handler);
+                }
+            }
+            bound_keys = shell.key_bindings.clone();
+        }
+        rl.set_completion_type(if shell.options.menucomplete {
+            CompletionType::Circular
+        } else {
+            CompletionType::List
+        });
+        if let Ok(mut editor) = line_editor.lock() {
+            *editor = editor_command(&shell.vars, &["VISUAL", "EDITOR"]);
+        }
+        if let Ok(mut clipboard) = clipboard.lock() {
+            *clipboard = Clipboard::for_shell(shell);
+        }
+        let prompt = match shell
+            .vars
+            .get("PROMPT_COMMAND_OUTPUT")
+            .filter(|command| !command.is_empty())
+        {
+            Some(command) => {
+                run_prompt_program(command, shell.last_status, duration, shell.jobs.jobs.len())
+            }
+            None => None,
+        };
+        let mut prompt = prompt.unwrap_or_else(|| {
+            expand_prompt(shell.vars.get("PS1").unwrap_or(DEFAULT_PROMPT), shell)
+        });
+        let mut continuation = expand_prompt(
+            shell.vars.get("PS2").unwrap_or(DEFAULT_CONTINUATION_PROMPT),
+            shell,
+        );
+        if shell.options.vi {
+            prompt.insert_str(0, VI_INSERT_INDICATOR);
+            continuation.insert_str(0, VI_INSERT_INDICATOR);
+        }
+        if let Some(notifier) = &notifier {
+            notifier.lend(&mut shell.jobs);
+        }
+        let readline = read_command(
+            &mut rl,
+            &prompt,
+            &continuation,
+            &mem::take(&mut edited_line),
+        );
+        if let Some(notifier) = &notifier {
+            notifier.take_back(&mut shell.jobs);
+        }
+        match readline {
+            Ok(None) => shell.last_status = 2,
+            Ok(Some(line)) => {
+                let line = match expand_history(&line, rl.history()) {
+                    Ok(Some(expanded)) => {
+                        println!("{}", expanded);
+                        expanded
+                    }
+                    Ok(None) => line,
+                    Err(message) => {
+                        eprintln!("{}", message);
+                        shell.last_status = 1;
+                        continue;
+                    }
+                };
+                if rl.add_history_entry(line.as_str())? {
+                    shell.history.push(line.clone());
+                    let excess = shell.history.len().saturating_sub(history_size());
+                    shell.history.drain(..excess);
+                }
+                if let Some(path) = &history_path
+                    && let Err(e) = rl.append_history(path)
+                {
+                    eprintln!("history: {}: {}", path.display(), e);
+                }
+                // A second `exit` only leaves despite stopped jobs right after
+                // the first.
+                let warned = shell.stopped_jobs_warned;
+                shell.run_hook("preexec", slice::from_ref(&line));
+                if shell.exit_requested {
+                    break;
+                }
+                if let Some(title) = window_title(shell, Some(&line)) {
+                    print!("{}", title);
+                    let _ = io::stdout().flush();
+                }
+                if shell.options.shellintegration {
+                    print!("\x1b]133;C\x07");
+                    let _ = io::stdout().flush();
+                }
+                let started = Instant::now();
+                shell.run_line(&line);
+                let corrections = mem::take(&mut shell.corrections_to_edit);
+                if !corrections.is_empty() {
+                    edited_line = (corrections.iter())
+                        .fold(line.clone(), |line, (word, correction)| {
+                            line.replacen(word.as_str(), correction, 1)
+                        });
+                }
+                if !line.trim().is_empty() {
+                    duration = started.elapsed();
+                    let _ = shell
+                        .vars
+                        .set("LAST_DURATION", format!("{:.3}", duration.as_secs_f64()));
+                    report_time(shell, &line, duration);
+                }
+                if shell.options.shellintegration {
+                    print!("\x1b]133;D;{}\x07", shell.last_status);
+                    let _ = io::stdout().flush();
+                }
+                // A resize while a job had the terminal was signalled to the
+                // job rather than the shell.
+                WINDOW_RESIZED.store(true, Ordering::SeqCst);
+                if warned {
+                    shell.stopped_jobs_warned = false;
+                }
+                if mem::take(&mut shell.history_changed) {
+                    reload_history(&mut rl, shell, history_path.as_deref())?;
+                }
+                if shell.exit_requested {
+                    break;
+                }
+            }
+            Err(ReadlineError::Interrupted) => {
+                println!("^C");
+                shell.last_status = 130;
+                continue;
+            }
+            Err(ReadlineError::Eof) => {
+                println!("^D");
+                shell.jobs.reap();
+                if shell.jobs.has_stopped() && !mem::replace(&mut shell.stopped_jobs_warned, true) {
+                    eprintln!("There are stopped jobs.");
+                    continue;
+                }
+                break;
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                break;
+            }
+        }
+    }
+    shell.jobs.hang_up();
+    Ok(shell.last_status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(entries: &[&str]) -> DefaultHistory {
+        let mut history = DefaultHistory::new();
+        for entry in entries {
+            history.add(entry).unwrap();
+        }
+        history
+    }
+
+    fn expand(line: &str) -> std::result::Result<Option<String>, String> {
+        expand_history(line, &history(&["git status", "ls -l", "echo a 'b c'"]))
+    }
+
+    #[test]
+    fn expands_event_designators() {
+        assert_eq!(expand("sudo !!"), Ok(Some("sudo echo a 'b c'".to_string())));
+        assert_eq!(
+            expand("!1 && !2"),
+            Ok(Some("git status && ls -l".to_string()))
+        );
+        assert_eq!(expand("!-2"), Ok(Some("ls -l".to_string())));
+        assert_eq!(expand("!gi; !l"), Ok(Some("git status; ls -l".to_string())));
+        assert_eq!(expand("cat !$"), Ok(Some("cat 'b c'".to_string())));
+        assert_eq!(expand("echo none"), Ok(None));
+    }
+
+    #[test]
+    fn leaves_quoted_and_escaped_bangs_alone() {
+        assert_eq!(expand("echo '!!' \\!! !"), Ok(None));
+        assert_eq!(
+            expand("echo \"!!\""),
+            Ok(Some("echo \"echo a 'b c'\"".to_string()))
+        );
+    }
+
+    #[test]
+    fn reports_missing_events() {
+        assert_eq!(
+            expand("!nothing"),
+            Err("!nothing: event not found".to_string())
+        );
+        assert_eq!(expand("!9"), Err("!9: event not found".to_string()));
+        assert_eq!(expand("!-4"), Err("!-4: event not found".to_string()));
+        assert_eq!(
+            expand_history("!!", &history(&[])),
+            Err("!!: event not found".to_string())
+        );
+        assert_eq!(
+            expand_history("!$", &history(&[])),
+            Err("!$: event not found".to_string())
+        );
+    }
+
+    #[test]
+    fn parses_key_sequences() {
+        assert_eq!(
+            parse_key_sequence("\\C-x\\C-e"),
+            Ok(vec![KeyEvent::ctrl('X'), KeyEvent::ctrl('E')])
+        );
+        assert_eq!(parse_key_sequence("\\ef"), Ok(vec![KeyEvent::alt('f')]));
+        assert_eq!(parse_key_sequence("\\M-b"), Ok(vec![KeyEvent::alt('b')]));
+        assert_eq!(
+            parse_key_sequence("\\e"),
+            Ok(vec![KeyEvent(KeyCode::Esc, Modifiers::NONE)])
+        );
+        assert_eq!(
+            parse_key_sequence("\\C-?"),
+            Ok(vec![KeyEvent::new('\x7f', Modifiers::NONE)])
+        );
+        assert_eq!(
+            parse_key_sequence("a\\t"),
+            Ok(vec![
+                KeyEvent::from('a'),
+                KeyEvent::new('\t', Modifiers::NONE)
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_incomplete_key_sequences() {
+        assert!(parse_key_sequence("").is_err());
+        assert!(parse_key_sequence("\\C-").is_err());
+        assert!(parse_key_sequence("\\M-").is_err());
+        assert!(parse_key_sequence("a\\").is_err());
+        assert!(parse_key_sequence("\\q").is_err());
+        assert!(parse_key_sequence("\\C-é").is_err());
+    }
+
+    #[test]
+    fn parses_bindings_to_functions_and_text() {
+        assert_eq!(
+            parse_binding("\"\\C-a\": beginning-of-line"),
+            Ok(("\\C-a".to_string(), "beginning-of-line".to_string()))
+        );
+        assert_eq!(
+            parse_binding("\"\\C-g\" : \"git status\\n\""),
+            Ok(("\\C-g".to_string(), "\"git status\\n\"".to_string()))
+        );
+        assert_eq!(
+            parse_binding("\"\\\"\": self-insert"),
+            Err("self-insert: unknown function name".to_string())
+        );
+        assert!(parse_binding("\\C-a: undo").is_err());
+        assert!(parse_binding("\"\\C-a undo").is_err());
+        assert!(parse_binding("\"\\C-a\" undo").is_err());
+        assert!(parse_binding("\"\\C-\": undo").is_err());
+    }
+}
@@ -0,0 +1,624 @@
+//! Job control and signal handling.
+
+use std::io::{self, Read};
+use std::mem;
+use std::os::fd::{IntoRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use nix::errno::Errno;
+use nix::libc;
+use nix::sys::signal::{SaFlags, SigAction, SigHandler, SigSet, Signal, killpg, sigaction, signal};
+use nix::sys::wait::{WaitPidFlag, WaitStatus, waitpid};
+use nix::unistd::{Pid, getpgrp, isatty, setpgid, tcsetpgrp};
+
+/// Converts a `waitpid` result into a shell status code, if the process has
+/// terminated.
+fn wait_status_code(status: WaitStatus) -> Option<i32> {
+    match status {
+        WaitStatus::Exited(_, code) => Some(code),
+        WaitStatus::Signaled(_, signal, _) => Some(128 + signal as i32),
+        _ => None,
+    }
+}
+
+/// How waiting for a job ended.
+#[derive(Debug)]
+pub(crate) enum JobWait {
+    /// Every process terminated; holds the status of the last one.
+    Finished(i32),
+    /// The job was stopped, e.g. by Ctrl-Z. Holds the processes that have not
+    /// terminated and the status of the last process if it already did.
+    Stopped {
+        remaining: Vec<Pid>,
+        last_status: i32,
+    },
+}
+
+/// Blocks until every process in `pids` has terminated and returns the status of
+/// `last_pid`, or `default` if it is not among them. With `pipefail`, the status
+/// of the last process to fail takes precedence. With `untraced`, waiting also
+/// ends as soon as one of the processes is stopped.
+pub(crate) fn wait_for_pids(
+    pids: &[Pid],
+    last_pid: Pid,
+    default: i32,
+    pipefail: bool,
+    untraced: bool,
+) -> JobWait {
+    let flags = untraced.then_some(WaitPidFlag::WUNTRACED);
+    let mut last_status = default;
+    let mut failed_status = None;
+    for (i, &pid) in pids.iter().enumerate() {
+        loop {
+            match waitpid(pid, flags) {
+                Ok(WaitStatus::Stopped(..)) => {
+                    return JobWait::Stopped {
+                        remaining: pids[i..].to_vec(),
+                        last_status,
+                    };
+                }
+                Ok(status) => {
+                    if let Some(code) = wait_status_code(status) {
+                        if pid == last_pid {
+                            last_status = code;
+                        }
+                        if code != 0 {
+                            failed_status = Some(code);
+                        }
+                        break;
+                    }
+                }
+                Err(Errno::EINTR) => continue,
+                Err(_) => break,
+            }
+        }
+    }
+    match failed_status {
+        Some(status) if pipefail => JobWait::Finished(status),
+        _ => JobWait::Finished(last_status),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum JobState {
+    Running,
+    Stopped,
+    Done(i32),
+}
+
+#[derive(Debug)]
+pub(crate) struct Job {
+    pub(crate) id: usize,
+    pub(crate) pgid: Pid,
+    /// Processes of the job that have not been reaped yet.
+    pub(crate) pids: Vec<Pid>,
+    pub(crate) last_pid: Pid,
+    pub(crate) last_status: i32,
+    pub(crate) command: String,
+    pub(crate) state: JobState,
+    /// Set by `disown -h` so that the job is not sent SIGHUP when the shell
+    /// exits.
+    pub(crate) no_hangup: bool,
+}
+
+impl Job {
+    pub(crate) fn state_label(&self) -> String {
+        match self.state {
+            JobState::Running => "Running".to_string(),
+            JobState::Stopped => "Stopped".to_string(),
+            JobState::Done(0) => "Done".to_string(),
+            JobState::Done(code) => format!("Exit {}", code),
+        }
+    }
+}
+
+/// How many finished jobs `wait` can still report the status of after they
+/// have left the job table.
+const FINISHED_JOB_LIMIT: usize = 64;
+
+#[derive(Debug, Default)]
+pub(crate) struct JobTable {
+    pub(crate) jobs: Vec<Job>,
+    /// The last process and status of jobs that finished and were dropped from
+    /// the table, most recent last, for `wait PID`.
+    pub(crate) finished: Vec<(Pid, i32)>,
+}
+
+impl JobTable {
+    pub(crate) fn next_id(&self) -> usize {
+        self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1
+    }
+
+    pub(crate) fn add(&mut self, pgid: Pid, pids: Vec<Pid>, command: String) -> usize {
+        let id = self.next_id();
+        let last_pid = *pids.last().unwrap_or(&pgid);
+        self.jobs.push(Job {
+            id,
+            pgid,
+            pids,
+            last_pid,
+            last_status: 0,
+            command,
+            state: JobState::Running,
+            no_hangup: false,
+        });
+        id
+    }
+
+    pub(crate) fn has_stopped(&self) -> bool {
+        self.jobs.iter().any(|job| job.state == JobState::Stopped)
+    }
+
+    /// Sends SIGHUP to every job not marked with `disown -h`, as the shell exits,
+    /// and SIGCONT to stopped ones so that they can act on it. Programs started
+    /// with `nohup` ignore it and keep running.
+    pub(crate) fn hang_up(&mut self) {
+        self.reap();
+        for job in self
+            .jobs
+            .iter()
+            .filter(|job| !job.no_hangup && !matches!(job.state, JobState::Done(_)))
+        {
+            let _ = killpg(job.pgid, Signal::SIGHUP);
+            if job.state == JobState::Stopped {
+                let _ = killpg(job.pgid, Signal::SIGCONT);
+            }
+        }
+    }
+
+    /// Records that a foreground job was stopped, makes it the current job and
+    /// reports it.
+    pub(crate) fn stopped_in_foreground(
+        &mut self,
+        mut job: Job,
+        remaining: Vec<Pid>,
+        last_status: i32,
+    ) {
+        job.pids = remaining;
+        job.last_status = last_status;
+        job.state = JobState::Stopped;
+        println!();
+        println!("{}", format_job(&job, Some(job.id)));
+        self.jobs.push(job);
+    }
+
+    /// Records a status change reported by `waitpid` for one of the job processes.
+    fn update(&mut self, status: WaitStatus) {
+        let Some(pid) = status.pid() else {
+            return;
+        };
+        let Some(job) = self.jobs.iter_mut().find(|job| job.pids.contains(&pid)) else {
+            return;
+        };
+        match status {
+            WaitStatus::Stopped(..) => job.state = JobState::Stopped,
+            WaitStatus::Continued(_) => job.state = JobState::Running,
+            _ => {
+                if let Some(code) = wait_status_code(status) {
+                    job.pids.retain(|&p| p != pid);
+                    if pid == job.last_pid {
+                        job.last_status = code;
+                    }
+                    if job.pids.is_empty() {
+                        job.state = JobState::Done(job.last_status);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Collects status changes of background children without blocking.
+    pub(crate) fn reap(&mut self) {
+        let flags = WaitPidFlag::WNOHANG | WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED;
+        loop {
+            match waitpid(Pid::from_raw(-1), Some(flags)) {
+                Ok(WaitStatus::StillAlive) | Err(_) => break,
+                Ok(status) => self.update(status),
+            }
+        }
+    }
+
+    /// Collects status changes of the job processes without blocking, leaving
+    /// other children alone, such as an editor the line editor is waiting for.
+    fn reap_jobs(&mut self) {
+        let flags = WaitPidFlag::WNOHANG | WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED;
+        let pids: Vec<Pid> = self
+            .jobs
+            .iter()
+            .flat_map(|job| job.pids.iter().copied())
+            .collect();
+        for pid in pids {
+            loop {
+                match waitpid(pid, Some(flags)) {
+                    Ok(WaitStatus::StillAlive) | Err(_) => break,
+                    Ok(status) => self.update(status),
+                }
+            }
+        }
+    }
+
+    /// Prints a notification for every finished job and drops it from the table.
+    pub(crate) fn notify_done(&mut self) {
+        for notice in self.done_notices() {
+            println!("{}", notice);
+        }
+    }
+
+    /// Formats a notification for every finished job and drops it from the table.
+    fn done_notices(&mut self) -> Vec<String> {
+        let current = self.current_id();
+        let notices = (self.jobs.iter())
+            .filter(|job| matches!(job.state, JobState::Done(_)))
+            .map(|job| format_job(job, current))
+            .collect();
+        self.forget_done();
+        notices
+    }
+
+    /// Drops every finished job from the table, remembering its status.
+    pub(crate) fn forget_done(&mut self) {
+        while let Some(index) = self
+            .jobs
+            .iter()
+            .position(|job| matches!(job.state, JobState::Done(_)))
+        {
+            self.take(index);
+        }
+    }
+
+    /// Removes a job from the table and returns the status of its last process.
+    /// If it finished, the status is remembered for a later `wait PID`.
+    pub(crate) fn take(&mut self, index: usize) -> i32 {
+        let job = self.jobs.remove(index);
+        if matches!(job.state, JobState::Done(_)) {
+            if self.finished.len() == FINISHED_JOB_LIMIT {
+                self.finished.remove(0);
+            }
+            self.finished.push((job.last_pid, job.last_status));
+        }
+        job.last_status
+    }
+
+    /// Blocks until `pid` terminates and returns its status, or `None` if it is
+    /// not a child that can be waited for.
+    pub(crate) fn wait_for_pid(&mut self, pid: Pid) -> Option<i32> {
+        loop {
+            match waitpid(pid, None) {
+                Ok(status) => {
+                    self.update(status);
+                    if let Some(code) = wait_status_code(status) {
+                        return Some(code);
+                    }
+                }
+                Err(Errno::EINTR) => continue,
+                Err(_) => return None,
+            }
+        }
+    }
+
+    /// Blocks until every process of the job at `index` terminates, then removes
+    /// the job and returns its status.
+    pub(crate) fn wait_for_job(&mut self, index: usize) -> i32 {
+        for pid in self.jobs[index].pids.clone() {
+            self.wait_for_pid(pid);
+        }
+        let job = &mut self.jobs[index];
+        job.pids.clear();
+        job.state = JobState::Done(job.last_status);
+        self.take(index)
+    }
+
+    /// Blocks until some running job finishes, removes it and returns its
+    /// status. Returns `None` if no job is left to wait for.
+    pub(crate) fn wait_for_next(&mut self) -> Option<i32> {
+        loop {
+            if let Some(index) = self
+                .jobs
+                .iter()
+                .position(|job| matches!(job.state, JobState::Done(_)))
+            {
+                return Some(self.take(index));
+            }
+            if !self.jobs.iter().any(|job| job.state == JobState::Running) {
+                return None;
+            }
+            match waitpid(Pid::from_raw(-1), None) {
+                Ok(status) => self.update(status),
+                Err(Errno::EINTR) => continue,
+                Err(_) => return None,
+            }
+        }
+    }
+
+    pub(crate) fn current_id(&self) -> Option<usize> {
+        self.jobs.last().map(|job| job.id)
+    }
+
+    fn previous_id(&self) -> Option<usize> {
+        self.jobs.iter().rev().nth(1).map(|job| job.id)
+    }
+
+    /// Resolves a job spec (`%1`, `%%`, `%+`, `%-`, `1`) to an index in the table.
+    /// Without a spec the current job is used.
+    pub(crate) fn resolve(&self, spec: Option<&str>) -> std::result::Result<usize, String> {
+        let id = match spec {
+            None | Some("%%") | Some("%+") | Some("%") => self.current_id(),
+            Some("%-") => self.previous_id(),
+            Some(spec) => spec.trim_start_matches('%').parse::<usize>().ok(),
+        };
+        id.and_then(|id| self.jobs.iter().position(|job| job.id == id))
+            .ok_or_else(|| format!("{}: no such job", spec.unwrap_or("current")))
+    }
+}
+
+pub(crate) fn format_job(job: &Job, current: Option<usize>) -> String {
+    let marker = if Some(job.id) == current { '+' } else { ' ' };
+    let suffix = if job.state == JobState::Running {
+        " &"
+    } else {
+        ""
+    };
+    format!(
+        "[{}]{}  {:<24}{}{}",
+        job.id,
+        marker,
+        job.state_label(),
+        job.command,
+        suffix
+    )
+}
+
+pub(crate) static CHILD_STATUS_CHANGED: AtomicBool = AtomicBool::new(false);
+
+/// The write end of a pipe that SIGCHLD deliveries are signalled on while a
+/// `JobNotifier` runs, or -1.
+static SIGCHLD_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+pub(crate) extern "C" fn handle_sigchld(_: libc::c_int) {
+    CHILD_STATUS_CHANGED.store(true, Ordering::SeqCst);
+    let pipe = SIGCHLD_PIPE.load(Ordering::SeqCst);
+    if pipe >= 0 {
+        unsafe {
+            libc::write(pipe, b"c".as_ptr().cast(), 1);
+        }
+    }
+}
+
+/// Reports jobs that finish while the prompt is shown. The job table is lent
+/// to a thread for as long as the line editor waits for input; the thread
+/// reaps the jobs whenever SIGCHLD arrives and hands the notices to `print`,
+/// which shows them above the line being edited.
+pub(crate) struct JobNotifier {
+    lent: Arc<Mutex<Option<JobTable>>>,
+}
+
+impl JobNotifier {
+    pub(crate) fn start(mut print: impl FnMut(String) + Send + 'static) -> io::Result<Self> {
+        let (mut reader, writer) = io::pipe()?;
+        let writer = OwnedFd::from(writer).into_raw_fd();
+        // The signal handler must never block on a full pipe.
+        unsafe {
+            libc::fcntl(writer, libc::F_SETFL, libc::O_NONBLOCK);
+        }
+        SIGCHLD_PIPE.store(writer, Ordering::SeqCst);
+        let lent = Arc::new(Mutex::new(None::<JobTable>));
+        let table = Arc::clone(&lent);
+        thread::spawn(move || {
+            let mut buffer = [0; 64];
+            loop {
+                match reader.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(_) => {}
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                }
+                let Ok(mut table) = table.lock() else {
+                    break;
+                };
+                if let Some(jobs) = table.as_mut() {
+                    jobs.reap_jobs();
+                    for notice in jobs.done_notices() {
+                        print(notice);
+                    }
+                }
+            }
+        });
+        Ok(JobNotifier { lent })
+    }
+
+    /// Hands the job table to the notifier thread while the prompt is shown.
+    pub(crate) fn lend(&self, jobs: &mut JobTable) {
+        if let Ok(mut lent) = self.lent.lock() {
+            *lent = Some(mem::take(jobs));
+        }
+    }
+
+    /// Takes the job table back before a command runs.
+    pub(crate) fn take_back(&self, jobs: &mut JobTable) {
+        if let Ok(mut lent) = self.lent.lock()
+            && let Some(table) = lent.take()
+        {
+            *jobs = table;
+        }
+    }
+}
+
+/// Set when the terminal window has been resized.
+pub(crate) static WINDOW_RESIZED: AtomicBool = AtomicBool::new(true);
+
+/// The SIGWINCH handler that was in place before `watch_window_size`, which the
+/// line editor relies on to redraw the line being edited, or 0.
+static PREVIOUS_SIGWINCH: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn handle_sigwinch(sig: libc::c_int) {
+    WINDOW_RESIZED.store(true, Ordering::SeqCst);
+    let previous = PREVIOUS_SIGWINCH.load(Ordering::SeqCst);
+    if previous != 0 {
+        let previous: extern "C" fn(libc::c_int) = unsafe { mem::transmute(previous) };
+        previous(sig);
+    }
+}
+
+/// Notes window resizes in `WINDOW_RESIZED`, passing SIGWINCH on to the
+/// handler already installed.
+pub(crate) fn watch_window_size() {
+    let action = SigAction::new(
+        SigHandler::Handler(handle_sigwinch),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    if let Ok(previous) = unsafe { sigaction(Signal::SIGWINCH, &action) }
+        && let SigHandler::Handler(handler) = previous.handler()
+    {
+        PREVIOUS_SIGWINCH.store(handler as usize, Ordering::SeqCst);
+    }
+}
+
+/// The number of columns and rows of the terminal `fd`.
+pub(crate) fn window_size(fd: RawFd) -> Option<(u16, u16)> {
+    let mut size: libc::winsize = unsafe { mem::zeroed() };
+    let result = unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) };
+    (result == 0 && size.ws_col > 0).then_some((size.ws_col, size.ws_row))
+}
+
+/// One past the highest signal number.
+pub(crate) const SIGNAL_LIMIT: usize = 65;
+
+/// Signals caught by a trap that have arrived but whose action has not run yet,
+/// indexed by signal number.
+pub(crate) static PENDING_TRAPS: [AtomicBool; SIGNAL_LIMIT] =
+    [const { AtomicBool::new(false) }; SIGNAL_LIMIT];
+
+pub(crate) extern "C" fn record_trapped_signal(sig: libc::c_int) {
+    if let Some(pending) = PENDING_TRAPS.get(sig as usize) {
+        pending.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Parses a signal for `trap` and `kill`: a name with or without the `SIG`
+/// prefix, in any case, a number, or `EXIT` (0).
+pub(crate) fn parse_signal_spec(spec: &str) -> Option<i32> {
+    if let Ok(number) = spec.parse::<i32>() {
+        return (number == 0 || Signal::try_from(number).is_ok()).then_some(number);
+    }
+    let name = spec.to_ascii_uppercase();
+    if name == "EXIT" {
+        return Some(0);
+    }
+    let name = if name.starts_with("SIG") {
+        name
+    } else {
+        format!("SIG{}", name)
+    };
+    name.parse::<Signal>().ok().map(|signal| signal as i32)
+}
+
+/// The name of a signal number as `trap` shows it.
+pub(crate) fn signal_name(number: i32) -> String {
+    match Signal::try_from(number) {
+        Ok(signal) => signal.as_str().to_string(),
+        Err(_) if number == 0 => "EXIT".to_string(),
+        Err(_) => number.to_string(),
+    }
+}
+
+/// Signals an interactive shell ignores for itself, which children must get back
+/// with their default disposition since ignored signals survive `exec`.
+pub(crate) const JOB_CONTROL_SIGNALS: [Signal; 5] = [
+    Signal::SIGINT,
+    Signal::SIGQUIT,
+    Signal::SIGTSTP,
+    Signal::SIGTTIN,
+    Signal::SIGTTOU,
+];
+
+/// Prepares the shell for job control: notes SIGCHLD deliveries so finished jobs
+/// can be reported, puts the shell in its own process group in the foreground,
+/// ignores SIGTTOU/SIGTTIN so it can take the terminal back from its jobs, and
+/// ignores SIGTSTP so Ctrl-Z only stops the foreground job.
+/// Returns a private descriptor for the terminal, or `None` if stdin is not one.
+pub(crate) fn init_job_control() -> Option<RawFd> {
+    let action = SigAction::new(
+        SigHandler::Handler(handle_sigchld),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    unsafe {
+        let _ = sigaction(Signal::SIGCHLD, &action);
+    }
+    if !isatty(io::stdin()).unwrap_or(false) {
+        return None;
+    }
+    unsafe {
+        let _ = signal(Signal::SIGQUIT, SigHandler::SigIgn);
+        let _ = signal(Signal::SIGTSTP, SigHandler::SigIgn);
+        let _ = signal(Signal::SIGTTIN, SigHandler::SigIgn);
+        let _ = signal(Signal::SIGTTOU, SigHandler::SigIgn);
+    }
+    let _ = setpgid(Pid::from_raw(0), Pid::from_raw(0));
+    give_terminal_to(getpgrp());
+
+    let terminal = unsafe { libc::fcntl(libc::STDIN_FILENO, libc::F_DUPFD_CLOEXEC, 10) };
+    (terminal >= 0).then_some(terminal)
+}
+
+/// Sets up a child before it executes: a foreground child takes the terminal for
+/// its process group (so it never races the shell to read from it), and the
+/// signals in `reset`, which the shell ignores for itself, get their default
+/// handling back.
+pub(crate) fn prepare_child(
+    cmd: &mut process::Command,
+    foreground_terminal: Option<RawFd>,
+    reset: Vec<Signal>,
+) {
+    unsafe {
+        cmd.pre_exec(move || {
+            if let Some(terminal) = foreground_terminal {
+                libc::tcsetpgrp(terminal, libc::getpgrp());
+            }
+            for &sig in &reset {
+                let _ = signal(sig, SigHandler::SigDfl);
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Hands the controlling terminal to `pgid` when the shell is attached to one.
+pub(crate) fn give_terminal_to(pgid: Pid) {
+    if isatty(io::stdin()).unwrap_or(false) {
+        let _ = tcsetpgrp(io::stdin(), pgid);
+    }
+}
+
+/// Waits for a job that owns the terminal, with the shell ignoring SIGINT so that
+/// Ctrl-C only reaches the job, then takes the terminal back. Waiting also ends
+/// when the job is stopped with Ctrl-Z.
+pub(crate) fn wait_in_foreground(
+    pgid: Pid,
+    pids: &[Pid],
+    last_pid: Pid,
+    default: i32,
+    pipefail: bool,
+) -> JobWait {
+    give_terminal_to(pgid);
+    let ignore = SigAction::new(SigHandler::SigIgn, SaFlags::empty(), SigSet::empty());
+    let previous = unsafe { sigaction(Signal::SIGINT, &ignore) };
+
+    let result = wait_for_pids(pids, last_pid, default, pipefail, true);
+
+    if let Ok(previous) = previous {
+        unsafe {
+            let _ = sigaction(Signal::SIGINT, &previous);
+        }
+    }
+    give_terminal_to(getpgrp());
+    if matches!(result, JobWait::Finished(status) if status == 128 + Signal::SIGINT as i32) {
+        println!();
+    }
+    result
+}
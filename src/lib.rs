@@ -0,0 +1,25 @@
+//! A Unix shell with job control, line editing and completion. The binary
+//! reads commands from a terminal, a script or `-c`; embedders and tests can
+//! drive a `Shell` directly with `run_line`.
+
+mod arithmetic;
+mod builtins;
+mod completion;
+mod envfile;
+mod error;
+mod exec;
+mod expansion;
+mod frecency;
+mod interactive;
+mod jobs;
+mod parser;
+mod spelling;
+mod variables;
+
+pub use exec::Shell;
+pub use interactive::{run_interactive, run_login_profile};
+
+/// The status a command exits with: 0 for success, 1 to 125 for failures, 126
+/// and 127 for commands that can't be run or found, and 128 plus the signal
+/// number for commands killed by a signal.
+pub type ExitStatus = i32;
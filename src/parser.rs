@@ -0,0 +1,1404 @@
+//! Lexing and parsing command lines into lists of pipelines.
+
+use std::collections::BTreeMap;
+use std::mem;
+use std::ops::Range;
+use std::os::fd::RawFd;
+use std::rc::Rc;
+use std::str::CharIndices;
+
+use crate::expansion::unescape_glob;
+use crate::variables::parse_assignment;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Token {
+    Word(String),
+    Pipe,
+    Background,
+    And,
+    Or,
+    Semi,
+    /// `;;`, which ends a `case` item.
+    DoubleSemi,
+    /// The `(` and `)` around a subshell.
+    OpenParen,
+    CloseParen,
+    /// A `<<` or `<<-` redirection with its delimiter and the lines read for it.
+    HereDoc(HereDoc),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct HereDoc {
+    /// The delimiter word as written, with any quotes.
+    delimiter: String,
+    /// Whether leading tabs are stripped from the lines, for `<<-`.
+    strip_tabs: bool,
+    /// The lines up to the delimiter line, or `None` if the input ended first.
+    body: Option<String>,
+}
+
+impl HereDoc {
+    /// Variables are expanded in the body unless part of the delimiter is quoted.
+    fn expands(&self) -> bool {
+        !self.delimiter.contains(['\'', '"', '\\'])
+    }
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{}", word),
+            Token::Pipe => write!(f, "|"),
+            Token::Background => write!(f, "&"),
+            Token::And => write!(f, "&&"),
+            Token::Or => write!(f, "||"),
+            Token::Semi => write!(f, ";"),
+            Token::DoubleSemi => write!(f, ";;"),
+            Token::OpenParen => write!(f, "("),
+            Token::CloseParen => write!(f, ")"),
+            Token::HereDoc(doc) => write!(
+                f,
+                "<<{}{}",
+                if doc.strip_tabs { "-" } else { "" },
+                doc.delimiter
+            ),
+        }
+    }
+}
+
+fn push_token(
+    tokens: &mut Vec<(Token, Range<usize>)>,
+    word: String,
+    quoted: bool,
+    span: Range<usize>,
+) {
+    // A here-document operator joins the delimiter that follows it, which may
+    // also be written in the same word as `<<EOF`.
+    if let Some((Token::Word(operator), operator_span)) = tokens.last()
+        && matches!(operator.as_str(), "<<" | "<<-")
+    {
+        let strip_tabs = operator == "<<-";
+        let span = operator_span.start..span.end;
+        tokens.pop();
+        tokens.push((
+            Token::HereDoc(HereDoc {
+                delimiter: word,
+                strip_tabs,
+                body: None,
+            }),
+            span,
+        ));
+        return;
+    }
+    if let Some(rest) = word.strip_prefix("<<")
+        && !rest.is_empty()
+        && !rest.starts_with('<')
+        && rest != "-"
+    {
+        let (delimiter, strip_tabs) = match rest.strip_prefix('-') {
+            Some(delimiter) => (delimiter, true),
+            None => (rest, false),
+        };
+        let doc = HereDoc {
+            delimiter: delimiter.to_string(),
+            strip_tabs,
+            body: None,
+        };
+        tokens.push((Token::HereDoc(doc), span));
+        return;
+    }
+    let token = match word.as_str() {
+        "|" if !quoted => Token::Pipe,
+        "&" if !quoted => Token::Background,
+        "&&" if !quoted => Token::And,
+        "||" if !quoted => Token::Or,
+        _ => Token::Word(word),
+    };
+    tokens.push((token, span));
+}
+
+/// Splits a command line into tokens, each with the byte range of `input` it was
+/// read from. Words keep their quotes and backslashes so that the expansion pass
+/// can tell quoted text apart; see `expand_word`.
+pub(crate) fn lex_command_line(input: &str) -> Vec<(Token, Range<usize>)> {
+    Lexer::new(input).run()
+}
+
+/// Reads the tokens of a command line one character at a time. Operators are
+/// recognized wherever they appear outside quotes, whether or not whitespace
+/// surrounds them.
+struct Lexer<'a> {
+    input: &'a str,
+    chars: CharIndices<'a>,
+    tokens: Vec<(Token, Range<usize>)>,
+    /// The word being read, with its quotes and backslashes.
+    word: String,
+    /// Where `word` starts in `input`.
+    start: usize,
+    /// Whether any part of `word` is quoted.
+    quoted: bool,
+    /// The quote `word` is inside of, if any.
+    quote: Option<char>,
+    /// How many subshells opened with `(` are waiting for their `)`.
+    open_subshells: usize,
+    /// The `case` commands being read, innermost last, each `true` while its
+    /// next pattern, ended by `)`, is being read.
+    cases: Vec<bool>,
+    /// Whether the words being read are inside `[[ ]]`, where a regular
+    /// expression may contain `(`, `)` and `|`.
+    in_conditional: bool,
+    /// Whether the last `)` ended a `case` pattern rather than a subshell, so
+    /// that a command follows it.
+    pattern_ended: bool,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(input: &'a str) -> Self {
+        Lexer {
+            input,
+            chars: input.char_indices(),
+            tokens: Vec::new(),
+            word: String::new(),
+            start: 0,
+            quoted: false,
+            quote: None,
+            open_subshells: 0,
+            cases: Vec::new(),
+            in_conditional: false,
+            pattern_ended: false,
+        }
+    }
+
+    fn run(mut self) -> Vec<(Token, Range<usize>)> {
+        while let Some((i, ch)) = self.chars.next() {
+            if self.word.is_empty() {
+                self.start = i;
+            }
+            let unquoted = self.quote.is_none();
+            let in_pattern = self.cases.last() == Some(&true);
+            match ch {
+                '\\' if self.quote != Some('\'') && self.chars.as_str().starts_with('\n') => {
+                    self.chars.next();
+                }
+                '\\' => {
+                    self.quoted = true;
+                    self.word.push(ch);
+                    if self.quote != Some('\'')
+                        && let Some((_, next_ch)) = self.chars.next()
+                    {
+                        self.word.push(next_ch);
+                    }
+                }
+                '"' | '\'' => {
+                    self.quoted = true;
+                    self.word.push(ch);
+                    if self.quote == Some(ch) {
+                        self.quote = None;
+                    } else if unquoted {
+                        self.quote = Some(ch);
+                    }
+                }
+                '$' if self.quote != Some('\'') && self.chars.as_str().starts_with('(') => {
+                    self.word.push(ch);
+                    take_group(&mut self.chars, &mut self.word, '(', ')');
+                }
+                '$' if self.quote != Some('\'') && self.chars.as_str().starts_with('{') => {
+                    self.word.push(ch);
+                    take_group(&mut self.chars, &mut self.word, '{', '}');
+                }
+                '(' if unquoted && self.word.is_empty() && self.chars.as_str().starts_with('(') => {
+                    self.word.push(ch);
+                    take_group(&mut self.chars, &mut self.word, '(', ')');
+                }
+                '(' if unquoted
+                    && self.word.is_empty()
+                    && !self.in_conditional
+                    && self.at_command_start() =>
+                {
+                    if !in_pattern {
+                        self.open_subshells += 1;
+                    }
+                    self.tokens.push((Token::OpenParen, i..i + 1));
+                }
+                ')' if unquoted
+                    && !self.in_conditional
+                    && (in_pattern || self.open_subshells > 0) =>
+                {
+                    self.end_word(i);
+                    match self.cases.last_mut() {
+                        Some(pattern) if *pattern => *pattern = false,
+                        _ => self.open_subshells -= 1,
+                    }
+                    self.pattern_ended = in_pattern;
+                    self.tokens.push((Token::CloseParen, i..i + 1));
+                }
+                '#' if unquoted && self.word.is_empty() => {
+                    while self
+                        .chars
+                        .as_str()
+                        .chars()
+                        .next()
+                        .is_some_and(|next_ch| next_ch != '\n')
+                    {
+                        self.chars.next();
+                    }
+                }
+                '\n' if unquoted => {
+                    self.end_word(i);
+                    if matches!(
+                        self.tokens.last(),
+                        Some((Token::Word(_) | Token::HereDoc(_), _))
+                    ) {
+                        self.tokens.push((Token::Semi, i..i + 1));
+                    }
+                    read_here_doc_bodies(&mut self.tokens, &mut self.chars);
+                }
+                ' ' | '\t' if unquoted => self.end_word(i),
+                ';' if unquoted => {
+                    self.end_word(i);
+                    if self.chars.as_str().starts_with(';') {
+                        self.chars.next();
+                        self.tokens.push((Token::DoubleSemi, i..i + 2));
+                        if let Some(pattern) = self.cases.last_mut() {
+                            *pattern = true;
+                        }
+                    } else {
+                        self.tokens.push((Token::Semi, i..i + 1));
+                    }
+                }
+                '|' | '<' | '>' if unquoted && self.in_conditional && !self.word.is_empty() => {
+                    self.word.push(ch)
+                }
+                '|' | '&' | '<' | '>' if unquoted => self.operator(i, ch),
+                _ => self.word.push(ch),
+            }
+        }
+        self.end_word(self.input.len());
+        self.tokens
+    }
+
+    /// Ends the word being read, if any, at `end`, noting the reserved words
+    /// that change how the words after them are read.
+    fn end_word(&mut self, end: usize) {
+        if self.word.is_empty() {
+            return;
+        }
+        let word = mem::take(&mut self.word);
+        if !self.quoted {
+            let command_position = self.at_command_start();
+            let previous = self.tokens.iter().nth_back(1);
+            let after_case = matches!(previous, Some((Token::Word(word), _)) if word == "case");
+            match word.as_str() {
+                "case" if command_position => self.cases.push(false),
+                "in" if after_case && let Some(pattern @ false) = self.cases.last_mut() => {
+                    *pattern = true
+                }
+                "esac" if command_position || self.cases.last() == Some(&true) => {
+                    self.cases.pop();
+                }
+                "[[" if command_position => self.in_conditional = true,
+                "]]" => self.in_conditional = false,
+                _ => {}
+            }
+        }
+        push_token(
+            &mut self.tokens,
+            word,
+            mem::take(&mut self.quoted),
+            self.start..end,
+        );
+    }
+
+    /// Whether a word starting here would be in command position.
+    fn at_command_start(&self) -> bool {
+        match self.tokens.last() {
+            Some((Token::CloseParen, _)) => self.pattern_ended,
+            _ => starts_command(&self.tokens),
+        }
+    }
+
+    /// Reads the operator starting with `ch` at `i`: a pipe, `&&` or `||`, `&`,
+    /// or a redirection. A word of digits just before a redirection is the
+    /// descriptor it applies to, as in `2>`.
+    fn operator(&mut self, i: usize, ch: char) {
+        let rest = self.chars.as_str();
+        let digits_after =
+            |text: &str| text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let operator_len = match ch {
+            '|' | '&' if rest.starts_with(ch) => 2,
+            '&' if rest.starts_with(">>") => 3,
+            '&' if rest.starts_with('>') => 2,
+            '>' if rest.starts_with('>') => 2,
+            '<' if rest.starts_with("<<") || rest.starts_with("<-") => 3,
+            '<' if rest.starts_with('<') => 2,
+            '<' | '>' if rest.starts_with("&-") => 3,
+            '<' | '>' if rest.starts_with('&') => 2 + digits_after(&rest[1..]),
+            _ => 1,
+        };
+        let descriptor = matches!(ch, '<' | '>')
+            && !self.quoted
+            && !self.word.is_empty()
+            && self.word.bytes().all(|b| b.is_ascii_digit());
+        let mut text = if descriptor {
+            mem::take(&mut self.word)
+        } else {
+            String::new()
+        };
+        let start = if descriptor { self.start } else { i };
+        self.end_word(i);
+        text.push_str(&self.input[i..i + operator_len]);
+        for _ in 1..operator_len {
+            self.chars.next();
+        }
+        push_token(&mut self.tokens, text, false, start..i + operator_len);
+    }
+}
+
+/// Whether a word starting after `tokens` would be in command position, where
+/// `(` opens a subshell rather than being part of a word.
+fn starts_command(tokens: &[(Token, Range<usize>)]) -> bool {
+    match tokens.last() {
+        None => true,
+        Some((Token::Word(word), _)) => {
+            matches!(word.as_str(), "if" | "then" | "elif" | "else" | "{" | "!")
+                || word.ends_with("()")
+        }
+        Some((Token::HereDoc(_) | Token::CloseParen, _)) => false,
+        Some(_) => true,
+    }
+}
+
+/// Reads the bodies of the here-documents on the line just ended from the
+/// lines that follow it, in order. A body is left as `None` if the input ends
+/// before its delimiter line.
+fn read_here_doc_bodies(tokens: &mut [(Token, Range<usize>)], chars: &mut CharIndices) {
+    let pending = tokens.iter_mut().filter_map(|(token, _)| match token {
+        Token::HereDoc(doc) if doc.body.is_none() => Some(doc),
+        _ => None,
+    });
+    for doc in pending {
+        let delimiter = unescape_glob(&doc.delimiter.replace(['\'', '"'], ""));
+        let mut body = String::new();
+        while !chars.as_str().is_empty() {
+            let rest = chars.as_str();
+            let end = rest.find('\n').map_or(rest.len(), |end| end + 1);
+            for _ in rest[..end].chars() {
+                chars.next();
+            }
+            let line = rest[..end].strip_suffix('\n').unwrap_or(&rest[..end]);
+            let line = if doc.strip_tabs {
+                line.trim_start_matches('\t')
+            } else {
+                line
+            };
+            if line == delimiter {
+                doc.body = Some(body);
+                break;
+            }
+            body.push_str(line);
+            body.push('\n');
+        }
+    }
+}
+
+/// Moves the text of a bracketed group such as the `(...)` of `$(...)` or the
+/// `{...}` of `${...}` onto `word`, up to its closing bracket, so that the
+/// spaces and operators inside it do not split the word. `word` already holds
+/// the character before the group, or its own opening bracket.
+fn take_group(chars: &mut CharIndices, word: &mut String, open: char, close: char) {
+    let mut depth = usize::from(word.ends_with(open));
+    for (_, ch) in chars.by_ref() {
+        word.push(ch);
+        if ch == open {
+            depth += 1;
+        } else if ch == close {
+            depth -= 1;
+        }
+        if depth == 0 {
+            break;
+        }
+    }
+}
+
+/// Whether `input` needs more lines to form a complete command: it has an
+/// unclosed quote or `$(`, ends with a backslash, or ends in the middle of a
+/// command, such as after `|` or before `fi`.
+pub(crate) fn incomplete_input(input: &str) -> bool {
+    let mut quote_state: Option<char> = None;
+    let mut open_substitutions = 0;
+    let mut word_start = true;
+    let mut chars = input.chars().peekable();
+    while let Some(ch) = chars.next() {
+        let at_word_start = mem::replace(
+            &mut word_start,
+            ch.is_whitespace() || ";|&()<>".contains(ch),
+        );
+        match ch {
+            '#' if quote_state.is_none() && at_word_start => {
+                while chars.next_if(|&next_ch| next_ch != '\n').is_some() {}
+            }
+            '\\' if quote_state != Some('\'') && chars.next().is_none() => return true,
+            '"' | '\'' => {
+                if quote_state == Some(ch) {
+                    quote_state = None;
+                } else if quote_state.is_none() {
+                    quote_state = Some(ch);
+                }
+            }
+            '$' if quote_state != Some('\'') && chars.peek() == Some(&'(') => {
+                chars.next();
+                open_substitutions += 1;
+            }
+            ')' if quote_state.is_none() && open_substitutions > 0 => open_substitutions -= 1,
+            _ => {}
+        }
+    }
+    quote_state.is_some()
+        || open_substitutions > 0
+        || parse_command_list(lex_command_line(input))
+            .is_err_and(|error| error.token == UNEXPECTED_EOF)
+}
+
+/// Replaces alias names in command position with their values. The value is
+/// tokenized again, so it may contain operators, and a value ending in a space
+/// makes the following word eligible for alias expansion too. Aliases being
+/// expanded are tracked in `active` so that `alias ls='ls -F'` does not loop.
+/// The tokens of an alias's value take the position of the alias name.
+pub(crate) fn expand_aliases(
+    tokens: Vec<(Token, Range<usize>)>,
+    aliases: &BTreeMap<String, String>,
+    active: &mut Vec<String>,
+) -> Vec<(Token, Range<usize>)> {
+    let mut result = Vec::new();
+    let mut command_position = true;
+    for (token, span) in tokens {
+        match token {
+            Token::Word(word) if command_position => {
+                if let Some(value) = aliases.get(&word)
+                    && !active.contains(&word)
+                {
+                    active.push(word);
+                    let expansion = expand_aliases(lex_command_line(value), aliases, active);
+                    active.pop();
+                    let ends_with_operator = expansion
+                        .last()
+                        .is_some_and(|(token, _)| !matches!(token, Token::Word(_)));
+                    command_position = value.ends_with(' ') || ends_with_operator;
+                    result.extend(
+                        expansion
+                            .into_iter()
+                            .map(|(token, _)| (token, span.clone())),
+                    );
+                    continue;
+                }
+                command_position = parse_assignment(&word).is_some()
+                    || matches!(word.as_str(), "if" | "then" | "elif" | "else" | "{");
+                result.push((Token::Word(word), span));
+            }
+            Token::Word(word) => {
+                command_position = false;
+                result.push((Token::Word(word), span));
+            }
+            doc @ Token::HereDoc(_) => result.push((doc, span)),
+            operator => {
+                command_position = true;
+                result.push((operator, span));
+            }
+        }
+    }
+    result
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Connector {
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum RedirectKind {
+    /// `[n]>file` and `[n]>>file`.
+    Write { append: bool },
+    /// `[n]<file`.
+    Read,
+    /// `&>file` and `&>>file`: stdout and stderr to the same file.
+    WriteBoth { append: bool },
+    /// `[n]>&m` and `[n]<&m`: make `n` a copy of descriptor `m`.
+    Duplicate(RawFd),
+    /// `[n]>&-` and `[n]<&-`: close descriptor `n`.
+    Close,
+    /// `<<delimiter` and `<<-delimiter`: the target is the text to read, with
+    /// variables expanded in it when `expand` is set.
+    HereDoc { expand: bool },
+    /// `[n]<<<word`: the target is expanded and read back with a newline added.
+    HereString,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Redirection {
+    pub(crate) fd: RawFd,
+    pub(crate) kind: RedirectKind,
+    /// The file to open, or the text of a here-document; empty for descriptor
+    /// duplication.
+    pub(crate) target: String,
+}
+
+/// A command's words with its leading `NAME=value` assignments and its
+/// redirections split off, in the order written.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SimpleCommand {
+    pub(crate) assignments: Vec<(String, String)>,
+    pub(crate) words: Vec<String>,
+    pub(crate) redirections: Vec<Redirection>,
+    /// Set when the command was run through `command`, which skips functions.
+    pub(crate) skip_functions: bool,
+}
+
+/// A compound command, which runs lists of commands in the shell itself.
+#[derive(Debug, Clone)]
+pub(crate) enum CompoundCommand {
+    /// `if`/`elif` conditions with their bodies, and the `else` body.
+    If {
+        branches: Vec<(Vec<AndOrList>, Vec<AndOrList>)>,
+        otherwise: Option<Vec<AndOrList>>,
+    },
+    /// `case` with its subject word and the `|`-separated patterns of each
+    /// item with the body run when one of them matches.
+    Case {
+        subject: String,
+        items: Vec<(Vec<String>, Vec<AndOrList>)>,
+    },
+    /// `((expression))`, which succeeds if the expression is non-zero.
+    Arithmetic(String),
+    /// `[[ expression ]]`.
+    Conditional(ConditionExpr),
+    /// `( list )`, run in a forked copy of the shell.
+    Subshell(Vec<AndOrList>),
+    /// `{ list; }`, run in the shell itself.
+    Group(Vec<AndOrList>),
+}
+
+/// The expression inside `[[ ]]`. Operands are single words: they are expanded
+/// but not split or globbed.
+#[derive(Debug, Clone)]
+pub(crate) enum ConditionExpr {
+    /// A lone word, true if it is not empty.
+    Word(String),
+    /// A file or string test such as `-f path` or `-z word`.
+    Unary(String, String),
+    /// A comparison such as `a == pattern`, `a =~ regex` or `a -lt b`.
+    Binary(String, String, String),
+    Not(Box<ConditionExpr>),
+    And(Box<ConditionExpr>, Box<ConditionExpr>),
+    Or(Box<ConditionExpr>, Box<ConditionExpr>),
+}
+
+const CONDITION_UNARY_OPERATORS: [&str; 17] = [
+    "-a", "-b", "-c", "-d", "-e", "-f", "-h", "-L", "-n", "-p", "-r", "-s", "-S", "-v", "-w", "-x",
+    "-z",
+];
+
+const CONDITION_BINARY_OPERATORS: [&str; 15] = [
+    "==", "=", "!=", "=~", "<", ">", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef",
+];
+
+/// One stage of a pipeline.
+#[derive(Debug, Clone)]
+pub(crate) enum Command {
+    Simple(SimpleCommand),
+    /// A compound command with the redirections that follow it.
+    Compound(CompoundCommand, Vec<Redirection>),
+    /// `name() compound` or `function name compound`, which defines a function.
+    Function(Rc<Function>),
+}
+
+/// A shell function: a compound command run with the caller's arguments as the
+/// positional parameters.
+#[derive(Debug)]
+pub(crate) struct Function {
+    pub(crate) name: String,
+    pub(crate) body: CompoundCommand,
+    /// The redirections after the body, applied each time the function runs.
+    pub(crate) redirections: Vec<Redirection>,
+    /// The definition as typed, printed by `type`.
+    pub(crate) text: String,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Pipeline {
+    pub(crate) stages: Vec<Command>,
+}
+
+/// Recognizes a redirection operator word such as `>`, `2>>`, `<`, `<<<`, `&>`,
+/// `2>&1` or `3<&-`. Returns the descriptor, the kind of redirection and whether the
+/// operator takes a file name or word as the next word.
+pub(crate) fn parse_redirect_operator(word: &str) -> Option<(RawFd, RedirectKind, bool)> {
+    if let Some(rest) = word.strip_prefix("&>") {
+        return match rest {
+            "" => Some((1, RedirectKind::WriteBoth { append: false }, true)),
+            ">" => Some((1, RedirectKind::WriteBoth { append: true }, true)),
+            _ => None,
+        };
+    }
+
+    let digits = word.len() - word.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let (fd, operator) = word.split_at(digits);
+    let fd = if fd.is_empty() {
+        None
+    } else {
+        Some(fd.parse::<RawFd>().ok()?)
+    };
+
+    let (default_fd, kind, needs_target) = match operator {
+        ">" => (1, RedirectKind::Write { append: false }, true),
+        ">>" => (1, RedirectKind::Write { append: true }, true),
+        "<" => (0, RedirectKind::Read, true),
+        "<<<" => (0, RedirectKind::HereString, true),
+        _ => {
+            let (default_fd, source) = if let Some(source) = operator.strip_prefix(">&") {
+                (1, source)
+            } else {
+                (0, operator.strip_prefix("<&")?)
+            };
+            if source == "-" {
+                (default_fd, RedirectKind::Close, false)
+            } else {
+                (
+                    default_fd,
+                    RedirectKind::Duplicate(source.parse::<RawFd>().ok()?),
+                    false,
+                )
+            }
+        }
+    };
+    Some((fd.unwrap_or(default_fd), kind, needs_target))
+}
+
+/// Whether `name` can name a function: a non-empty word without quotes,
+/// expansions or characters that end a word.
+fn is_function_name(name: &str) -> bool {
+    !name.is_empty()
+        && !RESERVED_WORDS.contains(&name)
+        && !name.contains(['$', '\'', '"', '\\', '`', '=', '(', ')', '/'])
+}
+
+/// Separates redirections from the arguments of a single pipeline stage, given
+/// its words and here-documents.
+fn parse_simple_command(tokens: Vec<Token>) -> ParseResult<SimpleCommand> {
+    let mut command = SimpleCommand::default();
+    let mut words = tokens.into_iter().map(|token| match token {
+        Token::Word(word) => Ok(word),
+        Token::HereDoc(doc) => Err(doc),
+        _ => unreachable!("{} is not part of a simple command", token),
+    });
+    while let Some(word) = words.next() {
+        let word = match word {
+            Ok(word) => word,
+            Err(doc) => {
+                let expand = doc.expands();
+                let target = doc.body.ok_or(UNEXPECTED_EOF)?;
+                let kind = RedirectKind::HereDoc { expand };
+                command.redirections.push(Redirection {
+                    fd: 0,
+                    kind,
+                    target,
+                });
+                continue;
+            }
+        };
+        let Some((fd, kind, needs_target)) = parse_redirect_operator(&word) else {
+            match parse_assignment(&word) {
+                Some((name, value)) if command.words.is_empty() => {
+                    command
+                        .assignments
+                        .push((name.to_string(), value.to_string()));
+                }
+                _ => command.words.push(word),
+            }
+            continue;
+        };
+        let target = if needs_target {
+            match words.next() {
+                Some(Ok(target)) => target,
+                Some(Err(doc)) => return Err(Token::HereDoc(doc).to_string()),
+                None => return Err("newline".to_string()),
+            }
+        } else {
+            String::new()
+        };
+        command.redirections.push(Redirection { fd, kind, target });
+    }
+    Ok(command)
+}
+
+/// Pipelines joined by `&&` and `||`, optionally run as a background job.
+#[derive(Debug, Clone)]
+pub(crate) struct AndOrList {
+    pub(crate) first: Pipeline,
+    pub(crate) rest: Vec<(Connector, Pipeline)>,
+    pub(crate) background: bool,
+    /// The command as typed, used to describe it in the job table.
+    pub(crate) text: String,
+}
+
+/// The syntax error reported when input ends in the middle of a command, which
+/// also tells the reader to ask for another line.
+pub(crate) const UNEXPECTED_EOF: &str = "end of file";
+
+/// Reserved words that may only appear at the start of a command.
+pub(crate) const RESERVED_WORDS: [&str; 12] = [
+    "if", "then", "elif", "else", "fi", "case", "esac", "[[", "]]", "{", "}", "function",
+];
+
+/// Reserved words that end the command list before them.
+const LIST_TERMINATORS: [&str; 6] = ["then", "elif", "else", "fi", "esac", "}"];
+
+/// A recursive-descent parser over the tokens of a command line. Syntax errors
+/// carry the offending token, or `UNEXPECTED_EOF`.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+type ParseResult<T> = std::result::Result<T, String>;
+
+impl Parser {
+    fn new(tokens: Vec<Token>) -> Self {
+        Parser { tokens, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    /// The next token if it is a word that could be a reserved word.
+    fn peek_word(&self) -> Option<&str> {
+        match self.peek() {
+            Some(Token::Word(word)) => Some(word),
+            _ => None,
+        }
+    }
+
+    /// The error for an unexpected token at the current position.
+    fn unexpected(&self) -> String {
+        match self.peek() {
+            Some(token) => token.to_string(),
+            None => UNEXPECTED_EOF.to_string(),
+        }
+    }
+
+    fn expect_word(&mut self, word: &str) -> ParseResult<()> {
+        if self.peek_word() != Some(word) {
+            return Err(self.unexpected());
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Skips the separators that may follow a reserved word, since a newline
+    /// after `then` or `else` is read as `;`.
+    fn skip_separators(&mut self) {
+        while self.peek() == Some(&Token::Semi) {
+            self.pos += 1;
+        }
+    }
+
+    /// Parses and-or lists separated by `;` or `&` until the end of input, or
+    /// until a reserved word, `;;` or `)` that ends the list when `nested`.
+    fn parse_list(&mut self, nested: bool) -> ParseResult<Vec<AndOrList>> {
+        let mut lists = Vec::new();
+        loop {
+            match self.peek_word() {
+                None if self.peek().is_none() => break,
+                None if nested
+                    && matches!(self.peek(), Some(Token::DoubleSemi | Token::CloseParen)) =>
+                {
+                    break;
+                }
+                Some(word) if nested && LIST_TERMINATORS.contains(&word) => break,
+                _ => {}
+            }
+            let start = self.pos;
+            let (first, rest) = self.parse_and_or()?;
+            let text = self.tokens[start..self.pos]
+                .iter()
+                .map(|token| token.to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            let background = match self.peek() {
+                Some(Token::Semi) => {
+                    self.pos += 1;
+                    false
+                }
+                Some(Token::Background) => {
+                    self.pos += 1;
+                    true
+                }
+                None => false,
+                Some(Token::DoubleSemi | Token::CloseParen) if nested => false,
+                Some(Token::Word(word)) if nested && LIST_TERMINATORS.contains(&word.as_str()) => {
+                    false
+                }
+                Some(_) => return Err(self.unexpected()),
+            };
+            lists.push(AndOrList {
+                first,
+                rest,
+                background,
+                text,
+            });
+        }
+        Ok(lists)
+    }
+
+    /// Parses a list that must contain at least one command.
+    fn parse_nonempty_list(&mut self) -> ParseResult<Vec<AndOrList>> {
+        self.skip_separators();
+        let list = self.parse_list(true)?;
+        if list.is_empty() {
+            return Err(self.unexpected());
+        }
+        Ok(list)
+    }
+
+    fn parse_and_or(&mut self) -> ParseResult<(Pipeline, Vec<(Connector, Pipeline)>)> {
+        let first = self.parse_pipeline()?;
+        let mut rest = Vec::new();
+        loop {
+            let connector = match self.peek() {
+                Some(Token::And) => Connector::And,
+                Some(Token::Or) => Connector::Or,
+                _ => break,
+            };
+            self.pos += 1;
+            rest.push((connector, self.parse_pipeline()?));
+        }
+        Ok((first, rest))
+    }
+
+    fn parse_pipeline(&mut self) -> ParseResult<Pipeline> {
+        let mut stages = vec![self.parse_command()?];
+        while self.peek() == Some(&Token::Pipe) {
+            self.pos += 1;
+            stages.push(self.parse_command()?);
+        }
+        Ok(Pipeline { stages })
+    }
+
+    fn parse_command(&mut self) -> ParseResult<Command> {
+        if self.peek() == Some(&Token::OpenParen) {
+            self.pos += 1;
+            let body = self.parse_nonempty_list()?;
+            if self.peek() != Some(&Token::CloseParen) {
+                return Err(self.unexpected());
+            }
+            self.pos += 1;
+            let redirections = self.parse_redirections()?;
+            return Ok(Command::Compound(
+                CompoundCommand::Subshell(body),
+                redirections,
+            ));
+        }
+        match self.peek_word() {
+            Some(word @ ("if" | "case" | "[[" | "{")) => {
+                let compound = match word {
+                    "if" => self.parse_if()?,
+                    "case" => self.parse_case()?,
+                    "{" => self.parse_group()?,
+                    _ => self.parse_conditional()?,
+                };
+                let redirections = self.parse_redirections()?;
+                Ok(Command::Compound(compound, redirections))
+            }
+            Some(word) if word.starts_with("((") => {
+                let Some(expr) = word
+                    .strip_prefix("((")
+                    .and_then(|word| word.strip_suffix("))"))
+                else {
+                    return Err(UNEXPECTED_EOF.to_string());
+                };
+                let compound = CompoundCommand::Arithmetic(expr.to_string());
+                self.pos += 1;
+                let redirections = self.parse_redirections()?;
+                Ok(Command::Compound(compound, redirections))
+            }
+            Some(word) if word == "function" || self.at_function_definition() => {
+                self.parse_function()
+            }
+            Some(word) if RESERVED_WORDS.contains(&word) => Err(word.to_string()),
+            Some(_) => {
+                let mut tokens = Vec::new();
+                while let Some(token @ (Token::Word(_) | Token::HereDoc(_))) = self.peek() {
+                    tokens.push(token.clone());
+                    self.pos += 1;
+                }
+                Ok(Command::Simple(parse_simple_command(tokens)?))
+            }
+            None => Err(self.unexpected()),
+        }
+    }
+
+    /// Whether the next words are a function name and `()`, written together or
+    /// apart.
+    fn at_function_definition(&self) -> bool {
+        let Some(word) = self.peek_word() else {
+            return false;
+        };
+        let (name, parens) = match word.strip_suffix("()") {
+            Some(name) => (name, true),
+            None => (
+                word,
+                matches!(self.tokens.get(self.pos + 1), Some(Token::Word(next)) if next == "()"),
+            ),
+        };
+        parens && is_function_name(name)
+    }
+
+    /// Parses `name() compound [redirections]` or `function name [()] compound
+    /// [redirections]`.
+    fn parse_function(&mut self) -> ParseResult<Command> {
+        let start = self.pos;
+        if self.peek_word() == Some("function") {
+            self.pos += 1;
+        }
+        let Some(word) = self.peek_word() else {
+            return Err(self.unexpected());
+        };
+        let (name, parens) = match word.strip_suffix("()") {
+            Some(name) => (name.to_string(), true),
+            None => (word.to_string(), false),
+        };
+        if !is_function_name(&name) {
+            return Err(word.to_string());
+        }
+        self.pos += 1;
+        if !parens && self.peek_word() == Some("()") {
+            self.pos += 1;
+        }
+        self.skip_separators();
+        let starts_compound =
+            |word: &str| matches!(word, "if" | "case" | "[[" | "{") || word.starts_with("((");
+        let compound =
+            self.peek() == Some(&Token::OpenParen) || self.peek_word().is_some_and(starts_compound);
+        if !compound {
+            return Err(self.unexpected());
+        }
+        let Command::Compound(body, redirections) = self.parse_command()? else {
+            unreachable!("a compound command was parsed");
+        };
+        // Newlines were read as `;`, which is dropped where a reserved word
+        // already separates the commands.
+        let mut words: Vec<String> = Vec::new();
+        for token in &self.tokens[start..self.pos] {
+            let separated = matches!(
+                words.last().map(String::as_str),
+                Some("{" | "then" | "else" | ";")
+            );
+            if *token != Token::Semi || !separated {
+                words.push(token.to_string());
+            }
+        }
+        let text = words.join(" ");
+        Ok(Command::Function(Rc::new(Function {
+            name,
+            body,
+            redirections,
+            text,
+        })))
+    }
+
+    /// Parses the redirections after a compound command.
+    fn parse_redirections(&mut self) -> ParseResult<Vec<Redirection>> {
+        let mut tokens = Vec::new();
+        loop {
+            let needs_target = match self.peek() {
+                Some(Token::HereDoc(_)) => false,
+                Some(Token::Word(word)) => match parse_redirect_operator(word) {
+                    Some((_, _, needs_target)) => needs_target,
+                    None => return Err(word.to_string()),
+                },
+                _ => break,
+            };
+            tokens.push(self.tokens[self.pos].clone());
+            self.pos += 1;
+            if needs_target {
+                let Some(target) = self.peek_word() else {
+                    return Err(self.peek().map_or("newline".to_string(), Token::to_string));
+                };
+                tokens.push(Token::Word(target.to_string()));
+                self.pos += 1;
+            }
+        }
+        Ok(parse_simple_command(tokens)?.redirections)
+    }
+
+    fn parse_if(&mut self) -> ParseResult<CompoundCommand> {
+        self.expect_word("if")?;
+        let mut branches = Vec::new();
+        let mut otherwise = None;
+        loop {
+            let condition = self.parse_nonempty_list()?;
+            self.expect_word("then")?;
+            let body = self.parse_nonempty_list()?;
+            branches.push((condition, body));
+            match self.peek_word() {
+                Some("elif") => self.pos += 1,
+                Some("else") => {
+                    self.pos += 1;
+                    otherwise = Some(self.parse_nonempty_list()?);
+                    self.expect_word("fi")?;
+                    break;
+                }
+                _ => {
+                    self.expect_word("fi")?;
+                    break;
+                }
+            }
+        }
+        Ok(CompoundCommand::If {
+            branches,
+            otherwise,
+        })
+    }
+
+    fn parse_group(&mut self) -> ParseResult<CompoundCommand> {
+        self.expect_word("{")?;
+        let body = self.parse_nonempty_list()?;
+        self.expect_word("}")?;
+        Ok(CompoundCommand::Group(body))
+    }
+
+    fn parse_case(&mut self) -> ParseResult<CompoundCommand> {
+        self.expect_word("case")?;
+        let Some(subject) = self.peek_word().map(str::to_string) else {
+            return Err(self.unexpected());
+        };
+        self.pos += 1;
+        self.skip_separators();
+        self.expect_word("in")?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_separators();
+            if self.peek_word() == Some("esac") {
+                self.pos += 1;
+                break;
+            }
+            let patterns = self.parse_case_patterns()?;
+            self.skip_separators();
+            let body = self.parse_list(true)?;
+            items.push((patterns, body));
+            if self.peek() == Some(&Token::DoubleSemi) {
+                self.pos += 1;
+            } else {
+                self.expect_word("esac")?;
+                break;
+            }
+        }
+        Ok(CompoundCommand::Case { subject, items })
+    }
+
+    /// Parses the patterns of a `case` item up to the closing `)`. Since `|` is
+    /// only an operator when it stands alone, the patterns may arrive as one
+    /// word like `a|b)` or as separate tokens like `a | b )`.
+    fn parse_case_patterns(&mut self) -> ParseResult<Vec<String>> {
+        let mut text = String::new();
+        loop {
+            match self.peek() {
+                Some(Token::Word(word)) => {
+                    let closed = word.ends_with(')') && !word.ends_with("\\)");
+                    text.push_str(if closed {
+                        &word[..word.len() - 1]
+                    } else {
+                        word
+                    });
+                    self.pos += 1;
+                    if closed {
+                        break;
+                    }
+                }
+                Some(Token::Pipe) => {
+                    text.push('|');
+                    self.pos += 1;
+                }
+                Some(Token::OpenParen) if text.is_empty() => {
+                    text.push('(');
+                    self.pos += 1;
+                }
+                Some(Token::CloseParen) => {
+                    self.pos += 1;
+                    break;
+                }
+                _ => return Err(self.unexpected()),
+            }
+        }
+        let text = text.strip_prefix('(').unwrap_or(&text);
+        let patterns = split_case_patterns(text);
+        if patterns.iter().any(String::is_empty) {
+            return Err(")".to_string());
+        }
+        Ok(patterns)
+    }
+
+    fn parse_conditional(&mut self) -> ParseResult<CompoundCommand> {
+        self.expect_word("[[")?;
+        let expr = self.parse_condition_or()?;
+        self.expect_word("]]")?;
+        Ok(CompoundCommand::Conditional(expr))
+    }
+
+    fn parse_condition_or(&mut self) -> ParseResult<ConditionExpr> {
+        let mut expr = self.parse_condition_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            expr = ConditionExpr::Or(Box::new(expr), Box::new(self.parse_condition_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_condition_and(&mut self) -> ParseResult<ConditionExpr> {
+        let mut expr = self.parse_condition_not()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            expr = ConditionExpr::And(Box::new(expr), Box::new(self.parse_condition_not()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_condition_not(&mut self) -> ParseResult<ConditionExpr> {
+        if self.peek_word() == Some("!") {
+            self.pos += 1;
+            return Ok(ConditionExpr::Not(Box::new(self.parse_condition_not()?)));
+        }
+        self.parse_condition_primary()
+    }
+
+    /// The operand word at the current position, which may be anything but the
+    /// closing `]]`.
+    fn condition_operand(&mut self) -> ParseResult<String> {
+        match self.peek_word() {
+            Some(word) if word != "]]" => {
+                let word = word.to_string();
+                self.pos += 1;
+                Ok(word)
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn parse_condition_primary(&mut self) -> ParseResult<ConditionExpr> {
+        if self.peek_word() == Some("(") {
+            self.pos += 1;
+            let expr = self.parse_condition_or()?;
+            self.expect_word(")")?;
+            return Ok(expr);
+        }
+        let word = self.condition_operand()?;
+        let next = self.peek_word().filter(|&next| next != "]]");
+        if CONDITION_UNARY_OPERATORS.contains(&word.as_str()) && next.is_some() {
+            return Ok(ConditionExpr::Unary(word, self.condition_operand()?));
+        }
+        match next {
+            Some(operator) if CONDITION_BINARY_OPERATORS.contains(&operator) => {
+                let operator = operator.to_string();
+                self.pos += 1;
+                Ok(ConditionExpr::Binary(
+                    word,
+                    operator,
+                    self.condition_operand()?,
+                ))
+            }
+            _ => Ok(ConditionExpr::Word(word)),
+        }
+    }
+}
+
+/// Splits the text of `case` patterns at each `|` outside quotes.
+fn split_case_patterns(text: &str) -> Vec<String> {
+    let mut patterns = Vec::new();
+    let mut current = String::new();
+    let mut quote_state: Option<char> = None;
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '|' if quote_state.is_none() => {
+                patterns.push(mem::take(&mut current));
+                continue;
+            }
+            '\\' if quote_state != Some('\'') => {
+                current.push(ch);
+                if let Some(next_ch) = chars.next() {
+                    current.push(next_ch);
+                }
+                continue;
+            }
+            '"' | '\'' => {
+                if quote_state == Some(ch) {
+                    quote_state = None;
+                } else if quote_state.is_none() {
+                    quote_state = Some(ch);
+                }
+            }
+            _ => {}
+        }
+        current.push(ch);
+    }
+    patterns.push(current);
+    patterns
+}
+
+/// A syntax error in a command line.
+#[derive(Debug)]
+pub(crate) struct SyntaxError {
+    /// The unexpected token, or `UNEXPECTED_EOF`.
+    pub(crate) token: String,
+    /// Where the unexpected token starts in the line, or `None` if the error
+    /// is at its end.
+    pub(crate) offset: Option<usize>,
+}
+
+/// Parses a command line into and-or lists separated by `;` or `&`, where `&`
+/// also marks the list before it as a background job. Tokens come with their
+/// positions in the line, which locate any syntax error.
+pub(crate) fn parse_command_list(
+    tokens: Vec<(Token, Range<usize>)>,
+) -> std::result::Result<Vec<AndOrList>, SyntaxError> {
+    let (tokens, spans): (Vec<Token>, Vec<Range<usize>>) = tokens.into_iter().unzip();
+    let mut parser = Parser::new(tokens);
+    let lists = parser
+        .parse_list(false)
+        .and_then(|lists| match parser.peek() {
+            Some(_) => Err(parser.unexpected()),
+            None => Ok(lists),
+        });
+    lists.map_err(|token| SyntaxError {
+        offset: spans
+            .get(parser.pos)
+            .filter(|_| token != UNEXPECTED_EOF)
+            .map(|span| span.start),
+        token,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(line: &str) -> Vec<Token> {
+        lex_command_line(line)
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect()
+    }
+
+    fn word(text: &str) -> Token {
+        Token::Word(text.to_string())
+    }
+
+    #[test]
+    fn lexes_operators_with_or_without_spaces() {
+        assert_eq!(
+            tokens("a|b&&c||d;e&"),
+            [
+                word("a"),
+                Token::Pipe,
+                word("b"),
+                Token::And,
+                word("c"),
+                Token::Or,
+                word("d"),
+                Token::Semi,
+                word("e"),
+                Token::Background,
+            ]
+        );
+        assert_eq!(
+            tokens("echo  'a | b' \"c;d\" e\\&"),
+            [word("echo"), word("'a | b'"), word("\"c;d\""), word("e\\&")]
+        );
+    }
+
+    fn parse(line: &str) -> Vec<AndOrList> {
+        parse_command_list(lex_command_line(line)).unwrap()
+    }
+
+    fn simple(command: &Command) -> &SimpleCommand {
+        match command {
+            Command::Simple(command) => command,
+            _ => panic!("not a simple command: {:?}", command),
+        }
+    }
+
+    #[test]
+    fn parses_and_or_lists_and_pipelines() {
+        let lists = parse("a | b && c || d; e &");
+        assert_eq!(lists.len(), 2);
+        assert_eq!(lists[0].first.stages.len(), 2);
+        assert_eq!(simple(&lists[0].first.stages[1]).words, ["b"]);
+        let connectors: Vec<Connector> = lists[0]
+            .rest
+            .iter()
+            .map(|(connector, _)| *connector)
+            .collect();
+        assert_eq!(connectors, [Connector::And, Connector::Or]);
+        assert!(!lists[0].background);
+        assert!(lists[1].background);
+    }
+
+    #[test]
+    fn separates_assignments_words_and_redirections() {
+        let lists = parse("A=1 cmd arg 2>err >>out <in");
+        let command = simple(&lists[0].first.stages[0]);
+        assert_eq!(command.assignments, [("A".to_string(), "1".to_string())]);
+        assert_eq!(command.words, ["cmd", "arg"]);
+        let redirections: Vec<(RawFd, RedirectKind, &str)> = command
+            .redirections
+            .iter()
+            .map(|redirection| {
+                (
+                    redirection.fd,
+                    redirection.kind.clone(),
+                    redirection.target.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            redirections,
+            [
+                (2, RedirectKind::Write { append: false }, "err"),
+                (1, RedirectKind::Write { append: true }, "out"),
+                (0, RedirectKind::Read, "in"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_the_unexpected_token() {
+        let error = parse_command_list(lex_command_line("echo a ; ;")).unwrap_err();
+        assert_eq!((error.token.as_str(), error.offset), (";", Some(9)));
+        let error = parse_command_list(lex_command_line("| echo")).unwrap_err();
+        assert_eq!((error.token.as_str(), error.offset), ("|", Some(0)));
+        let error = parse_command_list(lex_command_line("if true; then echo")).unwrap_err();
+        assert_eq!((error.token.as_str(), error.offset), (UNEXPECTED_EOF, None));
+    }
+
+    #[test]
+    fn reads_redirect_operators() {
+        assert_eq!(
+            parse_redirect_operator(">"),
+            Some((1, RedirectKind::Write { append: false }, true))
+        );
+        assert_eq!(
+            parse_redirect_operator("2>&1"),
+            Some((2, RedirectKind::Duplicate(1), false))
+        );
+        assert_eq!(
+            parse_redirect_operator("3<&-"),
+            Some((3, RedirectKind::Close, false))
+        );
+        assert_eq!(
+            parse_redirect_operator("&>>"),
+            Some((1, RedirectKind::WriteBoth { append: true }, true))
+        );
+        assert_eq!(parse_redirect_operator("echo"), None);
+    }
+}
@@ -0,0 +1,223 @@
+//! Spelling correction of command names and `cd` directories, offered at the
+//! prompt with `set -o correct`.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::slice;
+
+use crate::builtins::{BUILTINS, read_line_bytes, search_cdpath};
+use crate::exec::{Shell, with_cached_commands};
+use crate::parser::{Command, SimpleCommand};
+
+/// What the user chose when offered a correction.
+pub(crate) enum Correction {
+    /// Use the corrected word.
+    Accepted(String),
+    /// Give the line back at the prompt with the correction made, without
+    /// running the rest of it.
+    Edit,
+}
+
+/// The number of single-character insertions, deletions, substitutions and
+/// swaps of neighbouring characters that turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    rows[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        rows[i][0] = i;
+        for j in 1..=b.len() {
+            let substitution = rows[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = substitution.min(rows[i - 1][j] + 1).min(rows[i][j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = distance;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+/// The candidate closest to `word`, if one is a small edit away: one for
+/// names of up to four characters, two for longer ones. Ties go to the name
+/// that sorts first.
+fn closest_match<'a>(word: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<String> {
+    let allowed = if word.chars().count() <= 4 { 1 } else { 2 };
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(word, candidate), candidate))
+        .filter(|&(distance, _)| distance > 0 && distance <= allowed)
+        .min()
+        .map(|(_, candidate)| candidate.to_string())
+}
+
+impl Shell {
+    /// Whether corrections may be offered: `set -o correct` is on and the
+    /// shell reads commands from the terminal, where the answer is read.
+    fn can_correct(&self) -> bool {
+        self.options.correct && self.interactive && self.terminal.is_some()
+    }
+
+    /// Asks whether to use `correction` in place of `word`. Answering `e`
+    /// records `edits`, the words to change in the line, and ends the line;
+    /// anything but `y` or `e` keeps `word`.
+    fn ask_correction(
+        &mut self,
+        word: &str,
+        correction: &str,
+        edits: Vec<(String, String)>,
+    ) -> Option<Correction> {
+        let terminal = self.terminal?;
+        eprint!(
+            "{}correct '{}' to '{}' [y/n/e]? ",
+            self.error_prefix(),
+            word,
+            correction
+        );
+        let answer = match read_line_bytes(terminal, true) {
+            Ok(Some(line)) => line.first().map(|&(byte, _)| byte.to_ascii_lowercase()),
+            _ => {
+                eprintln!();
+                None
+            }
+        };
+        match answer {
+            Some(b'y') => Some(Correction::Accepted(correction.to_string())),
+            Some(b'e') => {
+                self.corrections_to_edit = edits;
+                Some(Correction::Edit)
+            }
+            _ => None,
+        }
+    }
+
+    /// Offers the closest builtin, function or program in `PATH` for a
+    /// command that was not found, and runs the corrected command if it is
+    /// accepted.
+    pub(crate) fn correct_command(&mut self, command: &SimpleCommand) -> Option<i32> {
+        let name = command.words.first()?;
+        if !self.can_correct() || !self.is_missing_command(name) {
+            return None;
+        }
+        let path_var = self.vars.get("PATH").unwrap_or_default().to_string();
+        let correction = with_cached_commands(&path_var, |commands| {
+            let builtins = BUILTINS.iter().map(|builtin| builtin.name());
+            let functions = self.functions.keys().map(String::as_str);
+            closest_match(
+                name,
+                commands
+                    .keys()
+                    .map(String::as_str)
+                    .chain(builtins)
+                    .chain(functions),
+            )
+        })?;
+        match self.ask_correction(name, &correction, vec![(name.clone(), correction.clone())])? {
+            Correction::Accepted(correction) => {
+                let mut words = command.words.clone();
+                words[0] = correction;
+                let text = words.join(" ");
+                let stage = Command::Simple(SimpleCommand {
+                    words,
+                    ..command.clone()
+                });
+                match self.run_in_shell(&stage) {
+                    Some(status) => Some(status),
+                    None => Some(self.spawn_pipeline(slice::from_ref(&stage), false, &text)),
+                }
+            }
+            Correction::Edit => Some(127),
+        }
+    }
+
+    /// Offers a directory close to the target of `cd` when it does not exist,
+    /// correcting each component of the path in turn.
+    pub(crate) fn correct_cd_target(&mut self, args: &[&str]) -> Option<Correction> {
+        let [target] = args else {
+            return None;
+        };
+        if !self.can_correct() || target.starts_with('-') || Path::new(target).exists() {
+            return None;
+        }
+        if search_cdpath(&self.vars, target).is_some() {
+            return None;
+        }
+        let mut corrected = PathBuf::new();
+        let mut edits = Vec::new();
+        for component in Path::new(target).components() {
+            let path = corrected.join(component);
+            let Component::Normal(name) = component else {
+                corrected = path;
+                continue;
+            };
+            if path.is_dir() {
+                corrected = path;
+                continue;
+            }
+            let parent = if corrected.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                corrected.as_path()
+            };
+            let directories: Vec<String> = fs::read_dir(parent)
+                .ok()?
+                .flatten()
+                .filter(|entry| entry.path().is_dir())
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .collect();
+            let name = name.to_str()?;
+            let closest = closest_match(name, directories.iter().map(String::as_str))?;
+            edits.push((name.to_string(), closest.clone()));
+            corrected.push(closest);
+        }
+        let correction = corrected.display().to_string();
+        self.ask_correction(target, &correction, edits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_edits_and_swaps() {
+        assert_eq!(edit_distance("git", "git"), 0);
+        assert_eq!(edit_distance("gti", "git"), 1);
+        assert_eq!(edit_distance("gt", "git"), 1);
+        assert_eq!(edit_distance("gits", "git"), 1);
+        assert_eq!(edit_distance("got", "git"), 1);
+        assert_eq!(edit_distance("", "ls"), 2);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn matches_within_the_allowed_distance() {
+        assert_eq!(
+            closest_match("gti", ["git", "grep"]),
+            Some("git".to_string())
+        );
+        assert_eq!(closest_match("gxy", ["git"]), None);
+        assert_eq!(
+            closest_match("pyhtn", ["python"]),
+            Some("python".to_string())
+        );
+        assert_eq!(
+            closest_match("pyhtno", ["python"]),
+            Some("python".to_string())
+        );
+        assert_eq!(closest_match("pthn", ["python"]), None);
+        assert_eq!(
+            closest_match("git", ["git", "gif"]),
+            Some("gif".to_string())
+        );
+        assert_eq!(
+            closest_match("cst", ["cut", "cat"]),
+            Some("cat".to_string())
+        );
+        assert_eq!(
+            closest_match("cst", ["cat", "cut"]),
+            Some("cat".to_string())
+        );
+    }
+}
@@ -0,0 +1,325 @@
+//! Shell variables, their attributes and quoting for re-input.
+
+use std::collections::HashMap;
+use std::env;
+
+use crate::arithmetic::eval_arithmetic;
+use crate::error::ShellError;
+use crate::expansion::is_name_char;
+
+#[derive(Debug, Clone, Default)]
+struct Variable {
+    value: String,
+    exported: bool,
+    /// Set by `readonly` or `declare -r`; the variable can no longer be
+    /// assigned or unset.
+    readonly: bool,
+    /// Set by `declare -i`; values assigned are evaluated as arithmetic.
+    integer: bool,
+}
+
+impl Variable {
+    /// The attribute letters `declare` prints for the variable, in its order.
+    fn flags(&self) -> String {
+        [
+            (self.integer, 'i'),
+            (self.readonly, 'r'),
+            (self.exported, 'x'),
+        ]
+        .into_iter()
+        .filter_map(|(set, flag)| set.then_some(flag))
+        .collect()
+    }
+}
+
+/// Variables replaced by prefix assignments or `local`, to be restored afterwards.
+#[derive(Debug, Default)]
+pub(crate) struct SavedVariables(Vec<(String, Option<Variable>)>);
+
+/// Shell variables. Exported ones make up the environment of child processes.
+#[derive(Debug, Default)]
+pub(crate) struct Variables {
+    vars: HashMap<String, Variable>,
+    /// For each function being run, innermost last, the variables its `local`
+    /// declarations hid. Variables are scoped dynamically: a local is seen by
+    /// the functions it calls, and is replaced by what it hid on return.
+    scopes: Vec<SavedVariables>,
+}
+
+impl Variables {
+    /// Imports the shell's own environment as exported variables.
+    pub(crate) fn from_env() -> Self {
+        let vars = env::vars()
+            .map(|(name, value)| {
+                let variable = Variable {
+                    value,
+                    exported: true,
+                    ..Variable::default()
+                };
+                (name, variable)
+            })
+            .collect();
+        Variables {
+            vars,
+            scopes: Vec::new(),
+        }
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(|var| var.value.as_str())
+    }
+
+    /// Sets a variable, keeping its attributes if it already exists. Fails if
+    /// it is read-only, or if it is an integer and the value is not a valid
+    /// arithmetic expression.
+    pub(crate) fn set(&mut self, name: &str, value: String) -> Result<(), ShellError> {
+        let (readonly, integer) = self
+            .vars
+            .get(name)
+            .map_or((false, false), |var| (var.readonly, var.integer));
+        if readonly {
+            return Err(ShellError::Readonly(name.to_string()));
+        }
+        let value = if integer {
+            let number =
+                eval_arithmetic(&value, self).map_err(|message| ShellError::Arithmetic {
+                    expr: value.trim().to_string(),
+                    message,
+                })?;
+            number.to_string()
+        } else {
+            value
+        };
+        self.vars.entry(name.to_string()).or_default().value = value;
+        Ok(())
+    }
+
+    pub(crate) fn set_exported(&mut self, name: &str, exported: bool) {
+        match self.vars.get_mut(name) {
+            Some(var) => var.exported = exported,
+            None if exported => {
+                self.vars.insert(
+                    name.to_string(),
+                    Variable {
+                        exported,
+                        ..Variable::default()
+                    },
+                );
+            }
+            None => {}
+        }
+    }
+
+    /// Makes a variable read-only, creating it empty if it does not exist.
+    pub(crate) fn set_readonly(&mut self, name: &str) {
+        self.vars.entry(name.to_string()).or_default().readonly = true;
+    }
+
+    pub(crate) fn set_integer(&mut self, name: &str, integer: bool) {
+        match self.vars.get_mut(name) {
+            Some(var) => var.integer = integer,
+            None if integer => {
+                self.vars.insert(
+                    name.to_string(),
+                    Variable {
+                        integer,
+                        ..Variable::default()
+                    },
+                );
+            }
+            None => {}
+        }
+    }
+
+    pub(crate) fn is_readonly(&self, name: &str) -> bool {
+        self.vars.get(name).is_some_and(|var| var.readonly)
+    }
+
+    /// Fails if any of the variables a command's prefix assignments would set
+    /// is read-only.
+    pub(crate) fn check_assignments(
+        &self,
+        assignments: &[(String, String)],
+    ) -> Result<(), ShellError> {
+        match assignments.iter().find(|(name, _)| self.is_readonly(name)) {
+            Some((name, _)) => Err(ShellError::Readonly(name.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// The `declare` command that recreates a variable with its attributes.
+    pub(crate) fn declaration(&self, name: &str) -> Option<String> {
+        let var = self.vars.get(name)?;
+        let flags = var.flags();
+        let flags = if flags.is_empty() {
+            "-".to_string()
+        } else {
+            flags
+        };
+        Some(format!(
+            "declare -{} {}={}",
+            flags,
+            name,
+            double_quote(&var.value)
+        ))
+    }
+
+    /// The `declare` commands for the variables that have every attribute in
+    /// `flags`, sorted by name.
+    pub(crate) fn declarations(&self, flags: &str) -> Vec<String> {
+        let mut names: Vec<&String> = self
+            .vars
+            .iter()
+            .filter(|(_, var)| flags.chars().all(|flag| var.flags().contains(flag)))
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+        names
+            .into_iter()
+            .filter_map(|name| self.declaration(name))
+            .collect()
+    }
+
+    pub(crate) fn unset(&mut self, name: &str) {
+        self.vars.remove(name);
+    }
+
+    /// Sets exported variables for the duration of one command, as its prefix
+    /// assignments, and returns what they replaced. Fails, setting nothing, if
+    /// any of them is read-only.
+    pub(crate) fn set_temporarily(
+        &mut self,
+        assignments: &[(String, String)],
+    ) -> Result<SavedVariables, ShellError> {
+        self.check_assignments(assignments)?;
+        let mut saved = Vec::new();
+        for (name, value) in assignments {
+            let variable = Variable {
+                value: value.clone(),
+                exported: true,
+                ..Variable::default()
+            };
+            let previous = self.vars.insert(name.clone(), variable);
+            if !saved.iter().any(|(saved_name, _)| saved_name == name) {
+                saved.push((name.clone(), previous));
+            }
+        }
+        Ok(SavedVariables(saved))
+    }
+
+    /// Puts back the variables replaced by `set_temporarily`.
+    pub(crate) fn restore(&mut self, saved: SavedVariables) {
+        for (name, previous) in saved.0 {
+            match previous {
+                Some(variable) => self.vars.insert(name, variable),
+                None => self.vars.remove(&name),
+            };
+        }
+    }
+
+    /// Starts the scope of a function call.
+    pub(crate) fn push_scope(&mut self) {
+        self.scopes.push(SavedVariables::default());
+    }
+
+    /// How many function scopes are open.
+    pub(crate) fn scope_depth(&self) -> usize {
+        self.scopes.len()
+    }
+
+    /// Ends the innermost function scope, putting back the variables its locals hid.
+    pub(crate) fn pop_scope(&mut self) {
+        if let Some(saved) = self.scopes.pop() {
+            self.restore(saved);
+        }
+    }
+
+    /// Makes `name` local to the innermost function, unset unless a value is
+    /// given. Returns `false` outside of a function. Fails if the variable is
+    /// read-only.
+    pub(crate) fn declare_local(
+        &mut self,
+        name: &str,
+        value: Option<String>,
+    ) -> Result<bool, ShellError> {
+        if self.is_readonly(name) {
+            return Err(ShellError::Readonly(name.to_string()));
+        }
+        let Some(SavedVariables(saved)) = self.scopes.last_mut() else {
+            return Ok(false);
+        };
+        if !saved.iter().any(|(saved_name, _)| saved_name == name) {
+            saved.push((name.to_string(), self.vars.remove(name)));
+        }
+        if let Some(value) = value {
+            self.set(name, value)?;
+        }
+        Ok(true)
+    }
+
+    /// The local variables of the innermost function that are set, in the
+    /// order they were declared.
+    pub(crate) fn locals(&self) -> Vec<(&str, &str)> {
+        let Some(SavedVariables(saved)) = self.scopes.last() else {
+            return Vec::new();
+        };
+        saved
+            .iter()
+            .filter_map(|(name, _)| Some((name.as_str(), self.get(name)?)))
+            .collect()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.vars.iter().map(|(name, var)| (name, &var.value))
+    }
+
+    pub(crate) fn exported(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.vars
+            .iter()
+            .filter(|(_, var)| var.exported)
+            .map(|(name, var)| (name, &var.value))
+    }
+}
+
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(ch) if ch.is_ascii_alphabetic() || ch == '_')
+        && chars.all(is_name_char)
+}
+
+/// Splits a `NAME=value` word into its name and raw value.
+pub(crate) fn parse_assignment(word: &str) -> Option<(&str, &str)> {
+    let (name, value) = word.split_once('=')?;
+    is_valid_name(name).then_some((name, value))
+}
+
+/// Quotes a value in single quotes so it is read back literally.
+pub(crate) fn single_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Quotes a word with single quotes only if it would not read back as itself.
+pub(crate) fn quote_if_needed(word: &str) -> String {
+    let plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-+=.,/:@%".contains(c));
+    if plain {
+        word.to_string()
+    } else {
+        single_quote(word)
+    }
+}
+
+/// Quotes a value so it can be read back by the shell inside double quotes.
+pub(crate) fn double_quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for ch in value.chars() {
+        if matches!(ch, '"' | '\\' | '$' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(ch);
+    }
+    quoted.push('"');
+    quoted
+}
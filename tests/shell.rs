@@ -0,0 +1,369 @@
+//! Runs command lines through `Shell` the way the binary does, and checks what
+//! they print and the status they leave.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use shell::Shell;
+
+/// An empty directory of the test's own, since tests run in parallel.
+fn scratch_dir(test: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("shell-test-{}-{}", process::id(), test));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs `source` in a new shell with `$T` set to a scratch directory, and
+/// returns what it wrote to stdout and stderr with its status.
+fn run(test: &str, source: &str) -> (String, i32) {
+    run_in(&scratch_dir(test), source)
+}
+
+/// Runs `source` like `run`, with `$T` set to `dir`.
+fn run_in(dir: &Path, source: &str) -> (String, i32) {
+    let output = dir.join("output");
+    let mut shell = Shell::new();
+    shell.run_line(&format!("T={}", dir.display()));
+    let status = shell.run_lines(
+        &format!("{{\n{}\n}} > {} 2>&1", source, output.display()),
+        None,
+    );
+    (fs::read_to_string(output).unwrap_or_default(), status)
+}
+
+#[test]
+fn shells_keep_their_state_between_lines() {
+    let mut shell = Shell::new();
+    shell.set_name("script".to_string());
+    shell.set_positional(vec!["one".to_string(), "two".to_string()]);
+    assert_eq!(shell.run_line("x=1"), 0);
+    assert_eq!(shell.run_line("f() { return 7; }"), 0);
+    assert_eq!(
+        shell.run_line("test \"$0 $# $1 $2 $x\" = 'script 2 one two 1'"),
+        0
+    );
+    assert_eq!(shell.run_line("f"), 7);
+    assert_eq!(shell.run_line("test $? = 7"), 0);
+}
+
+#[test]
+fn builtins_run_in_pipelines_without_changing_the_shell() {
+    let (output, status) = run(
+        "builtin_pipeline",
+        "echo foo | cat\npwd | cat\ncd / | true\npwd",
+    );
+    let cwd = env::current_dir().unwrap();
+    assert_eq!(
+        output,
+        format!("foo\n{}\n{}\n", cwd.display(), cwd.display())
+    );
+    assert_eq!(status, 0);
+}
+
+#[test]
+fn redirections_apply_left_to_right() {
+    let dir = scratch_dir("redirection_order");
+    fs::write(dir.join("in"), "input\n").unwrap();
+    fs::write(dir.join("a"), "old\n").unwrap();
+    let source = "cat - /nonexistent > $T/out 2> $T/err < $T/in\n\
+                  echo x > $T/a > $T/b\n\
+                  ls /nonexistent 2>&1 > /dev/null";
+    let (output, _) = run_in(&dir, source);
+    assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "input\n");
+    assert!(
+        fs::read_to_string(dir.join("err"))
+            .unwrap()
+            .contains("/nonexistent")
+    );
+    assert_eq!(fs::read_to_string(dir.join("a")).unwrap(), "");
+    assert_eq!(fs::read_to_string(dir.join("b")).unwrap(), "x\n");
+    assert!(output.contains("/nonexistent"));
+}
+
+#[test]
+fn eval_applies_its_redirections_to_the_commands_it_runs() {
+    let dir = scratch_dir("eval_redirections");
+    let source = "eval 'echo one; ls /nonexistent' > $T/out 2> $T/err\n\
+                  eval cat < $T/out\n\
+                  echo two";
+    assert_eq!(run_in(&dir, source), ("one\ntwo\n".to_string(), 0));
+    assert!(
+        fs::read_to_string(dir.join("err"))
+            .unwrap()
+            .contains("/nonexistent")
+    );
+}
+
+#[test]
+fn source_applies_its_redirections_and_reports_its_own_name() {
+    let dir = scratch_dir("source_redirections");
+    fs::write(dir.join("script"), "echo sourced $1\nls /nonexistent\n").unwrap();
+    let source = "source $T/script arg > $T/out 2> /dev/null\n\
+                  . $T/script 2>&1 > /dev/null | grep -c nonexistent\n\
+                  . $T/missing\n\
+                  source $T/missing 2> /dev/null";
+    let (output, status) = run_in(&dir, source);
+    assert_eq!(
+        fs::read_to_string(dir.join("out")).unwrap(),
+        "sourced arg\n"
+    );
+    assert!(output.starts_with("1\n.: "), "{}", output);
+    assert_eq!(output.lines().count(), 2);
+    assert_eq!(status, 1);
+}
+
+#[test]
+fn pipelines_connect_stages() {
+    assert_eq!(
+        run("pipeline", "echo hello | tr a-z A-Z"),
+        ("HELLO\n".to_string(), 0)
+    );
+    assert_eq!(run("pipeline_status", "true | false").1, 1);
+    let three = run(
+        "pipeline_three",
+        "printf 'b\\na\\nb\\n' | sort | uniq -c | wc -l",
+    );
+    assert_eq!(three.0.trim(), "2");
+}
+
+#[test]
+fn background_jobs_are_listed_and_waited_for() {
+    let source = "sleep 0 & wait\n\
+                  echo waited $?\n\
+                  sleep 5 &\n\
+                  echo last $!\n\
+                  jobs\n\
+                  kill %1\n\
+                  wait %1\n\
+                  echo killed $?\n\
+                  jobs\n\
+                  (exit 3) &\n\
+                  wait $!\n\
+                  echo status $?";
+    let (output, status) = run("jobs", source);
+    let pid = output
+        .lines()
+        .find_map(|line| line.strip_prefix("last "))
+        .unwrap();
+    assert!(pid.parse::<u32>().is_ok(), "{}", output);
+    assert_eq!(
+        output,
+        format!(
+            "waited 0\nlast {}\n[1]+  Running                 sleep 5 &\nkilled 143\nstatus 3\n",
+            pid
+        )
+    );
+    assert_eq!(status, 0);
+}
+
+#[test]
+fn and_or_lists_follow_statuses() {
+    let source = "false && echo no || echo yes\ntrue || echo no\ntrue && echo ok";
+    assert_eq!(run("and_or", source), ("yes\nok\n".to_string(), 0));
+    assert_eq!(run("and_or_status", "true && false").1, 1);
+    assert_eq!(run("or_status", "false || false").1, 1);
+}
+
+#[test]
+fn input_is_read_from_redirected_files() {
+    let dir = scratch_dir("input_redirection");
+    fs::write(dir.join("in"), "one\ntwo\n").unwrap();
+    assert_eq!(run_in(&dir, "wc -l < $T/in"), ("2\n".to_string(), 0));
+    assert_eq!(run_in(&dir, "cat < $T/missing").1, 1);
+}
+
+#[test]
+fn output_descriptors_are_duplicated() {
+    let dir = scratch_dir("duplication");
+    let source = "ls /nonexistent > $T/both 2>&1\n\
+                  { echo out; ls /nonexistent; } &> $T/all\n\
+                  echo more &>> $T/all\n\
+                  echo to-stderr 1>&2";
+    assert_eq!(run_in(&dir, source).0, "to-stderr\n");
+    let both = fs::read_to_string(dir.join("both")).unwrap();
+    assert!(both.contains("/nonexistent"), "{}", both);
+    let all = fs::read_to_string(dir.join("all")).unwrap();
+    assert!(
+        all.starts_with("out\n") && all.ends_with("more\n"),
+        "{}",
+        all
+    );
+    assert!(all.contains("/nonexistent"), "{}", all);
+}
+
+#[test]
+fn if_runs_the_first_branch_whose_condition_succeeds() {
+    let source = "if false; then echo a; elif true; then echo b; else echo c; fi\n\
+                  if false; then echo a; else echo c; fi\n\
+                  if false; then echo a; fi";
+    assert_eq!(run("if", source), ("b\nc\n".to_string(), 0));
+    assert_eq!(run("if_status", "if true; then (exit 4); fi").1, 4);
+}
+
+#[test]
+fn case_runs_the_first_matching_item() {
+    let source = "case foo.rs in *.c) echo C;; *.rs|*.toml) echo Rust;; *) echo other;; esac\n\
+                  x=b; case $x in a) echo a;; [bc]) echo bc;; esac\n\
+                  case '*' in \\*) echo star;; esac\n\
+                  case none in a) echo a;; esac";
+    assert_eq!(run("case", source), ("Rust\nbc\nstar\n".to_string(), 0));
+}
+
+#[test]
+fn conditionals_match_globs_and_regular_expressions() {
+    let source = "[[ foo.rs == *.rs ]] && echo glob\n\
+                  [[ foo.rs != *.c ]] && echo not-glob\n\
+                  [[ foo == \"*\" ]] || echo quoted\n\
+                  BASH_REMATCHES=kept\n\
+                  [[ ab12 =~ ^([a-z]+)([0-9]+)$ ]]\n\
+                  echo $BASH_REMATCH $BASH_REMATCH_1 $BASH_REMATCH_2\n\
+                  [[ ab =~ ^[0-9]+$ ]]\n\
+                  echo \"$? [$BASH_REMATCH$BASH_REMATCH_1] $BASH_REMATCHES\"";
+    assert_eq!(
+        run("conditional_matching", source).0,
+        "glob\nnot-glob\nquoted\nab12 ab 12\n1 [] kept\n"
+    );
+}
+
+#[test]
+fn conditionals_short_circuit() {
+    let source = "[[ -n a || -n ${x:=or} ]] && echo \"or [$x]\"\n\
+                  [[ -z a && -n ${x:=and} ]] || echo \"and [$x]\"\n\
+                  [[ -z a || -n ${x:=right} ]] && echo \"right [$x]\"\n\
+                  [[ ! -z a ]] && echo not";
+    assert_eq!(
+        run("conditional_short_circuit", source).0,
+        "or []\nand []\nright [right]\nnot\n"
+    );
+}
+
+#[test]
+fn regular_expression_matches_respect_readonly_captures() {
+    let source = "readonly BASH_REMATCH_1=fixed\n\
+                  [[ ab =~ (a)b ]]\n\
+                  echo $? $BASH_REMATCH_1";
+    assert_eq!(run("readonly_captures", source).0, "2 fixed\n");
+}
+
+#[test]
+fn read_splits_lines_into_variables() {
+    let dir = scratch_dir("read");
+    fs::write(dir.join("lines"), "l1\nl2\n").unwrap();
+    let source = "read a b <<< 'one two three'\n\
+                  echo \"[$a][$b]\"\n\
+                  IFS=: read -r x y <<< 'p:q\\r'\n\
+                  echo \"[$x][$y]\"\n\
+                  read w <<< 'a\\ b'\n\
+                  echo \"[$w]\"\n\
+                  read <<< '  kept  '\n\
+                  echo \"[$REPLY]\"\n\
+                  { read l; read m; } < $T/lines\n\
+                  echo $l $m\n\
+                  read z < /dev/null\n\
+                  echo $? \"[$z]\"";
+    assert_eq!(
+        run_in(&dir, source).0,
+        "[one][two three]\n[p][q\\r]\n[a b]\n[  kept  ]\nl1 l2\n1 []\n"
+    );
+}
+
+#[test]
+fn errexit_stops_at_the_first_failure() {
+    let (output, status) = run("errexit", "set -e\necho one\nfalse\necho two");
+    assert_eq!(output, "one\n");
+    assert_eq!(status, 1);
+    let (output, _) = run(
+        "errexit_condition",
+        "set -e\nif false; then :; fi\nfalse || echo handled\necho end",
+    );
+    assert_eq!(output, "handled\nend\n");
+}
+
+#[test]
+fn failed_expansions_end_a_script() {
+    let (output, status) = run("arithmetic_error", "echo $((1/0))\necho after");
+    assert_eq!(output, "");
+    assert_ne!(status, 0);
+    let (output, status) = run("nounset", "set -u\necho $missing\necho after");
+    assert_eq!(output, "");
+    assert_ne!(status, 0);
+}
+
+#[test]
+fn traps_run_on_signals_and_exit() {
+    let dir = scratch_dir("traps");
+    let source = "trap 'echo caught' USR2\n\
+                  kill -USR2 $$\n\
+                  echo next\n\
+                  trap\n\
+                  trap - USR2\n\
+                  trap\n\
+                  trap 'echo x' BOGUS || echo rejected";
+    assert_eq!(
+        run_in(&dir, source).0,
+        "caught\nnext\ntrap -- 'echo caught' SIGUSR2\n\
+         trap: BOGUS: invalid signal specification\nrejected\n"
+    );
+
+    let mut shell = Shell::new();
+    shell.run_line(&format!(
+        "trap 'echo bye > {}/exit; exit 3' EXIT",
+        dir.display()
+    ));
+    assert_eq!(shell.run_exit_trap(0), 3);
+    assert_eq!(fs::read_to_string(dir.join("exit")).unwrap(), "bye\n");
+}
+
+#[test]
+fn history_reads_lists_deletes_and_writes_entries() {
+    let dir = scratch_dir("history");
+    fs::write(dir.join("saved"), "one\ntwo\nthree\n").unwrap();
+    let source = "history -r $T/saved\n\
+                  history 2\n\
+                  history -d 1\n\
+                  history -w $T/written\n\
+                  history -d 9 || echo missing\n\
+                  history -c\n\
+                  history";
+    assert_eq!(
+        run_in(&dir, source).0,
+        "    2  two\n    3  three\nhistory: 9: history position out of range\nmissing\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("written")).unwrap(),
+        "#V2\ntwo\nthree\n"
+    );
+}
+
+#[test]
+fn umask_sets_the_mode_of_new_files() {
+    // The mask belongs to the test process, so it is put back afterwards.
+    let source = "umask > $T/old\n\
+                  read old < $T/old\n\
+                  umask 027\n\
+                  umask\n\
+                  umask -S\n\
+                  umask g+w\n\
+                  umask\n\
+                  umask 9 || echo rejected\n\
+                  umask u=rw,go=r\n\
+                  > $T/file\n\
+                  stat -c %a $T/file\n\
+                  umask $old";
+    assert_eq!(
+        run("umask", source).0,
+        "0027\nu=rwx,g=rx,o=\n0007\numask: 9: octal number out of range\nrejected\n644\n"
+    );
+}
+
+#[test]
+fn locals_are_restored_when_functions_return() {
+    let source = "x=outer\n\
+                  f() { local x=inner; echo $x $1; return 3; }\n\
+                  f arg\n\
+                  echo $? $x";
+    assert_eq!(run("locals", source).0, "inner arg\n3 outer\n");
+}